@@ -5,15 +5,19 @@ use anyhow::Result;
 use tokio::time::Duration;
 use async_trait::async_trait;
 
+use openi_core_fabric::Signer;
+use openi_core_kernel::control::{self, ControlReply, ControlRequest};
+use openi_core_kernel::identity::{self, NodeCertificate};
+use openi_core_kernel::policy::{Action, Resource};
 use openi_core_reflex::{
     monitor::{PolicyGuardReflex, RateLimitReflex},
     supervisor::{ReflexSupervisor, ReflexSubjects},
     FabricBus, Envelope, BusSubscription,
 };
 
-/// ---------------------------------------------------------------------------
-/// CLI Entry
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// CLI Entry
+// ---------------------------------------------------------------------------
 
 #[derive(Parser)]
 #[command(name = "openi", version, about = "OpenI Core CLI — Fabric, Agents, and Reflex Node Controller")]
@@ -32,13 +36,44 @@ enum Cmd {
     Deploy { path: String },
     /// Start a local kernel node (dev)
     Node,
+    /// List the agents of a running node and their replicas
+    Agents,
+    /// Set how many replicas of an agent a running node keeps
+    Scale { agent: String, replicas: u32 },
+    /// Show the last health probe of each replica (optionally of one agent)
+    Health { agent: Option<String> },
+    /// Ask a running node whether an agent may publish, subscribe or access
+    /// a topic and/or scopes (exits 1 if denied)
+    Can {
+        agent: String,
+        action: String,
+        topic: Option<String>,
+        #[arg(long)]
+        ctype: Option<String>,
+        #[arg(long = "scope")]
+        scopes: Vec<String>,
+    },
+    /// Certify a node's key for a tenant; prints the certificate JSON (for
+    /// OPENI_NODE_CERT) and the tenant key to trust (OPENI_TENANT_KEY)
+    NodeCert {
+        tenant: String,
+        node: String,
+        /// The tenant's 32-byte Ed25519 seed
+        #[arg(long)]
+        tenant_key: String,
+        /// The node's 32-byte Ed25519 seed (OPENI_NODE_KEY)
+        #[arg(long)]
+        node_key: String,
+        #[arg(long, default_value_t = 365)]
+        days: u64,
+    },
     /// Trigger curiosity (exploration) loop manually
     Curiosity { topic: Option<String> },
 }
 
-/// ---------------------------------------------------------------------------
-/// Setup
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Setup
+// ---------------------------------------------------------------------------
 
 fn init_tracing() {
     tracing_subscriber::fmt()
@@ -57,13 +92,19 @@ fn main() -> Result<()> {
         Cmd::Package { path } => package_manifest(&path),
         Cmd::Deploy { path } => deploy_manifest(&path),
         Cmd::Node => run_node(),
+        Cmd::Agents => list_agents(),
+        Cmd::Scale { agent, replicas } => scale_agent(&agent, replicas),
+        Cmd::Health { agent } => show_health(agent.as_deref()),
+        Cmd::Can { agent, action, topic, ctype, scopes } => can(&agent, &action, Resource { topic, ctype, scopes }),
+        Cmd::NodeCert { tenant, node, tenant_key, node_key, days } =>
+            node_cert(&tenant, &node, &tenant_key, &node_key, days),
         Cmd::Curiosity { topic } => run_curiosity(topic),
     }
 }
 
-/// ---------------------------------------------------------------------------
-/// Agent Commands
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Agent Commands
+// ---------------------------------------------------------------------------
 
 fn init_agent(name: &str) -> Result<()> {
     use std::fs;
@@ -117,9 +158,109 @@ fn deploy_manifest(path: &str) -> Result<()> {
     Ok(())
 }
 
-/// ---------------------------------------------------------------------------
-/// Node Runtime — Launch Reflex Supervisor + Mock Kernel
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Node Control — talk to a running node over its control socket
+// ---------------------------------------------------------------------------
+
+fn node_request(request: ControlRequest) -> Result<ControlReply> {
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let reply = rt.block_on(control::request(&control::socket_path(), &request))?;
+    match reply {
+        ControlReply::Error { message } => Err(anyhow::anyhow!(message)),
+        reply => Ok(reply),
+    }
+}
+
+fn node_agents() -> Result<Vec<openi_core_kernel::agents::AgentStatus>> {
+    match node_request(ControlRequest::Agents)? {
+        ControlReply::Agents { agents } => Ok(agents),
+        _ => anyhow::bail!("unexpected reply from node"),
+    }
+}
+
+fn list_agents() -> Result<()> {
+    let agents = node_agents()?;
+    if agents.is_empty() {
+        println!("No agents registered.");
+    }
+    for agent in agents {
+        let up = agent.replicas.iter().filter(|r| r.state.is_up()).count();
+        println!(
+            "{:<24} {:<10} {}/{} up  {} start(s)  {}",
+            agent.name,
+            agent.state.to_string(),
+            up,
+            agent.replicas.len(),
+            agent.starts,
+            agent.reason.unwrap_or_default()
+        );
+        for r in agent.replicas {
+            println!("  #{:<3} {:<10} since {}  {}", r.replica, r.state.to_string(), r.since, r.reason.unwrap_or_default());
+        }
+    }
+    Ok(())
+}
+
+fn show_health(only: Option<&str>) -> Result<()> {
+    let agents: Vec<_> = node_agents()?.into_iter().filter(|a| only.is_none_or(|name| a.name == name)).collect();
+    if let (Some(name), true) = (only, agents.is_empty()) {
+        anyhow::bail!("unknown agent {}", name);
+    }
+    for agent in agents {
+        for r in agent.replicas {
+            let name = format!("{}#{}", agent.name, r.replica);
+            match r.health {
+                Some(h) => println!(
+                    "{:<28} {:<8} {:<10} checked {}  {}",
+                    name,
+                    if h.live { "live" } else { "dead" },
+                    if h.ready { "ready" } else { "not ready" },
+                    h.checked,
+                    h.detail.unwrap_or_default()
+                ),
+                None => println!("{:<28} {:<8} (not probed)", name, r.state.to_string()),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn scale_agent(agent: &str, replicas: u32) -> Result<()> {
+    node_request(ControlRequest::Scale { agent: agent.to_string(), replicas })?;
+    println!("✅ Scaled {} to {} replica(s)", agent, replicas);
+    Ok(())
+}
+
+fn can(agent: &str, action: &str, resource: Resource) -> Result<()> {
+    let action: Action = action.parse()?;
+    let ControlReply::Decision { decision } =
+        node_request(ControlRequest::Can { agent: agent.to_string(), action, resource })?
+    else {
+        anyhow::bail!("unexpected reply from node");
+    };
+    if decision.allowed {
+        println!("✅ {}", decision);
+        Ok(())
+    } else {
+        println!("⛔ {}", decision);
+        std::process::exit(1);
+    }
+}
+
+fn node_cert(tenant: &str, node: &str, tenant_key: &str, node_key: &str, days: u64) -> Result<()> {
+    let tenant_key = identity::load_key(tenant_key)?;
+    let node_key = identity::load_key(node_key)?;
+    let ttl = Duration::from_secs(days * 24 * 60 * 60);
+    let cert = NodeCertificate::issue(&Signer::new(tenant_key.clone()), tenant, node, node_key.public_key_base64(), ttl)?;
+    println!("{}", serde_json::to_string_pretty(&cert)?);
+    eprintln!("🔏 Certified {} until {}", cert.authority(), cert.expires_at);
+    eprintln!("OPENI_TENANT_KEY={}", tenant_key.public_key_base64());
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Node Runtime — Launch Reflex Supervisor + Mock Kernel
+// ---------------------------------------------------------------------------
 
 fn run_node() -> Result<()> {
     let rt = tokio::runtime::Builder::new_multi_thread()
@@ -130,18 +271,7 @@ fn run_node() -> Result<()> {
         // 1️⃣ Initialize Fabric bus
         let bus = openi_core_kernel::get_bus().await?;
 
-        // 2️⃣ Load policy if available
-        if let Ok(policy_path) = std::env::var("OPENI_POLICY") {
-            if let Err(e) = openi_core_kernel::load_policy(&policy_path) {
-                tracing::warn!("⚠️ Failed to load policy config: {}", e);
-            } else {
-                tracing::info!("✅ Loaded policy configuration from {}", policy_path);
-            }
-        } else {
-            tracing::info!("No OPENI_POLICY set; running with default permissive policy.");
-        }
-
-        // 3️⃣ Telemetry counters
+        // 2️⃣ Telemetry counters
         let events = Arc::new(AtomicU64::new(0));
         let alerts = Arc::new(AtomicU64::new(0));
         let halts = Arc::new(AtomicU64::new(0));
@@ -175,7 +305,7 @@ fn run_node() -> Result<()> {
             counter: bus_events.clone(),
         });
 
-        // 4️⃣ ReflexSupervisor with telemetry listeners
+        // 3️⃣ ReflexSupervisor with telemetry listeners
         let subjects = ReflexSubjects::default();
         let reflex_bus = tele_bus.clone();
         let alerts_ref = alerts.clone();
@@ -201,10 +331,10 @@ fn run_node() -> Result<()> {
             ])))
             .spawn();
 
-        // 5️⃣ Start kernel node (mock runtime)
-        openi_core_kernel::start_node().await?;
+        // 4️⃣ Start kernel node (loads OPENI_NODE_CERT and OPENI_POLICY, if set)
+        let _agents = openi_core_kernel::start_node().await?;
 
-        // 6️⃣ Heartbeat telemetry
+        // 5️⃣ Heartbeat telemetry
         tokio::spawn({
             let events = events.clone();
             let alerts = alerts.clone();
@@ -225,16 +355,16 @@ fn run_node() -> Result<()> {
             }
         });
 
-        // 7️⃣ Keep alive indefinitely
+        // 6️⃣ Keep alive indefinitely
         loop {
             tokio::time::sleep(Duration::from_secs(60)).await;
         }
     })
 }
 
-/// ---------------------------------------------------------------------------
-/// Curiosity / Exploration Stub
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Curiosity / Exploration Stub
+// ---------------------------------------------------------------------------
 
 fn run_curiosity(topic: Option<String>) -> Result<()> {
     let t = topic.unwrap_or_else(|| "general".to_string());
@@ -0,0 +1,327 @@
+use crate::bus::{matches, DeliveryError, DeliveryFilter};
+use crate::signing::Verifier;
+use crate::Envelope;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Threshold over roles, e.g. `2 of {clinician, pharmacist}`: at least
+/// `threshold` distinct signers holding any of `roles` must countersign.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApprovalPolicy {
+    pub threshold: usize,
+    pub roles: BTreeSet<String>,
+}
+
+impl ApprovalPolicy {
+    pub fn new<I, S>(threshold: usize, roles: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self { threshold, roles: roles.into_iter().map(Into::into).collect() }
+    }
+}
+
+impl fmt::Display for ApprovalPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let roles: Vec<&str> = self.roles.iter().map(String::as_str).collect();
+        write!(f, "{} of {{{}}}", self.threshold, roles.join(", "))
+    }
+}
+
+impl FromStr for ApprovalPolicy {
+    type Err = ApprovalError;
+
+    /// Parses `N of {role, role}`; the braces are optional.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || ApprovalError::Policy(s.to_string());
+        let (n, roles) = s.split_once(" of ").ok_or_else(bad)?;
+        let threshold: usize = n.trim().parse().map_err(|_| bad())?;
+        let roles = roles.trim().trim_start_matches('{').trim_end_matches('}');
+        let roles: BTreeSet<String> = roles
+            .split(',')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+            .map(str::to_string)
+            .collect();
+        if threshold == 0 || roles.is_empty() {
+            return Err(bad());
+        }
+        Ok(Self { threshold, roles })
+    }
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ApprovalError {
+    #[error("invalid approval policy {0:?}")]
+    Policy(String),
+    #[error("countersignature {index} from unknown signer {signer}")]
+    UnknownSigner { index: usize, signer: String },
+    #[error("signer {signer} is not trusted for role {role}")]
+    RoleNotGranted { signer: String, role: String },
+    #[error("countersignature {index} from {signer} does not verify")]
+    BadSignature { index: usize, signer: String },
+    #[error("signer {0} countersigned more than once")]
+    DuplicateSigner(String),
+    #[error("envelope {0} carries no origin signature")]
+    Unsigned(String),
+    #[error("no key for origin {0}")]
+    UnknownOrigin(String),
+    #[error("origin signature from {0} does not verify")]
+    BadOrigin(String),
+    #[error("approval policy {policy} not met: {have} qualifying signature(s)")]
+    Threshold { policy: String, have: usize },
+}
+
+struct TrustedSigner {
+    verifier: Verifier,
+    roles: HashSet<String>,
+}
+
+/// JSON form of an `ApprovalVerifier`, e.g. for a node's `OPENI_APPROVALS`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ApprovalConfig {
+    #[serde(default)]
+    pub signers: Vec<SignerConfig>,
+    /// Topic pattern to policy, e.g. `"topic://orders/*": "2 of {clinician, pharmacist}"`.
+    #[serde(default)]
+    pub require: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignerConfig {
+    pub id: String,
+    /// Ed25519, base64.
+    pub public_key: String,
+    /// Roles the signer may approve in; none for an origin-only key.
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+type OriginKeys = Box<dyn Fn(&str) -> Option<Verifier> + Send + Sync>;
+
+/// Checks countersignature chains against per-topic approval policies.
+///
+/// Signers must be registered with `trust` along with the roles they may
+/// act in; a countersignature claiming any other role is rejected. The
+/// envelope's own `sig` must verify as its `src` before any approval
+/// counts: origins are looked up among the trusted signers (trust them with
+/// no roles), then through `with_origin_keys`.
+#[derive(Default)]
+pub struct ApprovalVerifier {
+    signers: HashMap<String, TrustedSigner>,
+    origins: Option<OriginKeys>,
+    policies: Vec<(String, ApprovalPolicy)>,
+}
+
+impl ApprovalVerifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: &ApprovalConfig) -> anyhow::Result<Self> {
+        let mut verifier = Self::new();
+        for signer in &config.signers {
+            verifier.trust(signer.id.clone(), &signer.public_key, signer.roles.iter().cloned())?;
+        }
+        for (pattern, policy) in &config.require {
+            verifier.require(pattern.clone(), policy.parse()?);
+        }
+        Ok(verifier)
+    }
+
+    pub fn trust<I, S>(&mut self, signer_id: impl Into<String>, pk_b64: &str, roles: I) -> anyhow::Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let verifier = Verifier::from_base64(pk_b64)?;
+        let roles = roles.into_iter().map(Into::into).collect();
+        self.signers.insert(signer_id.into(), TrustedSigner { verifier, roles });
+        Ok(())
+    }
+
+    /// Resolve origin keys not registered with `trust`, e.g. from the
+    /// credentials of a node's identity service.
+    pub fn with_origin_keys(mut self, keys: impl Fn(&str) -> Option<Verifier> + Send + Sync + 'static) -> Self {
+        self.origins = Some(Box::new(keys));
+        self
+    }
+
+    /// Require `policy` on every topic matching `pattern`.
+    pub fn require(&mut self, pattern: impl Into<String>, policy: ApprovalPolicy) -> &mut Self {
+        self.policies.push((pattern.into(), policy));
+        self
+    }
+
+    /// Verify the origin signature and the whole countersignature chain,
+    /// then check `policy`.
+    pub fn verify<T: Serialize + for<'de> serde::Deserialize<'de>>(
+        &self,
+        env: &Envelope<T>,
+        policy: &ApprovalPolicy,
+    ) -> Result<(), ApprovalError> {
+        self.verify_origin(env)?;
+        let mut seen = HashSet::new();
+        let mut qualifying = 0;
+        for (index, cs) in env.countersigs.iter().enumerate() {
+            let trusted = self.signers.get(&cs.signer).ok_or_else(|| ApprovalError::UnknownSigner {
+                index,
+                signer: cs.signer.clone(),
+            })?;
+            if !trusted.roles.contains(&cs.role) {
+                return Err(ApprovalError::RoleNotGranted { signer: cs.signer.clone(), role: cs.role.clone() });
+            }
+            trusted
+                .verifier
+                .verify_bytes(&env.countersign_bytes(index), &cs.sig)
+                .map_err(|_| ApprovalError::BadSignature { index, signer: cs.signer.clone() })?;
+            if !seen.insert(cs.signer.as_str()) {
+                return Err(ApprovalError::DuplicateSigner(cs.signer.clone()));
+            }
+            if policy.roles.contains(&cs.role) {
+                qualifying += 1;
+            }
+        }
+        if qualifying < policy.threshold {
+            return Err(ApprovalError::Threshold { policy: policy.to_string(), have: qualifying });
+        }
+        Ok(())
+    }
+
+    fn verify_origin<T: Serialize + for<'de> serde::Deserialize<'de>>(&self, env: &Envelope<T>) -> Result<(), ApprovalError> {
+        let sig = env.sig.as_deref().ok_or_else(|| ApprovalError::Unsigned(env.id.clone()))?;
+        let key = match self.signers.get(&env.src) {
+            Some(trusted) => trusted.verifier.clone(),
+            None => self
+                .origins
+                .as_ref()
+                .and_then(|keys| keys(&env.src))
+                .ok_or_else(|| ApprovalError::UnknownOrigin(env.src.clone()))?,
+        };
+        key.verify_bytes(&env.canonical_bytes(), sig).map_err(|_| ApprovalError::BadOrigin(env.src.clone()))
+    }
+}
+
+impl DeliveryFilter for ApprovalVerifier {
+    fn name(&self) -> &'static str {
+        "approval"
+    }
+
+    fn check(&self, topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        for (pattern, policy) in &self.policies {
+            if matches(pattern, topic) {
+                self.verify(env, policy)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::{Keypair, Signer};
+    use crate::ContentType;
+    use serde_json::json;
+
+    struct Party {
+        id: &'static str,
+        signer: Signer,
+    }
+
+    fn party(id: &'static str, seed: u8) -> (Party, String) {
+        let kp = Keypair::from_seed(&[seed; 32]);
+        let pk = kp.public_key_base64();
+        (Party { id, signer: Signer::new(kp) }, pk)
+    }
+
+    /// An order from `agent://local/ehr`, signed, with a verifier trusting
+    /// the origin and two clinicians and a pharmacist as approvers.
+    fn setup() -> (Envelope<Value>, ApprovalVerifier, Vec<Party>) {
+        let (origin, origin_pk) = party("agent://local/ehr", 1);
+        let mut verifier = ApprovalVerifier::new();
+        verifier.trust(origin.id, &origin_pk, Vec::<String>::new()).unwrap();
+        let mut parties = Vec::new();
+        for (n, (id, role)) in [("dr-a", "clinician"), ("dr-b", "clinician"), ("rph-c", "pharmacist")].iter().enumerate() {
+            let (p, pk) = party(id, 10 + n as u8);
+            verifier.trust(*id, &pk, [*role]).unwrap();
+            parties.push(p);
+        }
+        let ctype = ContentType::mime("application", "json");
+        let mut env = Envelope::new(origin.id, "topic://orders/opioid", ctype, json!({ "drug": "x" }));
+        env.sign(&origin.signer).unwrap();
+        (env, verifier, parties)
+    }
+
+    #[test]
+    fn approvals_count_towards_the_threshold() {
+        let (mut env, verifier, parties) = setup();
+        let policy: ApprovalPolicy = "2 of {clinician, pharmacist}".parse().unwrap();
+        env.countersign(&parties[0].signer, parties[0].id, "clinician").unwrap();
+        assert_eq!(
+            verifier.verify(&env, &policy),
+            Err(ApprovalError::Threshold { policy: policy.to_string(), have: 1 })
+        );
+        env.countersign(&parties[2].signer, parties[2].id, "pharmacist").unwrap();
+        verifier.verify(&env, &policy).unwrap();
+
+        let mut twice = env.clone();
+        twice.countersign(&parties[0].signer, parties[0].id, "clinician").unwrap();
+        assert_eq!(verifier.verify(&twice, &policy), Err(ApprovalError::DuplicateSigner("dr-a".into())));
+        let mut wrong_role = env.clone();
+        wrong_role.countersign(&parties[1].signer, parties[1].id, "pharmacist").unwrap();
+        assert!(matches!(verifier.verify(&wrong_role, &policy), Err(ApprovalError::RoleNotGranted { .. })));
+    }
+
+    #[test]
+    fn tampered_countersignatures_and_origins_are_rejected() {
+        let (mut env, verifier, parties) = setup();
+        let policy: ApprovalPolicy = "1 of {clinician}".parse().unwrap();
+        env.countersign(&parties[0].signer, parties[0].id, "clinician").unwrap();
+        verifier.verify(&env, &policy).unwrap();
+
+        let mut forged = env.clone();
+        forged.countersigs[0].signer = "dr-b".into();
+        assert!(matches!(verifier.verify(&forged, &policy), Err(ApprovalError::BadSignature { index: 0, .. })));
+        let mut edited = env.clone();
+        edited.payload = json!({ "drug": "y" });
+        assert_eq!(verifier.verify(&edited, &policy), Err(ApprovalError::BadOrigin("agent://local/ehr".into())));
+
+        // The approval is bound to the origin's signature: re-signing the
+        // same content as someone else invalidates it.
+        let (mallory, mallory_pk) = party("agent://local/mallory", 99);
+        let verifier = verifier.with_origin_keys(move |src| {
+            (src == "agent://local/mallory").then(|| Verifier::from_base64(&mallory_pk).unwrap())
+        });
+        let mut stolen = env.clone();
+        stolen.src = mallory.id.into();
+        stolen.sign(&mallory.signer).unwrap();
+        assert!(matches!(verifier.verify(&stolen, &policy), Err(ApprovalError::BadSignature { index: 0, .. })));
+
+        let mut unsigned = env.clone();
+        unsigned.sig = None;
+        assert!(matches!(verifier.verify(&unsigned, &policy), Err(ApprovalError::Unsigned(_))));
+        let mut stranger = env;
+        stranger.src = "agent://local/stranger".into();
+        assert_eq!(verifier.verify(&stranger, &policy), Err(ApprovalError::UnknownOrigin("agent://local/stranger".into())));
+    }
+
+    #[test]
+    fn a_verifier_is_built_from_json() {
+        let (_, pk) = party("dr-a", 10);
+        let config: ApprovalConfig = serde_json::from_value(json!({
+            "signers": [{ "id": "dr-a", "public_key": pk, "roles": ["clinician"] }],
+            "require": { "topic://orders/*": "1 of {clinician}" }
+        }))
+        .unwrap();
+        let verifier = ApprovalVerifier::from_config(&config).unwrap();
+        assert_eq!(verifier.policies, vec![("topic://orders/*".to_string(), "1 of {clinician}".parse().unwrap())]);
+        let bad: ApprovalConfig = serde_json::from_value(json!({ "require": { "topic://x": "most of them" } })).unwrap();
+        assert!(ApprovalVerifier::from_config(&bad).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::envelope::header;
+use crate::Envelope;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// Content address of a blob: `blob://sha256/<hex>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlobRef {
+    pub sha256: [u8; 32],
+}
+
+#[derive(Debug, Error)]
+pub enum BlobError {
+    #[error("invalid blob reference {0:?}")]
+    InvalidRef(String),
+    #[error("blob {0} not found")]
+    NotFound(BlobRef),
+    #[error("blob {0} failed its digest check")]
+    Corrupt(BlobRef),
+    #[error("blob store io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("claim check: {0}")]
+    Payload(String),
+}
+
+impl BlobRef {
+    pub fn of(bytes: &[u8]) -> Self {
+        Self { sha256: Sha256::digest(bytes).into() }
+    }
+
+    pub fn hex(&self) -> String {
+        hex::encode(self.sha256)
+    }
+
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        Self::of(bytes) == *self
+    }
+}
+
+impl fmt::Display for BlobRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "blob://sha256/{}", self.hex())
+    }
+}
+
+impl FromStr for BlobRef {
+    type Err = BlobError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || BlobError::InvalidRef(s.to_string());
+        let hex = s.strip_prefix("blob://sha256/").ok_or_else(bad)?;
+        let mut sha256 = [0u8; 32];
+        hex::decode_to_slice(hex, &mut sha256).map_err(|_| bad())?;
+        Ok(Self { sha256 })
+    }
+}
+
+/// Content-addressed storage for payloads too large to ride in an envelope.
+///
+/// Blobs are kept alive by leases: each envelope that references a blob
+/// holds one (keyed by envelope id) until it is released or its retention
+/// runs out. `gc` deletes blobs with no live leases.
+pub trait BlobStore: Send + Sync {
+    fn put(&self, bytes: &[u8]) -> Result<BlobRef, BlobError>;
+    /// Store a blob with a lease for `holder` already taken, so `gc` can
+    /// never see it unreferenced in between. Stores with a concurrent `gc`
+    /// must override this; the default is only as atomic as `put` then
+    /// `retain`.
+    fn put_retained(&self, bytes: &[u8], holder: &str, until: Option<SystemTime>) -> Result<BlobRef, BlobError> {
+        let blob = self.put(bytes)?;
+        self.retain(&blob, holder, until)?;
+        Ok(blob)
+    }
+    /// Fetch a blob, verifying it still hashes to its address.
+    fn get(&self, blob: &BlobRef) -> Result<Vec<u8>, BlobError>;
+    fn contains(&self, blob: &BlobRef) -> bool;
+    /// Take (or extend) a lease for `holder`; `None` means until released.
+    fn retain(&self, blob: &BlobRef, holder: &str, until: Option<SystemTime>) -> Result<(), BlobError>;
+    fn release(&self, blob: &BlobRef, holder: &str) -> Result<(), BlobError>;
+    /// Live lease count.
+    fn refcount(&self, blob: &BlobRef) -> Result<usize, BlobError>;
+    /// Drop expired leases and delete unreferenced blobs; returns blobs removed.
+    fn gc(&self, now: SystemTime) -> Result<usize, BlobError>;
+}
+
+/// When to swap a payload for a blob reference.
+#[derive(Debug, Clone)]
+pub struct ClaimCheckPolicy {
+    /// Payloads whose JSON form is larger than this go to the blob store.
+    pub threshold: usize,
+    /// Lease length for envelopes without a TTL or deadline.
+    pub default_retention: Duration,
+}
+
+impl Default for ClaimCheckPolicy {
+    fn default() -> Self {
+        Self { threshold: 256 * 1024, default_retention: Duration::from_secs(24 * 60 * 60) }
+    }
+}
+
+impl ClaimCheckPolicy {
+    /// Move a large payload into `store` and return an envelope carrying only
+    /// its `blob://` reference, with digest and size headers. The blob is
+    /// leased to the envelope id until the envelope's retention ends.
+    /// Returns `None` when the payload is small enough to send inline.
+    /// `check_out` restores the original exactly, so a signature taken
+    /// before check-in verifies again afterwards.
+    pub fn check_in<T: Serialize>(
+        &self,
+        env: &Envelope<T>,
+        store: &dyn BlobStore,
+    ) -> Result<Option<Envelope<Value>>, BlobError> {
+        let bytes = serde_json::to_vec(&env.payload).map_err(|e| BlobError::Payload(e.to_string()))?;
+        if bytes.len() <= self.threshold {
+            return Ok(None);
+        }
+        let until = env
+            .expires_at()
+            .map(SystemTime::from)
+            .unwrap_or_else(|| SystemTime::now() + self.default_retention);
+        let blob = store.put_retained(&bytes, &env.id, Some(until))?;
+
+        let mut out = env.with_payload(Value::String(blob.to_string()));
+        out.headers.insert(header::BLOB_DIGEST.into(), format!("sha256:{}", blob.hex()));
+        out.headers.insert(header::BLOB_SIZE.into(), bytes.len().to_string());
+        Ok(Some(out))
+    }
+}
+
+pub fn is_claim_check<T>(env: &Envelope<T>) -> bool {
+    env.headers.contains_key(header::BLOB_DIGEST)
+}
+
+/// The `blob://` reference a claim-checked envelope carries, once checked
+/// against its digest header; `None` without a claim check.
+pub fn reference(env: &Envelope<Value>) -> Result<Option<BlobRef>, BlobError> {
+    let Some(digest) = env.headers.get(header::BLOB_DIGEST) else {
+        return Ok(None);
+    };
+    let Value::String(uri) = &env.payload else {
+        return Err(BlobError::Payload("expected blob:// reference".into()));
+    };
+    let blob: BlobRef = uri.parse()?;
+    if digest.strip_prefix("sha256:") != Some(blob.hex().as_str()) {
+        return Err(BlobError::Payload(format!("digest header {} does not match {}", digest, blob)));
+    }
+    Ok(Some(blob))
+}
+
+/// Swap a `blob://` reference back for the stored payload. Envelopes
+/// without a claim check are returned untouched.
+pub fn check_out(mut env: Envelope<Value>, store: &dyn BlobStore) -> Result<Envelope<Value>, BlobError> {
+    let Some(blob) = reference(&env)? else {
+        return Ok(env);
+    };
+    let bytes = store.get(&blob)?;
+    env.payload = serde_json::from_slice(&bytes).map_err(|e| BlobError::Payload(e.to_string()))?;
+    env.headers.remove(header::BLOB_DIGEST);
+    env.headers.remove(header::BLOB_SIZE);
+    Ok(env)
+}
@@ -1,13 +1,26 @@
+use crate::approval::ApprovalError;
+use crate::compression::{self, CompressionError};
+use crate::replay::ReplayError;
+use crate::seal::SealError;
+use crate::schema::{SchemaViolation, SchemaViolationReport};
+use crate::typed::{PayloadTypeError, TypeRegistry, TypedSubscription};
+use crate::upcast::Upcaster;
+use crate::content::ContentType;
+use crate::dedup::{Dedup, DedupConfig};
+use crate::envelope::header;
 use crate::Envelope;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::Value;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+use thiserror::Error;
 use tokio::sync::mpsc;
 
 /// Simple in-process bus with prefix-wildcard topics.
 /// Subscribers register a pattern like "topic://ddl/discovered/*".
 /// Publishers send to a concrete topic like "topic://ddl/discovered/pg".
-fn matches(pattern: &str, topic: &str) -> bool {
+pub(crate) fn matches(pattern: &str, topic: &str) -> bool {
     if let Some(pref) = pattern.strip_suffix("/*") {
         topic.starts_with(pref)
     } else {
@@ -20,15 +33,80 @@ pub struct Subscription {
     pub rx: mpsc::Receiver<Envelope<Value>>,
 }
 
+type Sender = mpsc::Sender<Envelope<Value>>;
+
 struct SubEntry {
     pattern: String,
-    tx: mpsc::Sender<Envelope<Value>>,
+    tx: Sender,
+    dedup: Option<Arc<Dedup>>,
+    /// Queue group and member name.
+    queue: Option<(String, String)>,
+}
+
+/// Why an envelope was not delivered.
+#[derive(Debug, Error)]
+pub enum DeliveryError {
+    #[error("replay rejected: {0}")]
+    Replay(#[from] ReplayError),
+    #[error("approval rejected: {0}")]
+    Approval(#[from] ApprovalError),
+    #[error("envelope {0} expired before delivery")]
+    Expired(String),
+    #[error("compressed payload rejected: {0}")]
+    Compression(#[from] CompressionError),
+    #[error("schema rejected: {0}")]
+    Schema(#[from] SchemaViolation),
+    #[error(transparent)]
+    Type(#[from] PayloadTypeError),
+    #[error(transparent)]
+    Seal(#[from] SealError),
+    /// Refused by policy; carries the explanation.
+    #[error("denied: {0}")]
+    Denied(String),
+    /// `src` is not who signed the envelope, or can't be vouched for.
+    #[error("identity rejected: {0}")]
+    Identity(String),
+}
+
+/// Hook run on every published envelope before it is fanned out.
+/// Any filter returning an error stops delivery to all subscribers.
+pub trait DeliveryFilter: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn check(&self, topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError>;
+
+    /// Run per receiving queue group (the group name is the receiver's
+    /// identity) once the envelope has passed `check`. An error skips that
+    /// receiver only.
+    fn check_receiver(&self, _receiver: &str, _topic: &str, _env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        Ok(())
+    }
+
+    /// Run once per delivery for subscriptions outside any queue group,
+    /// which carry no identity to check. An error skips all of them.
+    fn check_anonymous(&self, _topic: &str, _env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        Ok(())
+    }
+
+    /// Run once the envelope has passed every filter's `check`, to record
+    /// state that a rejected envelope must not leave behind. An error still
+    /// stops delivery.
+    fn commit(&self, _topic: &str, _env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        Ok(())
+    }
 }
 
 #[derive(Default)]
 pub struct Bus {
     subs: RwLock<HashMap<usize, SubEntry>>,
     next_id: RwLock<usize>,
+    filters: RwLock<Vec<Arc<dyn DeliveryFilter>>>,
+    expiry_topic: RwLock<Option<String>>,
+    errors_topics: RwLock<HashMap<String, String>>,
+    types: RwLock<TypeRegistry>,
+    upcaster: RwLock<Option<Arc<Upcaster>>>,
+    dedup_groups: RwLock<HashMap<String, Arc<Dedup>>>,
+    queue_cursors: Mutex<HashMap<String, usize>>,
+    unready: RwLock<HashSet<(String, String)>>,
 }
 
 impl Bus {
@@ -36,12 +114,91 @@ impl Bus {
         Self {
             subs: RwLock::new(HashMap::new()),
             next_id: RwLock::new(0),
+            filters: RwLock::new(Vec::new()),
+            expiry_topic: RwLock::new(None),
+            errors_topics: RwLock::new(HashMap::new()),
+            types: RwLock::new(TypeRegistry::new()),
+            upcaster: RwLock::new(None),
+            dedup_groups: RwLock::new(HashMap::new()),
+            queue_cursors: Mutex::new(HashMap::new()),
+            unready: RwLock::new(HashSet::new()),
         }
     }
 
+    /// Route expired envelopes to `topic` instead of silently dropping them.
+    pub fn set_expiry_topic(&self, topic: Option<String>) {
+        *self.expiry_topic.write() = topic;
+    }
+
+    pub fn expiry_topic(&self) -> Option<String> {
+        self.expiry_topic.read().clone()
+    }
+
+    /// Where to report payload violations for envelopes from `src`
+    /// (the `errors` topic in the agent's manifest).
+    pub fn set_errors_topic(&self, src: impl Into<String>, topic: impl Into<String>) {
+        self.errors_topics.write().insert(src.into(), topic.into());
+    }
+
+    pub fn errors_topic(&self, src: &str) -> Option<String> {
+        self.errors_topics.read().get(src).cloned()
+    }
+
+    /// Install a delivery filter. Filters run in installation order.
+    pub fn add_filter(&self, filter: Arc<dyn DeliveryFilter>) {
+        self.filters.write().push(filter);
+    }
+
     /// Subscribe to a topic pattern. Returns a Subscription with a Receiver.
     pub fn subscribe(&self, pattern: impl Into<String>) -> Subscription {
-        let pattern = pattern.into();
+        self.subscribe_inner(pattern.into(), None, None)
+    }
+
+    /// Subscribe, dropping envelopes `dedup` has already seen. Subscriptions
+    /// sharing one `Dedup` (see `dedup_group`) receive each key once between them.
+    pub fn subscribe_dedup(&self, pattern: impl Into<String>, dedup: Arc<Dedup>) -> Subscription {
+        self.subscribe_inner(pattern.into(), Some(dedup), None)
+    }
+
+    /// Subscribe as `member` of queue group `group`: each envelope goes to
+    /// one matching member of the group, round-robin, rather than to all.
+    /// A member may hold several subscriptions. Queue groups are not
+    /// deduplicated; members that need it check a `Dedup` themselves.
+    pub fn subscribe_queue(
+        &self,
+        pattern: impl Into<String>,
+        group: impl Into<String>,
+        member: impl Into<String>,
+    ) -> Subscription {
+        self.subscribe_inner(pattern.into(), None, Some((group.into(), member.into())))
+    }
+
+    /// Take `member` of `group` out of routing (or put it back). While every
+    /// matching member is unready, envelopes are routed among all of them.
+    pub fn set_queue_ready(&self, group: &str, member: &str, ready: bool) {
+        let key = (group.to_string(), member.to_string());
+        if ready {
+            self.unready.write().remove(&key);
+        } else {
+            self.unready.write().insert(key);
+        }
+    }
+
+    pub fn is_queue_ready(&self, group: &str, member: &str) -> bool {
+        !self.unready.read().contains(&(group.to_string(), member.to_string()))
+    }
+
+    /// The shared `Dedup` for consumer group `name`, created with `config`
+    /// on first use.
+    pub fn dedup_group(&self, name: &str, config: DedupConfig) -> Arc<Dedup> {
+        self.dedup_groups
+            .write()
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(Dedup::new(config)))
+            .clone()
+    }
+
+    fn subscribe_inner(&self, pattern: String, dedup: Option<Arc<Dedup>>, queue: Option<(String, String)>) -> Subscription {
         let (tx, rx) = mpsc::channel(1024);
 
         let mut subs = self.subs.write();
@@ -49,24 +206,168 @@ impl Bus {
         let id = *id_lock;
         *id_lock += 1;
 
-        subs.insert(id, SubEntry { pattern: pattern.clone(), tx });
+        subs.insert(id, SubEntry { pattern: pattern.clone(), tx, dedup, queue });
         Subscription { pattern, rx }
     }
 
+    /// Bind payload type `T` to `ctype` for typed publish and subscribe.
+    pub fn register_type<T: 'static>(&self, ctype: ContentType) -> Result<(), PayloadTypeError> {
+        self.types.write().register::<T>(ctype)
+    }
+
+    pub fn ctype_of<T: 'static>(&self) -> Option<ContentType> {
+        self.types.read().ctype_of::<T>().cloned()
+    }
+
+    pub fn types(&self) -> TypeRegistry {
+        self.types.read().clone()
+    }
+
+    /// Migrations used to upcast older payloads for typed subscribers.
+    pub fn set_upcaster(&self, upcaster: Option<Arc<Upcaster>>) {
+        *self.upcaster.write() = upcaster;
+    }
+
+    pub fn upcaster(&self) -> Option<Arc<Upcaster>> {
+        self.upcaster.read().clone()
+    }
+
+    /// Subscribe to `pattern`, decoding payloads into `T`.
+    pub fn subscribe_typed<T: DeserializeOwned + 'static>(&self, pattern: impl Into<String>) -> TypedSubscription<T> {
+        TypedSubscription::new(self.subscribe(pattern), self.ctype_of::<T>(), self.upcaster())
+    }
+
+    /// Publish `payload` from `src` to `topic` under `T`'s registered content type.
+    pub async fn publish_typed<T: Serialize + 'static>(&self, src: &str, topic: &str, payload: T) -> Result<(), DeliveryError> {
+        let env = self.typed_envelope(src, topic, payload)?;
+        self.publish(topic, env).await
+    }
+
+    /// Envelope for `payload` under `T`'s registered content type.
+    pub fn typed_envelope<T: Serialize + 'static>(&self, src: &str, dest: &str, payload: T) -> Result<Envelope<Value>, PayloadTypeError> {
+        let type_name = std::any::type_name::<T>();
+        let ctype = self.ctype_of::<T>().ok_or(PayloadTypeError::Unregistered(type_name))?;
+        let payload = serde_json::to_value(payload).map_err(|e| PayloadTypeError::Encode { type_name, msg: e.to_string() })?;
+        Ok(Envelope::new(src, dest, ctype, payload))
+    }
+
     /// Publish an envelope to a concrete topic.
-    pub async fn publish(&self, topic: &str, env: Envelope<Value>) {
+    /// Envelopes past their TTL or deadline are dead-lettered instead, once
+    /// they have passed the filters, and compressed payloads (e.g. bridged
+    /// from a transport) are inflated first.
+    pub async fn publish(&self, topic: &str, env: Envelope<Value>) -> Result<(), DeliveryError> {
+        let env = compression::decompress(env, compression::DEFAULT_MAX_DECOMPRESSED)?;
+        let filters: Vec<Arc<dyn DeliveryFilter>> = self.filters.read().clone();
+        for f in &filters {
+            if let Err(e) = f.check(topic, &env) {
+                if let DeliveryError::Schema(v) = &e {
+                    self.report_violation(topic, &env, v.clone()).await;
+                }
+                return Err(e);
+            }
+        }
+        for f in &filters {
+            f.commit(topic, &env)?;
+        }
+        if env.is_expired() {
+            let id = env.id.clone();
+            self.dead_letter_expired(topic, env).await;
+            return Err(DeliveryError::Expired(id));
+        }
+
+        self.fan_out(topic, env).await;
+        Ok(())
+    }
+
+    /// Send an expired envelope to the expiry topic, tagged with the topic it
+    /// was meant for. Dropped if no expiry topic is configured.
+    pub async fn dead_letter_expired(&self, topic: &str, mut env: Envelope<Value>) {
+        let Some(expiry) = self.expiry_topic() else { return };
+        if topic == expiry {
+            return;
+        }
+        env.headers.insert(header::EXPIRED_FROM.into(), topic.to_string());
+        self.fan_out(&expiry, env).await;
+    }
+
+    /// Send a schema violation report to the sender's errors topic, if known.
+    pub async fn report_violation(&self, topic: &str, env: &Envelope<Value>, violation: SchemaViolation) {
+        let Some(errors) = self.errors_topic(&env.src) else { return };
+        if topic == errors {
+            return;
+        }
+        let report = SchemaViolationReport::envelope("agent://fabric/bus", &errors, topic, env, violation);
+        self.fan_out(&errors, report).await;
+    }
+
+    async fn fan_out(&self, topic: &str, env: Envelope<Value>) {
+        let filters: Vec<Arc<dyn DeliveryFilter>> = self.filters.read().clone();
+        let anonymous = filters.iter().find_map(|f| f.check_anonymous(topic, &env).err());
+        let mut refused = false;
         // Collect matches then send; avoid holding lock across awaits
-        let targets: Vec<mpsc::Sender<Envelope<Value>>> = {
+        let (mut targets, mut queues, closed) = {
             let subs = self.subs.read();
-            subs.values()
-                .filter(|s| matches(&s.pattern, topic))
-                .map(|s| s.tx.clone())
-                .collect()
+            let mut targets: Vec<(Sender, Option<Arc<Dedup>>)> = Vec::new();
+            let unready = self.unready.read();
+            let mut queues: HashMap<String, Vec<(bool, usize, Sender)>> = HashMap::new();
+            let mut closed = false;
+            for (id, s) in subs.iter() {
+                if s.tx.is_closed() {
+                    closed = true;
+                    continue;
+                }
+                if !matches(&s.pattern, topic) {
+                    continue;
+                }
+                match &s.queue {
+                    Some(key) => {
+                        let ready = !unready.contains(key);
+                        queues.entry(key.0.clone()).or_default().push((ready, *id, s.tx.clone()))
+                    }
+                    // The key is reserved here, under the dedup's lock, so a
+                    // concurrent publish of the same key finds it taken; a
+                    // failed send below releases it again.
+                    None if anonymous.is_some() => refused = true,
+                    None if s.dedup.as_ref().is_some_and(|d| !d.record(&env)) => {}
+                    None => targets.push((s.tx.clone(), s.dedup.clone())),
+                }
+            }
+            (targets, queues, closed)
         };
+        if closed {
+            self.subs.write().retain(|_, s| !s.tx.is_closed());
+        }
+        if let (true, Some(e)) = (refused, &anonymous) {
+            tracing::debug!("{} not delivered to anonymous subscriptions: {}", env.id, e);
+        }
+        queues.retain(|group, _| match filters.iter().find_map(|f| f.check_receiver(group, topic, &env).err()) {
+            Some(e) => {
+                tracing::debug!("{} not delivered to {}: {}", env.id, group, e);
+                false
+            }
+            None => true,
+        });
+        if !queues.is_empty() {
+            // One member per queue group, taking turns in subscription order.
+            let mut cursors = self.queue_cursors.lock();
+            for (group, mut members) in queues {
+                if members.iter().any(|(ready, ..)| *ready) {
+                    members.retain(|(ready, ..)| *ready);
+                }
+                members.sort_by_key(|(_, id, _)| *id);
+                let cursor = cursors.entry(group).or_default();
+                targets.push((members.swap_remove(*cursor % members.len()).2, None));
+                *cursor = cursor.wrapping_add(1);
+            }
+        }
 
-        for tx in targets {
-            // Best-effort; drop on full queues
-            let _ = tx.send(env.clone()).await;
+        for (tx, dedup) in targets {
+            // Best-effort; a failed send releases the key so a redelivery gets through.
+            if tx.send(env.clone()).await.is_err() {
+                if let Some(d) = dedup {
+                    d.release(&env);
+                }
+            }
         }
     }
 }
@@ -77,3 +378,53 @@ pub static GLOBAL_BUS: once_cell::sync::Lazy<Arc<Bus>> =
     once_cell::sync::Lazy::new(|| Arc::new(Bus::new()));
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seal::RequireSealed;
+    use crate::ContentType;
+    use serde_json::json;
+    use time::format_description::well_known::Rfc3339;
+    use time::OffsetDateTime;
+
+    fn expired(topic: &str) -> Envelope<Value> {
+        let mut env = Envelope::new("agent://local/a", topic, ContentType::mime("application", "json"), json!(1))
+            .with_ttl(std::time::Duration::from_millis(1));
+        env.ts = (OffsetDateTime::now_utc() - time::Duration::minutes(1)).format(&Rfc3339).unwrap();
+        env
+    }
+
+    #[tokio::test]
+    async fn expired_envelopes_are_dead_lettered_not_delivered() {
+        let bus = Bus::new();
+        bus.set_expiry_topic(Some("topic://expired".into()));
+        let mut dead = bus.subscribe("topic://expired");
+        let mut live = bus.subscribe("topic://t");
+        let env = expired("topic://t");
+        let Err(DeliveryError::Expired(id)) = bus.publish("topic://t", env.clone()).await else {
+            panic!("expired envelope delivered")
+        };
+        assert_eq!(id, env.id);
+        assert!(live.rx.try_recv().is_err());
+        let lettered = dead.rx.try_recv().unwrap();
+        assert_eq!(lettered.id, env.id);
+        assert_eq!(lettered.headers.get(header::EXPIRED_FROM).map(String::as_str), Some("topic://t"));
+
+        // Without an expiry topic the envelope is just dropped.
+        bus.set_expiry_topic(None);
+        assert!(matches!(bus.publish("topic://t", expired("topic://t")).await, Err(DeliveryError::Expired(_))));
+        assert!(dead.rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn expired_envelopes_must_pass_the_filters_to_be_dead_lettered() {
+        let bus = Bus::new();
+        bus.set_expiry_topic(Some("topic://expired".into()));
+        bus.add_filter(Arc::new(RequireSealed::new(["topic://phi/*"])));
+        let mut dead = bus.subscribe("topic://expired");
+        let err = bus.publish("topic://phi/notes", expired("topic://phi/notes")).await.unwrap_err();
+        assert!(matches!(err, DeliveryError::Seal(_)), "{}", err);
+        assert!(dead.rx.try_recv().is_err());
+    }
+}
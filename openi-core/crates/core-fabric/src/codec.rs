@@ -0,0 +1,114 @@
+use crate::envelope::header;
+use crate::Envelope;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Wire encoding for whole envelopes.
+///
+/// Only the bytes on the wire change; signatures are always taken over
+/// `Envelope::canonical_bytes` (JSON), so an envelope can be re-encoded at
+/// every hop without invalidating `sig`. Payload fields marked with
+/// `serde_bytes` travel as native byte strings in CBOR and MessagePack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    Json,
+    Cbor,
+    MsgPack,
+}
+
+#[derive(Debug, Error)]
+pub enum CodecError {
+    #[error("unknown codec {0:?}")]
+    Unknown(String),
+    #[error("{codec} encode: {msg}")]
+    Encode { codec: Codec, msg: String },
+    #[error("{codec} decode: {msg}")]
+    Decode { codec: Codec, msg: String },
+}
+
+impl Codec {
+    /// All supported codecs, most compact first.
+    pub const ALL: [Codec; 3] = [Codec::Cbor, Codec::MsgPack, Codec::Json];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            Codec::Cbor => "cbor",
+            Codec::MsgPack => "msgpack",
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, env: &Envelope<T>) -> Result<Vec<u8>, CodecError> {
+        let err = |msg: String| CodecError::Encode { codec: *self, msg };
+        match self {
+            Codec::Json => serde_json::to_vec(env).map_err(|e| err(e.to_string())),
+            Codec::Cbor => {
+                let mut out = Vec::new();
+                ciborium::into_writer(env, &mut out).map_err(|e| err(e.to_string()))?;
+                Ok(out)
+            }
+            // Named (map) encoding: positional structs would break on the
+            // fields we skip when empty.
+            Codec::MsgPack => rmp_serde::to_vec_named(env).map_err(|e| err(e.to_string())),
+        }
+    }
+
+    /// Decode an envelope and record the codec in its `content-encoding`
+    /// header. That header is hop-local and excluded from canonical bytes.
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<Envelope<T>, CodecError> {
+        let err = |msg: String| CodecError::Decode { codec: *self, msg };
+        let mut env: Envelope<T> = match self {
+            Codec::Json => serde_json::from_slice(bytes).map_err(|e| err(e.to_string()))?,
+            Codec::Cbor => ciborium::from_reader(bytes).map_err(|e| err(e.to_string()))?,
+            Codec::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| err(e.to_string()))?,
+        };
+        env.headers.insert(header::CONTENT_ENCODING.into(), self.name().into());
+        Ok(env)
+    }
+
+    /// Decode an envelope as a plain document, before its version is known.
+    pub fn decode_value(&self, bytes: &[u8]) -> Result<serde_json::Value, CodecError> {
+        self.decode_as(bytes)
+    }
+
+    /// Decode any document, e.g. a partial view of an envelope.
+    pub(crate) fn decode_as<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        let err = |msg: String| CodecError::Decode { codec: *self, msg };
+        match self {
+            Codec::Json => serde_json::from_slice(bytes).map_err(|e| err(e.to_string())),
+            Codec::Cbor => ciborium::from_reader(bytes).map_err(|e| err(e.to_string())),
+            Codec::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| err(e.to_string())),
+        }
+    }
+
+    /// Pick the first of the initiator's preferences the acceptor supports.
+    /// Every peer speaks JSON, so that is the fallback.
+    pub fn negotiate(initiator: &[Codec], acceptor: &[Codec]) -> Codec {
+        initiator
+            .iter()
+            .copied()
+            .find(|c| acceptor.contains(c))
+            .unwrap_or(Codec::Json)
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Codec {
+    type Err = CodecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" | "application/json" => Ok(Codec::Json),
+            "cbor" | "application/cbor" => Ok(Codec::Cbor),
+            "msgpack" | "application/msgpack" | "application/x-msgpack" => Ok(Codec::MsgPack),
+            other => Err(CodecError::Unknown(other.to_string())),
+        }
+    }
+}
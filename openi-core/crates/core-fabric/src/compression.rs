@@ -0,0 +1,201 @@
+use crate::envelope::header;
+use crate::Envelope;
+use base64::{engine::general_purpose, Engine as _};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Upper bound on an inflated payload unless a policy says otherwise.
+pub const DEFAULT_MAX_DECOMPRESSED: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+#[derive(Debug, Error)]
+pub enum CompressionError {
+    #[error("unknown compression {0:?}")]
+    Unknown(String),
+    #[error("payload inflates past the {max} byte limit")]
+    TooLarge { max: usize },
+    #[error("{algorithm}: {msg}")]
+    Codec { algorithm: Compression, msg: String },
+    #[error("compressed payload: {0}")]
+    Payload(String),
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::Gzip => "gzip",
+        }
+    }
+
+    fn compress(&self, bytes: &[u8], level: i32) -> Result<Vec<u8>, CompressionError> {
+        let err = |e: std::io::Error| CompressionError::Codec { algorithm: *self, msg: e.to_string() };
+        match self {
+            Compression::Zstd => zstd::bulk::compress(bytes, level).map_err(err),
+            Compression::Gzip => {
+                let level = flate2::Compression::new(level.clamp(0, 9) as u32);
+                let mut out = Vec::new();
+                flate2::read::GzEncoder::new(bytes, level).read_to_end(&mut out).map_err(err)?;
+                Ok(out)
+            }
+        }
+    }
+
+    /// Inflate, reading at most `max + 1` bytes so a bomb is caught without
+    /// ever materialising it.
+    fn decompress(&self, bytes: &[u8], max: usize) -> Result<Vec<u8>, CompressionError> {
+        let err = |e: std::io::Error| CompressionError::Codec { algorithm: *self, msg: e.to_string() };
+        let reader: Box<dyn Read + '_> = match self {
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(bytes).map_err(err)?),
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(bytes)),
+        };
+        let mut out = Vec::new();
+        reader.take(max as u64 + 1).read_to_end(&mut out).map_err(err)?;
+        if out.len() > max {
+            return Err(CompressionError::TooLarge { max });
+        }
+        Ok(out)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = CompressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "zstd" => Ok(Compression::Zstd),
+            "gzip" => Ok(Compression::Gzip),
+            other => Err(CompressionError::Unknown(other.to_string())),
+        }
+    }
+}
+
+/// When and how to compress payloads on the way out, and how far a
+/// compressed payload may inflate on the way in.
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    pub algorithm: Compression,
+    /// Payloads whose JSON form is at most this many bytes go out raw.
+    pub threshold: usize,
+    pub level: i32,
+    pub max_decompressed: usize,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            algorithm: Compression::Zstd,
+            threshold: 64 * 1024,
+            level: 3,
+            max_decompressed: DEFAULT_MAX_DECOMPRESSED,
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// Compressed copy of `env` if its payload is over the threshold. The
+    /// payload becomes a base64 string and the `compression` headers say how
+    /// to restore it. Those headers are hop-local: envelopes are always
+    /// decompressed before signatures are checked or handlers run.
+    pub fn compress<T: Serialize>(&self, env: &Envelope<T>) -> Result<Option<Envelope<Value>>, CompressionError> {
+        let raw = serde_json::to_vec(&env.payload).map_err(|e| CompressionError::Payload(e.to_string()))?;
+        if raw.len() <= self.threshold {
+            return Ok(None);
+        }
+        let packed = self.algorithm.compress(&raw, self.level)?;
+        let mut out = env.with_payload(Value::String(general_purpose::STANDARD.encode(packed)));
+        out.headers.insert(header::COMPRESSION.into(), self.algorithm.name().into());
+        out.headers.insert(header::COMPRESSION_SIZE.into(), raw.len().to_string());
+        Ok(Some(out))
+    }
+}
+
+pub fn is_compressed<T>(env: &Envelope<T>) -> bool {
+    env.headers.contains_key(header::COMPRESSION)
+}
+
+/// Restore a payload compressed by `CompressionPolicy::compress`. Envelopes
+/// without a `compression` header are returned untouched.
+pub fn decompress(mut env: Envelope<Value>, max: usize) -> Result<Envelope<Value>, CompressionError> {
+    let Some(algorithm) = env.headers.remove(header::COMPRESSION) else {
+        return Ok(env);
+    };
+    let algorithm: Compression = algorithm.parse()?;
+    // Cheap early reject; the bounded read below is what actually protects us.
+    let claimed = env.headers.remove(header::COMPRESSION_SIZE).and_then(|s| s.parse::<usize>().ok());
+    if claimed.is_some_and(|n| n > max) {
+        return Err(CompressionError::TooLarge { max });
+    }
+    let Value::String(b64) = &env.payload else {
+        return Err(CompressionError::Payload("expected base64 string".into()));
+    };
+    let packed = general_purpose::STANDARD
+        .decode(b64)
+        .map_err(|e| CompressionError::Payload(e.to_string()))?;
+    let raw = algorithm.decompress(&packed, max)?;
+    env.payload = serde_json::from_slice(&raw).map_err(|e| CompressionError::Payload(e.to_string()))?;
+    Ok(env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ContentType;
+    use serde_json::json;
+
+    fn env(payload: Value) -> Envelope<Value> {
+        Envelope::new("agent://local/a", "topic://t", ContentType::mime("application", "json"), payload)
+    }
+
+    #[test]
+    fn payloads_over_the_threshold_round_trip() {
+        for algorithm in [Compression::Zstd, Compression::Gzip] {
+            let policy = CompressionPolicy { algorithm, threshold: 64, ..CompressionPolicy::default() };
+            assert!(policy.compress(&env(json!("small"))).unwrap().is_none());
+            let original = env(json!({ "text": "a".repeat(10_000) }));
+            let packed = policy.compress(&original).unwrap().unwrap();
+            assert!(is_compressed(&packed));
+            let restored = decompress(packed, DEFAULT_MAX_DECOMPRESSED).unwrap();
+            assert_eq!(restored.payload, original.payload);
+            assert!(!is_compressed(&restored));
+        }
+    }
+
+    #[test]
+    fn inflating_past_the_cap_is_refused() {
+        for algorithm in [Compression::Zstd, Compression::Gzip] {
+            let policy = CompressionPolicy { algorithm, threshold: 0, ..CompressionPolicy::default() };
+            let bomb = policy.compress(&env(json!("0".repeat(1 << 20)))).unwrap().unwrap();
+            assert!(matches!(decompress(bomb.clone(), 4096), Err(CompressionError::TooLarge { max: 4096 })));
+            // A sender understating the size is caught by the bounded read.
+            let mut liar = bomb;
+            liar.headers.insert(header::COMPRESSION_SIZE.into(), "10".into());
+            assert!(matches!(decompress(liar, 4096), Err(CompressionError::TooLarge { max: 4096 })));
+        }
+    }
+
+    #[test]
+    fn unknown_algorithms_and_bad_payloads_are_refused() {
+        let mut e = env(json!("AAAA"));
+        e.headers.insert(header::COMPRESSION.into(), "lzma".into());
+        assert!(matches!(decompress(e, 1024), Err(CompressionError::Unknown(_))));
+        let mut e = env(json!(42));
+        e.headers.insert(header::COMPRESSION.into(), "zstd".into());
+        assert!(matches!(decompress(e, 1024), Err(CompressionError::Payload(_))));
+    }
+}
@@ -1,15 +1,329 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
 use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
 
-/// Simple content-type newtype so we can impl Display and validation later.
+/// Envelope content type: either a MIME type (`application/json;
+/// charset=utf-8`) or a logical, versioned type (`ddl.discovered.v1`).
+/// Serialized as its canonical string form.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct ContentType(pub String);
+pub enum ContentType {
+    Mime(MimeType),
+    Logical(LogicalType),
+}
+
+/// `type/subtype` plus parameters. Type, subtype and parameter names are
+/// case-insensitive and stored lowercased.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MimeType {
+    pub ty: String,
+    pub subtype: String,
+    pub params: BTreeMap<String, String>,
+}
+
+/// `namespace.name.vN`; the namespace may itself be dotted
+/// (`clinical.review.decision.v1` → namespace `clinical.review`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LogicalType {
+    pub namespace: String,
+    pub name: String,
+    pub version: u32,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ContentTypeError {
+    #[error("empty content type")]
+    Empty,
+    #[error("invalid MIME type {0:?}")]
+    Mime(String),
+    #[error("invalid logical type {0:?}: expected namespace.name.vN")]
+    Logical(String),
+}
+
+/// How a producer's content type relates to what a consumer expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    Exact,
+    /// Same family, producer on an older version the consumer can upcast.
+    Older,
+    /// Same family, producer on a newer version the consumer predates.
+    Newer,
+    Incompatible,
+}
+
+impl ContentType {
+    pub fn mime(ty: &str, subtype: &str) -> Self {
+        ContentType::Mime(MimeType {
+            ty: ty.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            params: BTreeMap::new(),
+        })
+    }
+
+    pub fn logical(namespace: impl Into<String>, name: impl Into<String>, version: u32) -> Self {
+        ContentType::Logical(LogicalType { namespace: namespace.into(), name: name.into(), version })
+    }
+
+    pub fn json() -> Self {
+        Self::mime("application", "json")
+    }
+
+    /// Logical version, or a MIME `version` parameter if present.
+    pub fn version(&self) -> Option<u32> {
+        match self {
+            ContentType::Logical(l) => Some(l.version),
+            ContentType::Mime(m) => m.params.get("version").and_then(|v| v.trim_start_matches('v').parse().ok()),
+        }
+    }
+
+    /// Same type with a different version.
+    pub fn with_version(&self, version: u32) -> Self {
+        match self {
+            ContentType::Logical(l) => ContentType::Logical(LogicalType { version, ..l.clone() }),
+            ContentType::Mime(m) => {
+                let mut m = m.clone();
+                m.params.insert("version".into(), version.to_string());
+                ContentType::Mime(m)
+            }
+        }
+    }
+
+    /// The type without its version: `ddl.discovered` or `type/subtype`.
+    pub fn family(&self) -> String {
+        match self {
+            ContentType::Logical(l) => format!("{}.{}", l.namespace, l.name),
+            ContentType::Mime(m) => format!("{}/{}", m.ty, m.subtype),
+        }
+    }
+
+    /// Compare `self` (what a consumer declares it accepts) against what a
+    /// producer sent. MIME consumers may use `*` wildcards.
+    pub fn compatibility(&self, producer: &ContentType) -> Compatibility {
+        let same_family = match (self, producer) {
+            (ContentType::Logical(c), ContentType::Logical(p)) => c.namespace == p.namespace && c.name == p.name,
+            (ContentType::Mime(c), ContentType::Mime(p)) => {
+                (c.ty == "*" || c.ty == p.ty) && (c.subtype == "*" || c.subtype == p.subtype)
+            }
+            _ => false,
+        };
+        if !same_family {
+            return Compatibility::Incompatible;
+        }
+        match (self.version(), producer.version()) {
+            (Some(c), Some(p)) if p < c => Compatibility::Older,
+            (Some(c), Some(p)) if p > c => Compatibility::Newer,
+            _ => Compatibility::Exact,
+        }
+    }
+
+    /// A consumer accepts the same version, or an older one it can upcast.
+    pub fn accepts(&self, producer: &ContentType) -> bool {
+        matches!(self.compatibility(producer), Compatibility::Exact | Compatibility::Older)
+    }
+}
 
 impl fmt::Display for ContentType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            ContentType::Logical(l) => write!(f, "{}.{}.v{}", l.namespace, l.name, l.version),
+            ContentType::Mime(m) => {
+                write!(f, "{}/{}", m.ty, m.subtype)?;
+                for (k, v) in &m.params {
+                    if v.chars().all(is_token_char) && !v.is_empty() {
+                        write!(f, "; {}={}", k, v)?;
+                    } else {
+                        write!(f, "; {}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\""))?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for ContentType {
+    type Err = ContentTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ContentTypeError::Empty);
+        }
+        if s.contains('/') {
+            parse_mime(s).map(ContentType::Mime)
+        } else {
+            parse_logical(s).map(ContentType::Logical)
+        }
+    }
+}
+
+impl TryFrom<&str> for ContentType {
+    type Error = ContentTypeError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
     }
 }
 
-impl From<&str> for ContentType {
-    fn from(s: &str) -> Self { ContentType(s.to_string()) }
+impl Serialize for ContentType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ContentType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$&-^_.+*".contains(c)
+}
+
+fn is_segment(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+fn parse_mime(s: &str) -> Result<MimeType, ContentTypeError> {
+    let bad = || ContentTypeError::Mime(s.to_string());
+    let mut parts = split_params(s).into_iter();
+    let essence = parts.next().ok_or_else(bad)?;
+    let (ty, subtype) = essence.trim().split_once('/').ok_or_else(bad)?;
+    let (ty, subtype) = (ty.trim().to_ascii_lowercase(), subtype.trim().to_ascii_lowercase());
+    if ty.is_empty() || subtype.is_empty() || !ty.chars().all(is_token_char) || !subtype.chars().all(is_token_char) {
+        return Err(bad());
+    }
+    let mut params = BTreeMap::new();
+    for p in parts {
+        let p = p.trim();
+        if p.is_empty() {
+            continue;
+        }
+        let (k, v) = p.split_once('=').ok_or_else(bad)?;
+        let k = k.trim().to_ascii_lowercase();
+        if k.is_empty() || !k.chars().all(is_token_char) {
+            return Err(bad());
+        }
+        let v = v.trim();
+        let v = match v.strip_prefix('"') {
+            Some(quoted) => unquote(quoted).ok_or_else(bad)?,
+            None => v.to_string(),
+        };
+        params.insert(k, v);
+    }
+    Ok(MimeType { ty, subtype, params })
+}
+
+/// Body of a quoted string after its opening quote: unescape up to the
+/// closing quote, which must end the value.
+fn unquote(s: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push(chars.next()?),
+            '"' => return chars.as_str().is_empty().then_some(out),
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+/// Split on `;` outside double quotes.
+fn split_params(s: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                out.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    out.push(&s[start..]);
+    out
+}
+
+fn parse_logical(s: &str) -> Result<LogicalType, ContentTypeError> {
+    let bad = || ContentTypeError::Logical(s.to_string());
+    let segments: Vec<&str> = s.split('.').collect();
+    if segments.len() < 3 || !segments.iter().all(|seg| is_segment(seg)) {
+        return Err(bad());
+    }
+    let (version, rest) = segments.split_last().ok_or_else(bad)?;
+    let version = version
+        .strip_prefix('v')
+        .filter(|v| v.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(bad)?;
+    let (name, namespace) = rest.split_last().ok_or_else(bad)?;
+    Ok(LogicalType { namespace: namespace.join("."), name: name.to_string(), version })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(s: &str) -> ContentType {
+        let ct: ContentType = s.parse().unwrap();
+        assert_eq!(ct.to_string().parse::<ContentType>().unwrap(), ct, "{}", s);
+        ct
+    }
+
+    #[test]
+    fn mime_types_round_trip_with_quoted_params() {
+        let ct = round_trip("Application/JSON; Charset=utf-8");
+        assert_eq!(ct.to_string(), "application/json; charset=utf-8");
+
+        let ct = round_trip(r#"text/plain; title="a; b=c"; note="say \"hi\" \\ bye"; empty="""#);
+        let ContentType::Mime(m) = &ct else { panic!("not mime") };
+        assert_eq!(m.params["title"], "a; b=c");
+        assert_eq!(m.params["note"], r#"say "hi" \ bye"#);
+        assert_eq!(m.params["empty"], "");
+        assert_eq!(ct.to_string(), r#"text/plain; empty=""; note="say \"hi\" \\ bye"; title="a; b=c""#);
+
+        let json = serde_json::to_string(&ct).unwrap();
+        assert_eq!(serde_json::from_str::<ContentType>(&json).unwrap(), ct);
+    }
+
+    #[test]
+    fn logical_types_round_trip() {
+        let ct = round_trip("clinical.review.decision.v12");
+        assert_eq!(ct, ContentType::logical("clinical.review", "decision", 12));
+        assert_eq!(ct.family(), "clinical.review.decision");
+        assert_eq!(ct.version(), Some(12));
+        assert_eq!(ct.with_version(13).to_string(), "clinical.review.decision.v13");
+        assert_eq!(round_trip("application/fhir+json; version=v2").version(), Some(2));
+    }
+
+    #[test]
+    fn malformed_types_are_rejected() {
+        assert_eq!("  ".parse::<ContentType>(), Err(ContentTypeError::Empty));
+        for bad in ["application/", "/json", "app lication/json", "text/plain; charset", "text/plain; =x",
+            r#"text/plain; a="open"#, r#"text/plain; a="x"y"#]
+        {
+            assert_eq!(bad.parse::<ContentType>(), Err(ContentTypeError::Mime(bad.to_string())), "{}", bad);
+        }
+        for bad in ["ddl.v1", "ddl.discovered", "ddl.discovered.v", "ddl.discovered.vx", "DDL.discovered.v1", "a..b.v1"] {
+            assert_eq!(bad.parse::<ContentType>(), Err(ContentTypeError::Logical(bad.to_string())), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn consumers_accept_their_version_and_older() {
+        let v2 = ContentType::logical("ddl", "discovered", 2);
+        assert_eq!(v2.compatibility(&v2.with_version(1)), Compatibility::Older);
+        assert_eq!(v2.compatibility(&v2.with_version(3)), Compatibility::Newer);
+        assert!(v2.accepts(&v2.with_version(1)) && !v2.accepts(&v2.with_version(3)));
+        assert_eq!(v2.compatibility(&ContentType::logical("ddl", "other", 2)), Compatibility::Incompatible);
+        assert!("application/*".parse::<ContentType>().unwrap().accepts(&ContentType::json()));
+    }
 }
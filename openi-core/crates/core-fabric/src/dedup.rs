@@ -0,0 +1,209 @@
+use crate::envelope::header;
+use crate::Envelope;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How long, and how many, delivery keys a `Dedup` remembers.
+#[derive(Debug, Clone, Copy)]
+pub struct DedupConfig {
+    pub window: Duration,
+    pub capacity: usize,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self { window: Duration::from_secs(5 * 60), capacity: 100_000 }
+    }
+}
+
+/// Drops redelivered envelopes for one subscription or consumer group.
+///
+/// Unlike `ReplayGuard`, which rejects replays at the trust boundary, this
+/// absorbs the duplicates that retries and multi-node bridging produce
+/// legitimately. An envelope is keyed by its `idempotency_key` header when
+/// present (scoped to its `src`), otherwise by its `id`. Keys are forgotten
+/// `window` after they were first seen, or earlier once `capacity` is hit.
+pub struct Dedup {
+    config: DedupConfig,
+    state: Mutex<Seen>,
+}
+
+#[derive(Default)]
+struct Seen {
+    keys: HashMap<String, Instant>,
+    order: VecDeque<(Instant, String)>,
+}
+
+impl Dedup {
+    pub fn new(config: DedupConfig) -> Self {
+        let config = DedupConfig { capacity: config.capacity.max(1), ..config };
+        Self { config, state: Mutex::new(Seen::default()) }
+    }
+
+    pub fn config(&self) -> DedupConfig {
+        self.config
+    }
+
+    pub fn key<T>(env: &Envelope<T>) -> String {
+        match env.headers.get(header::IDEMPOTENCY_KEY) {
+            Some(k) => format!("{}#{}", env.src, k),
+            None => env.id.clone(),
+        }
+    }
+
+    /// Record `env` and report whether it was already seen in the window.
+    pub fn is_duplicate<T>(&self, env: &Envelope<T>) -> bool {
+        self.is_duplicate_at(env, Instant::now())
+    }
+
+    pub fn is_duplicate_at<T>(&self, env: &Envelope<T>, now: Instant) -> bool {
+        !self.record_at(env, now)
+    }
+
+    /// Report whether `env` was already seen in the window, without recording it.
+    pub fn seen<T>(&self, env: &Envelope<T>) -> bool {
+        self.seen_at(env, Instant::now())
+    }
+
+    pub fn seen_at<T>(&self, env: &Envelope<T>, now: Instant) -> bool {
+        let mut seen = self.state.lock();
+        seen.prune(now, self.config.window);
+        seen.keys.contains_key(&Self::key(env))
+    }
+
+    /// Record `env`, reserving its key for one delivery. Returns false if it
+    /// was already recorded in the window.
+    pub fn record<T>(&self, env: &Envelope<T>) -> bool {
+        self.record_at(env, Instant::now())
+    }
+
+    pub fn record_at<T>(&self, env: &Envelope<T>, now: Instant) -> bool {
+        let key = Self::key(env);
+        let mut seen = self.state.lock();
+        seen.prune(now, self.config.window);
+        if seen.keys.contains_key(&key) {
+            return false;
+        }
+        seen.keys.insert(key.clone(), now);
+        seen.order.push_back((now, key));
+        while seen.order.len() > self.config.capacity {
+            if let Some((_, old)) = seen.order.pop_front() {
+                seen.keys.remove(&old);
+            }
+        }
+        true
+    }
+
+    /// Forget `env`'s key, e.g. when the delivery it was reserved for failed.
+    pub fn release<T>(&self, env: &Envelope<T>) {
+        let key = Self::key(env);
+        let mut seen = self.state.lock();
+        if seen.keys.remove(&key).is_some() {
+            if let Some(i) = seen.order.iter().rposition(|(_, k)| *k == key) {
+                seen.order.remove(i);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Seen {
+    fn prune(&mut self, now: Instant, window: Duration) {
+        while let Some((at, _)) = self.order.front() {
+            if now.duration_since(*at) < window {
+                break;
+            }
+            if let Some((_, key)) = self.order.pop_front() {
+                self.keys.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bus, ContentType};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn env() -> Envelope<Value> {
+        Envelope::new("agent://local/a", "topic://t", ContentType::mime("application", "json"), json!(1))
+    }
+
+    #[test]
+    fn seen_does_not_record() {
+        let dedup = Dedup::new(DedupConfig::default());
+        let e = env();
+        assert!(!dedup.seen(&e));
+        assert!(dedup.is_empty());
+        assert!(dedup.record(&e));
+        assert!(dedup.seen(&e));
+        assert!(!dedup.record(&e));
+        assert!(dedup.is_duplicate(&e));
+        dedup.release(&e);
+        assert!(dedup.is_empty());
+        assert!(dedup.record(&e));
+    }
+
+    #[tokio::test]
+    async fn a_shared_dedup_delivers_each_key_once() {
+        let bus = Bus::new();
+        let dedup = Arc::new(Dedup::new(DedupConfig::default()));
+        let mut a = bus.subscribe_dedup("topic://t", dedup.clone());
+        let mut b = bus.subscribe_dedup("topic://t", dedup.clone());
+        let e = env();
+        bus.publish("topic://t", e.clone()).await.unwrap();
+        bus.publish("topic://t", e.clone()).await.unwrap();
+        let got = [a.rx.try_recv().is_ok(), a.rx.try_recv().is_ok(), b.rx.try_recv().is_ok(), b.rx.try_recv().is_ok()];
+        assert_eq!(got.iter().filter(|g| **g).count(), 1);
+        assert_eq!(dedup.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn an_undelivered_key_is_not_recorded() {
+        let bus = Bus::new();
+        let dedup = Arc::new(Dedup::new(DedupConfig::default()));
+        drop(bus.subscribe_dedup("topic://t", dedup.clone()));
+        let e = env();
+        bus.publish("topic://t", e.clone()).await.unwrap();
+        assert!(!dedup.seen(&e));
+        let mut sub = bus.subscribe_dedup("topic://t", dedup.clone());
+        bus.publish("topic://t", e.clone()).await.unwrap();
+        assert_eq!(sub.rx.try_recv().unwrap().id, e.id);
+    }
+
+    #[tokio::test]
+    async fn concurrent_publishes_deliver_a_key_once() {
+        let bus = Bus::new();
+        let mut sub = bus.subscribe_dedup("topic://t", Arc::new(Dedup::new(DedupConfig::default())));
+        // With the queue full, the first publish waits on its send; the
+        // second has to find the key already reserved rather than go out too.
+        while sub.rx.len() < sub.rx.max_capacity() {
+            bus.publish("topic://t", env()).await.unwrap();
+        }
+        let queued = sub.rx.len();
+        let e = env();
+        let drain = async {
+            let mut got = Vec::new();
+            for _ in 0..=queued {
+                got.push(sub.rx.recv().await.unwrap().id);
+            }
+            got
+        };
+        let (first, second, mut got) =
+            tokio::join!(bus.publish("topic://t", e.clone()), bus.publish("topic://t", e.clone()), drain);
+        first.unwrap();
+        second.unwrap();
+        got.extend(std::iter::from_fn(|| sub.rx.try_recv().ok()).map(|x| x.id));
+        assert_eq!(got.iter().filter(|id| **id == e.id).count(), 1);
+    }
+}
@@ -1,10 +1,57 @@
+use crate::content::ContentType;
+use crate::signing::{Signer, Verifier};
+use crate::trace::TraceParent;
 use serde::{Serialize, Deserialize};
 use std::collections::BTreeMap;
-use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
 use ulid::Ulid;
 
 pub type Headers = BTreeMap<String, String>;
 
+/// Well-known header keys.
+pub mod header {
+    /// Milliseconds after `ts` at which the envelope is no longer deliverable.
+    pub const TTL_MS: &str = "ttl_ms";
+    /// RFC3339 instant after which the caller has given up; inherited by children.
+    pub const DEADLINE: &str = "deadline";
+    /// Topic an expired envelope was originally published to.
+    pub const EXPIRED_FROM: &str = "expired_from";
+    /// W3C trace context.
+    pub const TRACEPARENT: &str = "traceparent";
+    pub const TRACESTATE: &str = "tracestate";
+    /// Id of the envelope whose handling produced this one.
+    pub const CAUSATION_ID: &str = "causation_id";
+    /// Id of the envelope that started the whole conversation.
+    pub const CORRELATION_ID: &str = "correlation_id";
+    /// Wire codec the envelope arrived in.
+    pub const CONTENT_ENCODING: &str = "content-encoding";
+    /// Payload compression algorithm and uncompressed size in bytes.
+    pub const COMPRESSION: &str = "compression";
+    pub const COMPRESSION_SIZE: &str = "compression-size";
+    /// Claim check: `sha256:<hex>` of a payload moved to the blob store, and its size.
+    pub const BLOB_DIGEST: &str = "blob-digest";
+    pub const BLOB_SIZE: &str = "blob-size";
+    /// Sealed payloads: `x25519+<cipher>`, the sender's ephemeral X25519
+    /// key, and the content key wrapped per recipient (JSON).
+    pub const ENC: &str = "enc";
+    pub const ENC_EPK: &str = "enc-epk";
+    pub const ENC_RECIPIENTS: &str = "enc-recipients";
+    /// Application-level key for dedup; replaces `id` as the dedup key.
+    pub const IDEMPOTENCY_KEY: &str = "idempotency_key";
+    /// Original content type of a payload upcast on receipt.
+    pub const UPCAST_FROM: &str = "upcast_from";
+    /// Scopes a receiver must hold, comma-separated (`phi:read,phi:write`).
+    pub const SCOPES: &str = "scopes";
+
+    /// Set or rewritten per hop, so excluded from canonical bytes.
+    pub const HOP_LOCAL: &[&str] = &[CONTENT_ENCODING, COMPRESSION, COMPRESSION_SIZE];
+}
+
+/// Envelope format version written by this node. Older versions are read
+/// through `Upcaster`.
+pub const ENVELOPE_VERSION: u8 = 1;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Envelope<T = serde_json::Value> {
     pub v: u8,                 // schema version
@@ -12,28 +59,41 @@ pub struct Envelope<T = serde_json::Value> {
     pub src: String,           // agent://tenant/node/agent
     pub dest: String,          // topic://... or agent://...
     pub ts: String,            // RFC3339
-    pub ctype: String,         // ContentType
+    pub ctype: ContentType,    // MIME or logical type
     #[serde(default)]
-    pub headers: Headers,      // trace_id, ttl_ms, scopes, etc.
+    pub headers: Headers,      // traceparent, ttl_ms, scopes, etc.
     pub payload: T,            // typed payload
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sig: Option<String>,   // base64 signature
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub countersigs: Vec<Countersignature>, // ordered approvals after `sig`
+}
+
+/// An approval added on top of the origin signature. Each one covers the
+/// canonical bytes, the origin `sig` and every countersignature before it,
+/// so the chain can't be re-attributed, reordered or truncated without
+/// breaking later signatures.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Countersignature {
+    pub signer: String,        // agent://... or principal id
+    pub role: String,          // e.g. clinician, pharmacist
+    pub sig: String,           // base64 signature
 }
 
 impl<T: Serialize + for<'de> Deserialize<'de>> Envelope<T> {
-    pub fn new(src: impl Into<String>, dest: impl Into<String>, ctype: impl Into<String>, payload: T) -> Self {
+    pub fn new(src: impl Into<String>, dest: impl Into<String>, ctype: ContentType, payload: T) -> Self {
         Self {
-            v: 1,
+            v: ENVELOPE_VERSION,
             id: Ulid::new().to_string(),
             src: src.into(),
 
             dest: dest.into(),
-            ts: OffsetDateTime::now_utc()
-                .format(&time::format_description::well_known::Rfc3339).unwrap(),
-            ctype: ctype.into(),
+            ts: OffsetDateTime::now_utc().format(&Rfc3339).unwrap(),
+            ctype,
             headers: Headers::new(),
             payload,
             sig: None,
+            countersigs: Vec::new(),
         }
     }
 
@@ -43,12 +103,247 @@ impl<T: Serialize + for<'de> Deserialize<'de>> Envelope<T> {
     }
 
     pub fn canonical_bytes(&self) -> Vec<u8> {
-        // Sign without the signature field by serializing to a Value and
-        // removing the `sig` field. This avoids requiring `T: Clone`.
+        // Sign without the signature fields by serializing to a Value and
+        // removing `sig`/`countersigs`. This avoids requiring `T: Clone`.
+        // Hop-local headers are dropped too so re-encoding keeps `sig` valid.
         let mut v = serde_json::to_value(self).expect("serialize to value");
         if let serde_json::Value::Object(ref mut map) = v {
             map.remove("sig");
+            map.remove("countersigs");
+            if let Some(serde_json::Value::Object(h)) = map.get_mut("headers") {
+                for k in header::HOP_LOCAL {
+                    h.remove(*k);
+                }
+            }
         }
         serde_json::to_vec(&v).expect("serialize")
     }
+
+    /// Bytes covered by the countersignature at `index`: the canonical bytes
+    /// followed by the origin `sig` and the countersignatures that precede it.
+    pub fn countersign_bytes(&self, index: usize) -> Vec<u8> {
+        let mut bytes = self.canonical_bytes();
+        bytes.extend(serde_json::to_vec(&self.sig).expect("serialize"));
+        let prior = &self.countersigs[..index.min(self.countersigs.len())];
+        bytes.extend(serde_json::to_vec(prior).expect("serialize"));
+        bytes
+    }
+
+    /// Sign the canonical bytes as `src`, replacing any earlier signature.
+    /// Countersignatures made before this no longer verify.
+    pub fn sign(&mut self, signer: &Signer) -> anyhow::Result<()> {
+        self.sig = Some(signer.sign_bytes(&self.canonical_bytes())?);
+        Ok(())
+    }
+
+    /// Check `sig` against `src`'s key.
+    pub fn verify(&self, verifier: &Verifier) -> anyhow::Result<()> {
+        let sig = self.sig.as_deref().ok_or_else(|| anyhow::anyhow!("envelope {} is unsigned", self.id))?;
+        verifier.verify_bytes(&self.canonical_bytes(), sig)
+    }
+
+    /// Append a countersignature from `signer` acting in `role`.
+    pub fn countersign(&mut self, signer: &Signer, signer_id: impl Into<String>, role: impl Into<String>) -> anyhow::Result<()> {
+        let sig = signer.sign_bytes(&self.countersign_bytes(self.countersigs.len()))?;
+        self.countersigs.push(Countersignature { signer: signer_id.into(), role: role.into(), sig });
+        Ok(())
+    }
+}
+
+impl<T> Envelope<T> {
+    /// Copy of the metadata around a different payload.
+    pub fn with_payload<U>(&self, payload: U) -> Envelope<U> {
+        Envelope {
+            v: self.v,
+            id: self.id.clone(),
+            src: self.src.clone(),
+            dest: self.dest.clone(),
+            ts: self.ts.clone(),
+            ctype: self.ctype.clone(),
+            headers: self.headers.clone(),
+            payload,
+            sig: self.sig.clone(),
+            countersigs: self.countersigs.clone(),
+        }
+    }
+
+    /// Replace the payload, keeping id, headers and signatures intact.
+    pub fn map_payload<U, E>(self, f: impl FnOnce(T) -> Result<U, E>) -> Result<Envelope<U>, E> {
+        Ok(Envelope {
+            v: self.v,
+            id: self.id,
+            src: self.src,
+            dest: self.dest,
+            ts: self.ts,
+            ctype: self.ctype,
+            headers: self.headers,
+            payload: f(self.payload)?,
+            sig: self.sig,
+            countersigs: self.countersigs,
+        })
+    }
+
+    /// Earliest of `ts + ttl_ms` and the `deadline` header, if either is set.
+    /// Unparseable values are ignored rather than treated as expired.
+    pub fn expires_at(&self) -> Option<OffsetDateTime> {
+        let ttl = self
+            .headers
+            .get(header::TTL_MS)
+            .and_then(|ms| ms.parse::<i64>().ok())
+            .zip(OffsetDateTime::parse(&self.ts, &Rfc3339).ok())
+            .map(|(ms, ts)| ts + Duration::milliseconds(ms));
+        let deadline = self.deadline();
+        match (ttl, deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(OffsetDateTime::now_utc())
+    }
+
+    pub fn is_expired_at(&self, now: OffsetDateTime) -> bool {
+        self.expires_at().is_some_and(|at| now >= at)
+    }
+
+    pub fn deadline(&self) -> Option<OffsetDateTime> {
+        self.headers
+            .get(header::DEADLINE)
+            .and_then(|d| OffsetDateTime::parse(d, &Rfc3339).ok())
+    }
+
+    /// Entries of the `scopes` header.
+    pub fn scopes(&self) -> Vec<&str> {
+        self.headers
+            .get(header::SCOPES)
+            .map(|s| s.split([',', ' ']).map(str::trim).filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn with_ttl(mut self, ttl: std::time::Duration) -> Self {
+        self.headers.insert(header::TTL_MS.into(), ttl.as_millis().to_string());
+        self
+    }
+
+    /// Set the `deadline` header, keeping any earlier deadline already present.
+    pub fn with_deadline(mut self, at: OffsetDateTime) -> Self {
+        let at = self.deadline().map_or(at, |cur| cur.min(at));
+        if let Ok(s) = at.format(&Rfc3339) {
+            self.headers.insert(header::DEADLINE.into(), s);
+        }
+        self
+    }
+
+    pub fn traceparent(&self) -> Option<TraceParent> {
+        self.headers.get(header::TRACEPARENT).and_then(|t| t.parse().ok())
+    }
+
+    pub fn trace_id(&self) -> Option<String> {
+        self.traceparent().map(|t| t.trace_id_hex())
+    }
+
+    /// Start a new trace unless the envelope already carries a valid one.
+    pub fn ensure_trace(mut self) -> Self {
+        if self.traceparent().is_none() {
+            self.headers.insert(header::TRACEPARENT.into(), TraceParent::new_root().to_string());
+        }
+        self
+    }
+
+    /// Mark this envelope as caused by `parent`: continue its trace on a new
+    /// span, record causation/correlation ids, and inherit its deadline.
+    pub fn child_of<U>(mut self, parent: &Envelope<U>) -> Self {
+        let tp = parent.traceparent().map_or_else(TraceParent::new_root, |t| t.child());
+        self.headers.insert(header::TRACEPARENT.into(), tp.to_string());
+        match parent.headers.get(header::TRACESTATE) {
+            Some(state) => self.headers.insert(header::TRACESTATE.into(), state.clone()),
+            None => self.headers.remove(header::TRACESTATE),
+        };
+        let correlation = parent.headers.get(header::CORRELATION_ID).unwrap_or(&parent.id);
+        self.headers.insert(header::CORRELATION_ID.into(), correlation.clone());
+        self.headers.insert(header::CAUSATION_ID.into(), parent.id.clone());
+        self.inherit_deadline(parent)
+    }
+
+    /// Span carrying this envelope's trace ids, for `Instrument`ing handlers
+    /// so their log lines can be joined back to the trace.
+    pub fn span(&self) -> tracing::Span {
+        let tp = self.traceparent();
+        tracing::info_span!(
+            "envelope",
+            id = %self.id,
+            src = %self.src,
+            dest = %self.dest,
+            trace_id = tp.map(|t| t.trace_id_hex()).unwrap_or_default(),
+            span_id = tp.map(|t| t.parent_id_hex()).unwrap_or_default(),
+            correlation_id = self.headers.get(header::CORRELATION_ID).map(String::as_str).unwrap_or_default(),
+        )
+    }
+
+    /// Carry the parent's deadline over so work done on its behalf stops
+    /// once the original caller has given up.
+    pub fn inherit_deadline<U>(self, parent: &Envelope<U>) -> Self {
+        match parent.deadline() {
+            Some(at) => self.with_deadline(at),
+            None => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn env() -> Envelope<Value> {
+        Envelope::new("agent://local/a", "topic://t", ContentType::mime("application", "json"), json!(1))
+    }
+
+    fn ts(e: &Envelope<Value>) -> OffsetDateTime {
+        OffsetDateTime::parse(&e.ts, &Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn a_ttl_expires_relative_to_ts() {
+        let e = env().with_ttl(std::time::Duration::from_secs(1));
+        let at = ts(&e) + Duration::seconds(1);
+        assert_eq!(e.expires_at(), Some(at));
+        assert!(!e.is_expired_at(at - Duration::milliseconds(1)));
+        assert!(e.is_expired_at(at));
+        assert!(!e.is_expired());
+        assert_eq!(env().expires_at(), None);
+
+        let mut bad = env();
+        bad.headers.insert(header::TTL_MS.into(), "soon".into());
+        assert_eq!(bad.expires_at(), None);
+    }
+
+    #[test]
+    fn the_earlier_of_ttl_and_deadline_wins() {
+        let e = env();
+        let soon = ts(&e) + Duration::seconds(5);
+        let e = e.with_ttl(std::time::Duration::from_secs(60)).with_deadline(soon);
+        assert_eq!(e.expires_at(), Some(soon));
+        // A later deadline does not extend an earlier one.
+        let e = e.with_deadline(soon + Duration::hours(1));
+        assert_eq!(e.deadline(), Some(soon));
+        let e = env().with_ttl(std::time::Duration::from_secs(1)).with_deadline(soon);
+        assert_eq!(e.expires_at(), Some(ts(&e) + Duration::seconds(1)));
+    }
+
+    #[test]
+    fn children_inherit_the_parent_deadline() {
+        let parent = env().ensure_trace();
+        let at = ts(&parent) + Duration::seconds(30);
+        let parent = parent.with_deadline(at);
+        let child = env().child_of(&parent);
+        assert_eq!(child.deadline(), Some(at));
+        assert_eq!(child.headers.get(header::CAUSATION_ID), Some(&parent.id));
+        assert_eq!(child.trace_id(), parent.trace_id());
+        // The child's own earlier deadline is kept.
+        let early = at - Duration::seconds(20);
+        assert_eq!(env().with_deadline(early).child_of(&parent).deadline(), Some(early));
+        assert_eq!(env().child_of(&env()).deadline(), None);
+    }
 }
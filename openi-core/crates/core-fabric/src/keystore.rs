@@ -0,0 +1,335 @@
+use crate::signing::{Keypair, SigningBackend};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::{Signature as DalekSignature, VerifyingKey};
+use getrandom::getrandom;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use thiserror::Error;
+use time::OffsetDateTime;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Passphrase-protected store of named Ed25519 seeds.
+///
+/// On disk this is a JSON document. The wrapping key is derived from the
+/// passphrase with Argon2id, and each seed is sealed with ChaCha20-Poly1305
+/// using the entry name as associated data, so entries cannot be swapped.
+pub struct Keystore {
+    file: KeystoreFile,
+    key: Zeroizing<[u8; 32]>,
+}
+
+#[derive(Debug, Error)]
+pub enum KeystoreError {
+    #[error("keystore io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("keystore format: {0}")]
+    Format(String),
+    #[error("unsupported keystore version {0}")]
+    Version(u8),
+    #[error("wrong passphrase or corrupted keystore")]
+    Decrypt,
+    #[error("no keystore entry named {0:?}")]
+    NotFound(String),
+    #[error("keystore entry {0:?} already exists")]
+    Exists(String),
+}
+
+const KEYSTORE_VERSION: u8 = 1;
+const CHECK_AAD: &[u8] = b"openi-keystore-check";
+
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u8,
+    kdf: KdfParams,
+    cipher: String,
+    /// Sealed empty plaintext; lets `open` reject a wrong passphrase even
+    /// when the store has no entries yet.
+    check: Sealed,
+    #[serde(default)]
+    entries: BTreeMap<String, Entry>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct KdfParams {
+    alg: String,
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    public_key: String,
+    created: String,
+    #[serde(flatten)]
+    sealed: Sealed,
+}
+
+impl Keystore {
+    /// Create an empty keystore protected by `passphrase`.
+    pub fn create(passphrase: &str) -> Result<Self, KeystoreError> {
+        let mut salt = [0u8; 16];
+        getrandom(&mut salt).map_err(|e| KeystoreError::Format(e.to_string()))?;
+        let defaults = Params::default();
+        let kdf = KdfParams {
+            alg: "argon2id".into(),
+            salt: general_purpose::STANDARD.encode(salt),
+            m_cost: defaults.m_cost(),
+            t_cost: defaults.t_cost(),
+            p_cost: defaults.p_cost(),
+        };
+        let key = derive_key(passphrase, &kdf)?;
+        let check = seal(&key, CHECK_AAD, &[])?;
+        Ok(Self {
+            file: KeystoreFile {
+                version: KEYSTORE_VERSION,
+                kdf,
+                cipher: "chacha20poly1305".into(),
+                check,
+                entries: BTreeMap::new(),
+            },
+            key,
+        })
+    }
+
+    /// Load a keystore from `path` and unlock it with `passphrase`.
+    pub fn open(path: impl AsRef<Path>, passphrase: &str) -> Result<Self, KeystoreError> {
+        let data = std::fs::read(path)?;
+        Self::from_slice(&data, passphrase)
+    }
+
+    pub fn from_slice(data: &[u8], passphrase: &str) -> Result<Self, KeystoreError> {
+        let file: KeystoreFile =
+            serde_json::from_slice(data).map_err(|e| KeystoreError::Format(e.to_string()))?;
+        if file.version != KEYSTORE_VERSION {
+            return Err(KeystoreError::Version(file.version));
+        }
+        if file.kdf.alg != "argon2id" || file.cipher != "chacha20poly1305" {
+            return Err(KeystoreError::Format(format!(
+                "unsupported algorithms {}/{}",
+                file.kdf.alg, file.cipher
+            )));
+        }
+        let key = derive_key(passphrase, &file.kdf)?;
+        open_sealed(&key, CHECK_AAD, &file.check)?;
+        Ok(Self { file, key })
+    }
+
+    /// Write the keystore to `path`. Seeds stay encrypted on disk; the file
+    /// is readable by its owner only and replaced atomically.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), KeystoreError> {
+        let path = path.as_ref();
+        let data = serde_json::to_vec_pretty(&self.file)
+            .map_err(|e| KeystoreError::Format(e.to_string()))?;
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.tmp", ulid::Ulid::new()));
+        let tmp = path.with_file_name(name);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let written = options.open(&tmp).and_then(|mut f| {
+            f.write_all(&data)?;
+            f.sync_all()
+        });
+        if let Err(e) = written.and_then(|()| std::fs::rename(&tmp, path)) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.file.entries.keys().map(String::as_str)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.file.entries.contains_key(name)
+    }
+
+    /// Base64 public key of an entry; does not decrypt the seed.
+    pub fn public_key_base64(&self, name: &str) -> Result<&str, KeystoreError> {
+        self.file
+            .entries
+            .get(name)
+            .map(|e| e.public_key.as_str())
+            .ok_or_else(|| KeystoreError::NotFound(name.to_string()))
+    }
+
+    /// Seal an existing keypair under `name`.
+    pub fn insert(&mut self, name: &str, kp: &Keypair) -> Result<(), KeystoreError> {
+        if self.contains(name) {
+            return Err(KeystoreError::Exists(name.to_string()));
+        }
+        let seed = Zeroizing::new(kp.signing.to_bytes());
+        let sealed = seal(&self.key, name.as_bytes(), seed.as_ref())?;
+        let created = OffsetDateTime::now_utc()
+            .format(&time::format_description::well_known::Rfc3339)
+            .map_err(|e| KeystoreError::Format(e.to_string()))?;
+        self.file.entries.insert(
+            name.to_string(),
+            Entry { public_key: kp.public_key_base64(), created, sealed },
+        );
+        Ok(())
+    }
+
+    /// Generate a fresh keypair, seal it under `name` and return it.
+    pub fn generate(&mut self, name: &str) -> Result<Keypair, KeystoreError> {
+        let kp = Keypair::generate();
+        self.insert(name, &kp)?;
+        Ok(kp)
+    }
+
+    /// Decrypt the seed stored under `name`.
+    pub fn keypair(&self, name: &str) -> Result<Keypair, KeystoreError> {
+        let entry = self
+            .file
+            .entries
+            .get(name)
+            .ok_or_else(|| KeystoreError::NotFound(name.to_string()))?;
+        let mut plain = open_sealed(&self.key, name.as_bytes(), &entry.sealed)?;
+        let seed: Zeroizing<[u8; 32]> = Zeroizing::new(
+            plain
+                .as_slice()
+                .try_into()
+                .map_err(|_| KeystoreError::Format("seed length".into()))?,
+        );
+        plain.zeroize();
+        Ok(Keypair::from_seed(&seed))
+    }
+
+    /// Unlock an entry as a `SigningBackend` for `Signer::with_backend`.
+    pub fn backend(&self, name: &str) -> Result<KeystoreBackend, KeystoreError> {
+        Ok(KeystoreBackend { name: name.to_string(), kp: self.keypair(name)? })
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), KeystoreError> {
+        self.file
+            .entries
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| KeystoreError::NotFound(name.to_string()))
+    }
+}
+
+/// A keystore entry unlocked into memory.
+pub struct KeystoreBackend {
+    name: String,
+    kp: Keypair,
+}
+
+impl KeystoreBackend {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl SigningBackend for KeystoreBackend {
+    fn verifying_key(&self) -> VerifyingKey {
+        self.kp.verify
+    }
+
+    fn sign(&self, bytes: &[u8]) -> anyhow::Result<DalekSignature> {
+        self.kp.sign(bytes)
+    }
+}
+
+fn derive_key(passphrase: &str, kdf: &KdfParams) -> Result<Zeroizing<[u8; 32]>, KeystoreError> {
+    let salt = general_purpose::STANDARD
+        .decode(&kdf.salt)
+        .map_err(|e| KeystoreError::Format(e.to_string()))?;
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(32))
+        .map_err(|e| KeystoreError::Format(e.to_string()))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &salt, key.as_mut())
+        .map_err(|e| KeystoreError::Format(e.to_string()))?;
+    Ok(key)
+}
+
+fn seal(key: &[u8; 32], aad: &[u8], plain: &[u8]) -> Result<Sealed, KeystoreError> {
+    let mut nonce = [0u8; 12];
+    getrandom(&mut nonce).map_err(|e| KeystoreError::Format(e.to_string()))?;
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plain, aad })
+        .map_err(|_| KeystoreError::Format("encrypt".into()))?;
+    Ok(Sealed {
+        nonce: general_purpose::STANDARD.encode(nonce),
+        ciphertext: general_purpose::STANDARD.encode(ciphertext),
+    })
+}
+
+fn open_sealed(key: &[u8; 32], aad: &[u8], sealed: &Sealed) -> Result<Vec<u8>, KeystoreError> {
+    let nonce = general_purpose::STANDARD
+        .decode(&sealed.nonce)
+        .map_err(|e| KeystoreError::Format(e.to_string()))?;
+    let ciphertext = general_purpose::STANDARD
+        .decode(&sealed.ciphertext)
+        .map_err(|e| KeystoreError::Format(e.to_string()))?;
+    if nonce.len() != 12 {
+        return Err(KeystoreError::Format("nonce length".into()));
+    }
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad })
+        .map_err(|_| KeystoreError::Decrypt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::{Signer, Verifier};
+
+    #[test]
+    fn entries_survive_a_save_and_reopen() {
+        let path = std::env::temp_dir().join(format!("openi-keystore-{}.json", std::process::id()));
+        let mut store = Keystore::create("correct horse").unwrap();
+        let kp = store.generate("node").unwrap();
+        store.save(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        // Saving again replaces the file.
+        store.save(&path).unwrap();
+
+        let store = Keystore::open(&path, "correct horse").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(store.names().collect::<Vec<_>>(), vec!["node"]);
+        assert_eq!(store.public_key_base64("node").unwrap(), kp.public_key_base64());
+        let signer = Signer::with_backend(store.backend("node").unwrap());
+        let sig = signer.sign_bytes(b"payload").unwrap();
+        Verifier::from_base64(&kp.public_key_base64()).unwrap().verify_bytes(b"payload", &sig).unwrap();
+    }
+
+    #[test]
+    fn a_wrong_passphrase_is_refused() {
+        let mut store = Keystore::create("correct horse").unwrap();
+        store.generate("node").unwrap();
+        let data = serde_json::to_vec(&store.file).unwrap();
+        assert!(matches!(Keystore::from_slice(&data, "battery staple"), Err(KeystoreError::Decrypt)));
+        assert!(Keystore::from_slice(&data, "correct horse").is_ok());
+    }
+
+    #[test]
+    fn sealed_seeds_cannot_be_swapped_between_names() {
+        let mut store = Keystore::create("pw").unwrap();
+        store.generate("a").unwrap();
+        store.generate("b").unwrap();
+        let a = store.file.entries.remove("a").unwrap();
+        store.file.entries.insert("b".into(), a);
+        assert!(matches!(store.keypair("b"), Err(KeystoreError::Decrypt)));
+    }
+}
@@ -1,9 +1,37 @@
 pub mod envelope;
 pub mod signing;
+pub mod keystore;
 pub mod content;
 pub mod bus;
+pub mod replay;
+pub mod approval;
+pub mod trace;
+pub mod codec;
+pub mod transport;
+pub mod compression;
+pub mod blob;
+pub mod schema;
+pub mod typed;
+pub mod upcast;
+pub mod redact;
+pub mod dedup;
+pub mod seal;
 
-pub use envelope::{Envelope, Headers};
-pub use signing::{Keypair, PublicKey, Signature, Signer, Verifier};
-pub use content::ContentType;
-pub use crate::bus::{Bus, Subscription, GLOBAL_BUS};
\ No newline at end of file
+pub use envelope::{Countersignature, Envelope, Headers, ENVELOPE_VERSION};
+pub use signing::{Keypair, PublicKey, Signature, Signer, SigningBackend, Verifier};
+pub use keystore::{Keystore, KeystoreBackend, KeystoreError};
+pub use content::{Compatibility, ContentType, ContentTypeError, LogicalType, MimeType};
+pub use crate::bus::{Bus, DeliveryError, DeliveryFilter, Subscription, GLOBAL_BUS};
+pub use replay::{ReplayError, ReplayGuard};
+pub use approval::{ApprovalConfig, ApprovalError, ApprovalPolicy, ApprovalVerifier, SignerConfig};
+pub use trace::{TraceParent, TraceParentError};
+pub use codec::{Codec, CodecError};
+pub use transport::{FramedConnection, FramedReader, FramedWriter, Role, TransportError};
+pub use compression::{Compression, CompressionError, CompressionPolicy};
+pub use blob::{BlobError, BlobRef, BlobStore, ClaimCheckPolicy};
+pub use schema::{SchemaIssue, SchemaRegistry, SchemaViolation, SchemaViolationReport};
+pub use typed::{PayloadTypeError, TypeRegistry, TypedSubscription};
+pub use upcast::{Migration, UpcastError, Upcaster};
+pub use redact::{redacted, Redacted, RedactionError, RedactionPolicy, Redactor};
+pub use dedup::{Dedup, DedupConfig};
+pub use seal::{Cipher, EncryptionMode, Recipient, RequireSealed, SealError, SealKey, SealPolicy, SealPublicKey};
@@ -0,0 +1,286 @@
+use crate::Envelope;
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// What to hide before an envelope is logged: payload locations by JSON
+/// pointer (`*` matches any one key or index, `**` any depth), header keys, and regex detectors
+/// run over every remaining string. Detector hits are replaced in place
+/// with `[REDACTED:<name>]`; pointer and header hits lose the whole value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedactionPolicy {
+    pub pointers: Vec<String>,
+    pub headers: Vec<String>,
+    pub detectors: Vec<Detector>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Detector {
+    pub name: String,
+    pub pattern: String,
+}
+
+#[derive(Debug, Error)]
+pub enum RedactionError {
+    #[error("detector {name}: {msg}")]
+    Pattern { name: String, msg: String },
+    #[error("invalid JSON pointer {0:?}")]
+    Pointer(String),
+}
+
+pub const MASK: &str = "[REDACTED]";
+
+impl RedactionPolicy {
+    /// Detectors for common identifiers (SSN, MRN, email, phone) plus
+    /// the usual demographic payload fields.
+    pub fn phi() -> Self {
+        let detector = |name: &str, pattern: &str| Detector { name: name.into(), pattern: pattern.into() };
+        Self {
+            pointers: ["given", "family", "birthDate", "dob", "address", "telecom", "ssn", "mrn"]
+                .iter()
+                .map(|k| format!("/**/{}", k))
+                .collect(),
+            headers: vec!["authorization".into(), "x-patient-id".into()],
+            detectors: vec![
+                detector("ssn", r"\b\d{3}-\d{2}-\d{4}\b"),
+                detector("mrn", r"(?i)\bMRN[:#]?\s*[A-Z0-9-]{4,}\b"),
+                detector("email", r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}\b"),
+                detector("phone", r"\(?\b\d{3}\)?[-. ]\d{3}[-. ]\d{4}\b"),
+            ],
+        }
+    }
+}
+
+/// A compiled `RedactionPolicy`.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    pointers: Vec<Vec<String>>,
+    headers: Vec<String>,
+    detectors: Vec<(String, Regex)>,
+}
+
+impl Redactor {
+    pub fn new(policy: &RedactionPolicy) -> Result<Self, RedactionError> {
+        let pointers = policy
+            .pointers
+            .iter()
+            .map(|p| {
+                let rest = p.strip_prefix('/').ok_or_else(|| RedactionError::Pointer(p.clone()))?;
+                Ok(rest.split('/').map(|s| s.replace("~1", "/").replace("~0", "~")).collect())
+            })
+            .collect::<Result<_, RedactionError>>()?;
+        let detectors = policy
+            .detectors
+            .iter()
+            .map(|d| {
+                Regex::new(&d.pattern)
+                    .map(|re| (d.name.clone(), re))
+                    .map_err(|e| RedactionError::Pattern { name: d.name.clone(), msg: e.to_string() })
+            })
+            .collect::<Result<_, _>>()?;
+        let headers = policy.headers.iter().map(|h| h.to_ascii_lowercase()).collect();
+        Ok(Self { pointers, headers, detectors })
+    }
+
+    /// A copy of `env` safe to log. The signature is kept; it reveals nothing.
+    pub fn redact<T: Serialize>(&self, env: &Envelope<T>) -> Envelope<Value> {
+        let payload = serde_json::to_value(&env.payload).unwrap_or(Value::Null);
+        let mut out = env.with_payload(self.redact_value(payload));
+        for (k, v) in out.headers.iter_mut() {
+            if self.headers.contains(&k.to_ascii_lowercase()) {
+                *v = MASK.to_string();
+            } else {
+                *v = self.redact_str(v);
+            }
+        }
+        out
+    }
+
+    pub fn redact_value(&self, mut value: Value) -> Value {
+        for pointer in &self.pointers {
+            mask_pointer(&mut value, pointer);
+        }
+        self.scan(&mut value);
+        value
+    }
+
+    /// Run the detectors over free text (e.g. an error message).
+    pub fn redact_str(&self, s: &str) -> String {
+        let mut out = s.to_string();
+        for (name, re) in &self.detectors {
+            if re.is_match(&out) {
+                out = re.replace_all(&out, format!("[REDACTED:{}]", name).as_str()).into_owned();
+            }
+        }
+        out
+    }
+
+    /// Display adapter for `env`'s redacted JSON form.
+    pub fn view<T: Serialize>(&self, env: &Envelope<T>) -> Redacted {
+        Redacted(self.redact(env))
+    }
+
+    fn scan(&self, value: &mut Value) {
+        match value {
+            Value::String(s) if s != MASK => *s = self.redact_str(s),
+            Value::Array(items) => items.iter_mut().for_each(|v| self.scan(v)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.scan(v)),
+            _ => {}
+        }
+    }
+}
+
+/// `segments` may contain `*` (any one key or index) and `**` (any depth).
+fn mask_pointer(value: &mut Value, segments: &[String]) {
+    let Some((first, rest)) = segments.split_first() else {
+        *value = Value::String(MASK.into());
+        return;
+    };
+    if first == "**" {
+        mask_pointer(value, rest);
+        for child in children(value) {
+            mask_pointer(child, segments);
+        }
+        return;
+    }
+    match value {
+        Value::Object(map) if first == "*" => map.values_mut().for_each(|v| mask_pointer(v, rest)),
+        Value::Object(map) => {
+            if let Some(v) = map.get_mut(first.as_str()) {
+                mask_pointer(v, rest);
+            }
+        }
+        Value::Array(items) if first == "*" => items.iter_mut().for_each(|v| mask_pointer(v, rest)),
+        Value::Array(items) => {
+            if let Some(v) = first.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                mask_pointer(v, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn children(value: &mut Value) -> Vec<&mut Value> {
+    match value {
+        Value::Object(map) => map.values_mut().collect(),
+        Value::Array(items) => items.iter_mut().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Redacted envelope that formats as compact JSON.
+pub struct Redacted(pub Envelope<Value>);
+
+impl fmt::Display for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match serde_json::to_string(&self.0) {
+            Ok(s) => f.write_str(&s),
+            Err(_) => write!(f, "<envelope {}>", self.0.id),
+        }
+    }
+}
+
+static DEFAULT: once_cell::sync::Lazy<RwLock<Arc<Redactor>>> = once_cell::sync::Lazy::new(|| {
+    RwLock::new(Arc::new(Redactor::new(&RedactionPolicy::phi()).expect("built-in PHI policy compiles")))
+});
+
+/// Redactor used by all logging in the fabric, kernel and SDK.
+pub fn default_redactor() -> Arc<Redactor> {
+    DEFAULT.read().clone()
+}
+
+/// Replace the process-wide redactor (e.g. with a site policy).
+pub fn set_default_redactor(redactor: Redactor) {
+    *DEFAULT.write() = Arc::new(redactor);
+}
+
+/// `env` redacted with the default redactor, ready for `{}` in a log line.
+pub fn redacted<T: Serialize>(env: &Envelope<T>) -> Redacted {
+    default_redactor().view(env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ContentType;
+    use serde_json::json;
+
+    fn env(payload: Value) -> Envelope<Value> {
+        Envelope::new("agent://local/a", "topic://t", ContentType::json(), payload)
+    }
+
+    #[test]
+    fn the_phi_policy_masks_demographics_headers_and_identifiers() {
+        let redactor = Redactor::new(&RedactionPolicy::phi()).unwrap();
+        let sent = env(json!({
+            "patient": { "name": [{ "given": ["Ada"], "family": "Lovelace" }], "birthDate": "1815-12-10" },
+            "note": "SSN 123-45-6789, reach me at ada@example.org or (555) 123-4567",
+            "count": 3,
+        }))
+        .with_header("Authorization", "Bearer secret")
+        .with_header("x-note", "MRN: AB-12345");
+        let out = redactor.redact(&sent);
+        assert_eq!(out.payload["patient"]["name"][0], json!({ "given": MASK, "family": MASK }));
+        assert_eq!(out.payload["patient"]["birthDate"], json!(MASK));
+        assert_eq!(
+            out.payload["note"],
+            json!("SSN [REDACTED:ssn], reach me at [REDACTED:email] or [REDACTED:phone]")
+        );
+        assert_eq!(out.payload["count"], json!(3));
+        assert_eq!(out.headers["Authorization"], MASK);
+        assert_eq!(out.headers["x-note"], "[REDACTED:mrn]");
+        assert_eq!(out.id, sent.id);
+        assert!(!redactor.view(&sent).to_string().contains("Lovelace"));
+    }
+
+    #[test]
+    fn wildcards_match_one_level_and_double_wildcards_any_depth() {
+        let policy =
+            RedactionPolicy { pointers: vec!["/items/*/secret".into(), "/**/token".into()], ..Default::default() };
+        let redactor = Redactor::new(&policy).unwrap();
+        let out = redactor.redact_value(json!({
+            "items": [{ "secret": 1, "keep": 2 }, { "nested": { "secret": 3 } }],
+            "token": "a",
+            "deep": { "deeper": [{ "token": "b" }] },
+        }));
+        assert_eq!(
+            out,
+            json!({
+                "items": [{ "secret": MASK, "keep": 2 }, { "nested": { "secret": 3 } }],
+                "token": MASK,
+                "deep": { "deeper": [{ "token": MASK }] },
+            })
+        );
+        let escaped = RedactionPolicy { pointers: vec!["/a~1b/c~0d".into()], ..Default::default() };
+        let out = Redactor::new(&escaped).unwrap().redact_value(json!({ "a/b": { "c~d": 1, "e": 2 } }));
+        assert_eq!(out, json!({ "a/b": { "c~d": MASK, "e": 2 } }));
+    }
+
+    #[test]
+    fn bad_pointers_and_patterns_are_refused() {
+        let pointer = RedactionPolicy { pointers: vec!["no-slash".into()], ..Default::default() };
+        assert!(matches!(Redactor::new(&pointer), Err(RedactionError::Pointer(_))));
+        let pattern = RedactionPolicy {
+            detectors: vec![Detector { name: "broken".into(), pattern: "(".into() }],
+            ..Default::default()
+        };
+        assert!(matches!(Redactor::new(&pattern), Err(RedactionError::Pattern { name, .. }) if name == "broken"));
+    }
+
+    #[test]
+    fn the_default_redactor_can_be_replaced() {
+        let mut policy = RedactionPolicy::phi();
+        policy.detectors.push(Detector { name: "ward".into(), pattern: r"\bWARD-\d+\b".into() });
+        set_default_redactor(Redactor::new(&policy).unwrap());
+        let line = redacted(&env(json!({ "text": "moved to WARD-7, ssn 123-45-6789" }))).to_string();
+        assert!(line.contains("moved to [REDACTED:ward], ssn [REDACTED:ssn]"), "{}", line);
+        assert_eq!(default_redactor().redact_str("WARD-7"), "[REDACTED:ward]");
+        set_default_redactor(Redactor::new(&RedactionPolicy::phi()).unwrap());
+        assert_eq!(default_redactor().redact_str("WARD-7"), "WARD-7");
+    }
+}
@@ -0,0 +1,200 @@
+use crate::bus::{DeliveryError, DeliveryFilter};
+use crate::Envelope;
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use thiserror::Error;
+use time::OffsetDateTime;
+use ulid::Ulid;
+
+/// Verifier-side replay protection for signed envelopes.
+///
+/// An envelope is accepted once: its `ts` (and the timestamp embedded in its
+/// ULID `id`) must fall within `skew` of the local clock, and its `id` must
+/// not have been seen before. Seen IDs are kept in ULID order so anything
+/// older than the skew window can be pruned, since `ts` checks reject it anyway.
+pub struct ReplayGuard {
+    skew: Duration,
+    capacity: usize,
+    state: Mutex<SeenIds>,
+}
+
+#[derive(Default)]
+struct SeenIds {
+    ids: BTreeSet<Ulid>,
+    /// Latest millisecond evicted for capacity rather than age. IDs minted at
+    /// or before it are rejected, since we can no longer tell if they were seen.
+    floor_ms: Option<u64>,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    #[error("envelope id {0:?} is not a ULID")]
+    InvalidId(String),
+    #[error("envelope ts {0:?} is not RFC3339")]
+    InvalidTs(String),
+    #[error("envelope {id} is outside the {skew_ms}ms clock-skew window")]
+    Skew { id: String, skew_ms: u128 },
+    #[error("envelope {0} was already delivered")]
+    Duplicate(String),
+}
+
+impl ReplayGuard {
+    pub fn new(skew: Duration, capacity: usize) -> Self {
+        Self { skew, capacity: capacity.max(1), state: Mutex::new(SeenIds::default()) }
+    }
+
+    /// Check an envelope and record its id. Returns an error without recording
+    /// anything if the envelope is stale, from the future, or a duplicate.
+    pub fn check<T>(&self, env: &Envelope<T>) -> Result<(), ReplayError> {
+        self.check_at(env, OffsetDateTime::now_utc())
+    }
+
+    pub fn check_at<T>(&self, env: &Envelope<T>, now: OffsetDateTime) -> Result<(), ReplayError> {
+        self.admit(env, now, true)
+    }
+
+    /// Like `check`, without recording the id.
+    pub fn peek<T>(&self, env: &Envelope<T>) -> Result<(), ReplayError> {
+        self.admit(env, OffsetDateTime::now_utc(), false)
+    }
+
+    fn admit<T>(&self, env: &Envelope<T>, now: OffsetDateTime, record: bool) -> Result<(), ReplayError> {
+        let id: Ulid = env.id.parse().map_err(|_| ReplayError::InvalidId(env.id.clone()))?;
+        let ts = OffsetDateTime::parse(&env.ts, &time::format_description::well_known::Rfc3339)
+            .map_err(|_| ReplayError::InvalidTs(env.ts.clone()))?;
+
+        let now_ms = now.unix_timestamp_nanos() / 1_000_000;
+        let skew_ms = self.skew.as_millis() as i128;
+        let ts_ms = ts.unix_timestamp_nanos() / 1_000_000;
+        let id_ms = id.timestamp_ms() as i128;
+        if (now_ms - ts_ms).abs() > skew_ms || (now_ms - id_ms).abs() > skew_ms {
+            return Err(ReplayError::Skew { id: env.id.clone(), skew_ms: self.skew.as_millis() });
+        }
+
+        let mut seen = self.state.lock();
+        let horizon = (now_ms - skew_ms).max(0) as u64;
+        seen.prune(horizon);
+        if seen.floor_ms.is_some_and(|f| id.timestamp_ms() <= f) || seen.ids.contains(&id) {
+            return Err(ReplayError::Duplicate(env.id.clone()));
+        }
+        if !record {
+            return Ok(());
+        }
+        seen.ids.insert(id);
+        if seen.ids.len() > self.capacity {
+            seen.evict_oldest_ms();
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl SeenIds {
+    fn prune(&mut self, horizon_ms: u64) {
+        let cutoff = Ulid::from_parts(horizon_ms, 0);
+        self.ids = self.ids.split_off(&cutoff);
+        if self.floor_ms.is_some_and(|f| f < horizon_ms) {
+            self.floor_ms = None;
+        }
+    }
+
+    /// ULIDs are only ordered to the millisecond, so evict whole milliseconds.
+    fn evict_oldest_ms(&mut self) {
+        if let Some(oldest) = self.ids.first().map(Ulid::timestamp_ms) {
+            self.ids = self.ids.split_off(&Ulid::from_parts(oldest + 1, 0));
+            self.floor_ms = Some(self.floor_ms.map_or(oldest, |f| f.max(oldest)));
+        }
+    }
+}
+
+impl DeliveryFilter for ReplayGuard {
+    fn name(&self) -> &'static str {
+        "replay_guard"
+    }
+
+    fn check(&self, _topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        self.peek(env).map_err(DeliveryError::from)
+    }
+
+    /// Ids are recorded only once every filter has accepted the envelope,
+    /// so a corrected retry of a rejected one is not a replay.
+    fn commit(&self, _topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        ReplayGuard::check(self, env).map_err(DeliveryError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bus, ContentType};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn env() -> Envelope<Value> {
+        Envelope::new("agent://local/a", "topic://t", ContentType::mime("application", "json"), json!(1))
+    }
+
+    #[test]
+    fn an_id_is_accepted_once() {
+        let guard = ReplayGuard::new(Duration::from_secs(30), 16);
+        let e = env();
+        guard.peek(&e).unwrap();
+        guard.check(&e).unwrap();
+        assert_eq!(guard.check(&e), Err(ReplayError::Duplicate(e.id.clone())));
+        assert_eq!(guard.peek(&e), Err(ReplayError::Duplicate(e.id.clone())));
+        guard.check(&env()).unwrap();
+        assert_eq!(guard.len(), 2);
+    }
+
+    #[test]
+    fn envelopes_outside_the_skew_window_are_refused() {
+        let guard = ReplayGuard::new(Duration::from_secs(30), 16);
+        let e = env();
+        let now = OffsetDateTime::now_utc();
+        assert!(matches!(guard.check_at(&e, now + Duration::from_secs(60)), Err(ReplayError::Skew { .. })));
+        assert!(matches!(guard.check_at(&e, now - Duration::from_secs(60)), Err(ReplayError::Skew { .. })));
+        assert!(guard.is_empty());
+
+        let mut stale = env();
+        stale.ts = "2020-01-01T00:00:00Z".into();
+        assert!(matches!(guard.check(&stale), Err(ReplayError::Skew { .. })));
+        let mut bad = env();
+        bad.id = "not-a-ulid".into();
+        assert_eq!(guard.check(&bad), Err(ReplayError::InvalidId("not-a-ulid".into())));
+    }
+
+    struct RejectOnce(Mutex<bool>);
+
+    impl DeliveryFilter for RejectOnce {
+        fn name(&self) -> &'static str {
+            "reject_once"
+        }
+
+        fn check(&self, _topic: &str, _env: &Envelope<Value>) -> Result<(), DeliveryError> {
+            match std::mem::replace(&mut *self.0.lock(), false) {
+                true => Err(DeliveryError::Denied("first try".into())),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn an_envelope_rejected_by_a_later_filter_can_be_retried() {
+        let bus = Bus::new();
+        bus.add_filter(Arc::new(ReplayGuard::new(Duration::from_secs(30), 16)));
+        bus.add_filter(Arc::new(RejectOnce(Mutex::new(true))));
+        let e = env();
+        assert!(matches!(bus.publish("topic://t", e.clone()).await, Err(DeliveryError::Denied(_))));
+        bus.publish("topic://t", e.clone()).await.unwrap();
+        assert!(matches!(bus.publish("topic://t", e).await, Err(DeliveryError::Replay(ReplayError::Duplicate(_)))));
+    }
+}
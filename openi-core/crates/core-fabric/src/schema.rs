@@ -0,0 +1,232 @@
+use crate::bus::{DeliveryError, DeliveryFilter};
+use crate::content::ContentType;
+use crate::{blob, seal, Envelope};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// File suffix for payload schemas in the manifests tree.
+pub const SCHEMA_SUFFIX: &str = ".schema.json";
+/// Schema keyword naming the content type, for types (like MIME types)
+/// that can't be spelled as a file name.
+pub const CTYPE_KEYWORD: &str = "x-openi-ctype";
+
+/// JSON Schemas for payloads, keyed by content type.
+///
+/// Schemas are loaded from `<ctype>.schema.json` files anywhere under a
+/// directory, e.g. `manifests/schemas/ddl.discovered.v1.schema.json`.
+/// Content types without a schema are not checked.
+#[derive(Default, Clone)]
+pub struct SchemaRegistry {
+    schemas: HashMap<ContentType, Arc<jsonschema::Validator>>,
+}
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("schema io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{path}: {msg}")]
+    File { path: PathBuf, msg: String },
+    #[error("invalid schema for {ctype}: {msg}")]
+    Schema { ctype: ContentType, msg: String },
+}
+
+/// One failed keyword, located by JSON pointers into the payload and schema.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaIssue {
+    pub instance_path: String,
+    pub schema_path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Error, Clone, Serialize, Deserialize)]
+#[error("payload does not match schema for {ctype} ({} issue(s), first at {:?})", issues.len(), issues.first().map(|i| i.instance_path.as_str()).unwrap_or(""))]
+pub struct SchemaViolation {
+    pub ctype: ContentType,
+    pub issues: Vec<SchemaIssue>,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, ctype: ContentType, schema: &Value) -> Result<(), SchemaError> {
+        let validator = jsonschema::validator_for(schema)
+            .map_err(|e| SchemaError::Schema { ctype: ctype.clone(), msg: e.to_string() })?;
+        self.schemas.insert(ctype, Arc::new(validator));
+        Ok(())
+    }
+
+    /// Load every `*.schema.json` under `dir`, recursively.
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Self, SchemaError> {
+        let mut reg = Self::new();
+        reg.load_dir_into(dir.as_ref())?;
+        Ok(reg)
+    }
+
+    fn load_dir_into(&mut self, dir: &Path) -> Result<(), SchemaError> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                self.load_dir_into(&path)?;
+                continue;
+            }
+            let Some(stem) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(SCHEMA_SUFFIX)) else {
+                continue;
+            };
+            let file_err = |msg: String| SchemaError::File { path: path.clone(), msg };
+            let schema: Value = serde_json::from_slice(&std::fs::read(&path)?).map_err(|e| file_err(e.to_string()))?;
+            let ctype = schema.get(CTYPE_KEYWORD).and_then(Value::as_str).unwrap_or(stem);
+            let ctype: ContentType = ctype.parse().map_err(|e: crate::content::ContentTypeError| file_err(e.to_string()))?;
+            self.insert(ctype, &schema)?;
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, ctype: &ContentType) -> bool {
+        self.schemas.contains_key(ctype)
+    }
+
+    pub fn ctypes(&self) -> impl Iterator<Item = &ContentType> {
+        self.schemas.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.schemas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Check `payload` against the schema for `ctype`, collecting every issue.
+    pub fn validate(&self, ctype: &ContentType, payload: &Value) -> Result<(), SchemaViolation> {
+        let Some(validator) = self.schemas.get(ctype) else {
+            return Ok(());
+        };
+        let issues: Vec<SchemaIssue> = validator
+            .iter_errors(payload)
+            .map(|e| SchemaIssue {
+                instance_path: e.instance_path.to_string(),
+                schema_path: e.schema_path.to_string(),
+                message: e.to_string(),
+            })
+            .collect();
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(SchemaViolation { ctype: ctype.clone(), issues })
+        }
+    }
+}
+
+/// Validates on publish; the bus reports violations to the sender's
+/// errors topic (see `Bus::set_errors_topic`). Well-formed sealed and
+/// claim-checked payloads are passed through: the bus only sees ciphertext
+/// or a `blob://` reference, and subscribers validate once they have opened
+/// or fetched it. A header alone does not exempt a payload.
+impl DeliveryFilter for SchemaRegistry {
+    fn name(&self) -> &'static str {
+        "schema"
+    }
+
+    fn check(&self, _topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        if seal::check_sealed(env).is_ok() || matches!(blob::reference(env), Ok(Some(_))) {
+            return Ok(());
+        }
+        self.validate(&env.ctype, &env.payload).map_err(DeliveryError::from)
+    }
+}
+
+/// Payload of the report sent to an agent's errors topic.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaViolationReport {
+    pub envelope_id: String,
+    pub src: String,
+    pub topic: String,
+    #[serde(flatten)]
+    pub violation: SchemaViolation,
+}
+
+impl SchemaViolationReport {
+    pub fn ctype() -> ContentType {
+        ContentType::logical("fabric.schema", "violation", 1)
+    }
+
+    /// Report `env` (published to `topic`) as a child envelope addressed to `errors_topic`.
+    pub fn envelope(reporter: &str, errors_topic: &str, topic: &str, env: &Envelope<Value>, violation: SchemaViolation) -> Envelope<Value> {
+        let report = SchemaViolationReport {
+            envelope_id: env.id.clone(),
+            src: env.src.clone(),
+            topic: topic.to_string(),
+            violation,
+        };
+        let payload = serde_json::to_value(report).expect("serialize report");
+        Envelope::new(reporter, errors_topic, Self::ctype(), payload).child_of(env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::header;
+    use crate::Bus;
+    use serde_json::json;
+
+    fn registry() -> SchemaRegistry {
+        let mut reg = SchemaRegistry::new();
+        let schema = json!({ "type": "object", "required": ["table"] });
+        reg.insert(ContentType::logical("ddl", "discovered", 1), &schema).unwrap();
+        reg
+    }
+
+    fn env(payload: Value) -> Envelope<Value> {
+        Envelope::new("agent://local/a", "topic://ddl", ContentType::logical("ddl", "discovered", 1), payload)
+    }
+
+    #[tokio::test]
+    async fn the_bus_checks_plain_payloads_only() {
+        let bus = Bus::new();
+        bus.add_filter(Arc::new(registry()));
+        bus.publish("topic://ddl", env(json!({ "table": "t" }))).await.unwrap();
+        assert!(matches!(bus.publish("topic://ddl", env(json!({}))).await, Err(DeliveryError::Schema(_))));
+
+        // Opaque until the subscriber opens or fetches it.
+        let policy = seal::SealPolicy {
+            cipher: seal::Cipher::Aes256Gcm,
+            recipients: vec![seal::Recipient { id: "agent://local/b".into(), key: seal::SealKey::generate().public() }],
+        };
+        bus.publish("topic://ddl", policy.seal(&env(json!({}))).unwrap()).await.unwrap();
+        let blob = blob::BlobRef::of(b"{}");
+        let mut checked = env(json!(blob.to_string()));
+        checked.headers.insert(header::BLOB_DIGEST.into(), format!("sha256:{}", blob.hex()));
+        bus.publish("topic://ddl", checked).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn headers_alone_do_not_skip_validation() {
+        let bus = Bus::new();
+        bus.add_filter(Arc::new(registry()));
+        let forged = env(json!({})).with_header(header::ENC, "x25519+aes256gcm");
+        assert!(matches!(bus.publish("topic://ddl", forged).await, Err(DeliveryError::Schema(_))));
+        let forged = env(json!("Y2lwaGVydGV4dA==")).with_header(header::ENC, "x25519+aes256gcm");
+        assert!(matches!(bus.publish("topic://ddl", forged).await, Err(DeliveryError::Schema(_))));
+
+        let forged = env(json!({})).with_header(header::BLOB_DIGEST, "sha256:00");
+        assert!(matches!(bus.publish("topic://ddl", forged).await, Err(DeliveryError::Schema(_))));
+        let blob = blob::BlobRef::of(b"{}");
+        let forged = env(json!(blob.to_string())).with_header(header::BLOB_DIGEST, "sha256:00");
+        assert!(matches!(bus.publish("topic://ddl", forged).await, Err(DeliveryError::Schema(_))));
+    }
+
+    #[test]
+    fn schemas_load_from_the_manifests_tree() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../manifests/schemas");
+        let reg = SchemaRegistry::load_dir(dir).unwrap();
+        assert!(reg.contains(&"ddl.discovered.v1".parse().unwrap()));
+    }
+}
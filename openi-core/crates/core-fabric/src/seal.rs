@@ -0,0 +1,453 @@
+use crate::bus::{matches, DeliveryError, DeliveryFilter};
+use crate::envelope::header;
+use crate::Envelope;
+use aes_gcm::Aes256Gcm;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+use x25519_dalek::{PublicKey as X25519Public, StaticSecret};
+use zeroize::Zeroizing;
+
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const WRAP_INFO: &[u8] = b"openi-core seal v1 key wrap";
+
+/// AEAD used for the payload itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+/// The blueprint's `security.encryption` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionMode {
+    Aes256,
+    Rsa2048,
+    None,
+}
+
+#[derive(Debug, Error)]
+pub enum SealError {
+    #[error("unknown cipher {0:?}")]
+    UnknownCipher(String),
+    #[error("unknown encryption mode {0:?}")]
+    UnknownMode(String),
+    #[error("encryption mode {0} is not supported for sealing; use aes256")]
+    Unsupported(&'static str),
+    #[error("no recipients to seal for")]
+    NoRecipients,
+    #[error("envelope is not sealed for {0}")]
+    NotRecipient(String),
+    #[error("malformed sealed envelope: {0}")]
+    Malformed(String),
+    #[error("invalid key: {0}")]
+    Key(String),
+    #[error("decryption failed")]
+    Decrypt,
+    #[error("encryption failed")]
+    Encrypt,
+    #[error("topic {0} requires sealed payloads")]
+    Required(String),
+}
+
+impl Cipher {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cipher::Aes256Gcm => "aes256gcm",
+            Cipher::ChaCha20Poly1305 => "chacha20poly1305",
+        }
+    }
+
+    fn seal(&self, key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>, SealError> {
+        let payload = Payload { msg, aad };
+        match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).encrypt(nonce.into(), payload),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into()).encrypt(nonce.into(), payload),
+        }
+        .map_err(|_| SealError::Encrypt)
+    }
+
+    fn open(&self, key: &[u8; KEY_LEN], nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>, SealError> {
+        if nonce.len() != NONCE_LEN {
+            return Err(SealError::Malformed("bad nonce length".into()));
+        }
+        let payload = Payload { msg, aad };
+        match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(nonce.into(), payload),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), payload),
+        }
+        .map_err(|_| SealError::Decrypt)
+    }
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = SealError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "aes256gcm" | "aes256-gcm" => Ok(Cipher::Aes256Gcm),
+            "chacha20poly1305" => Ok(Cipher::ChaCha20Poly1305),
+            other => Err(SealError::UnknownCipher(other.to_string())),
+        }
+    }
+}
+
+impl EncryptionMode {
+    /// Payload cipher for this mode, or `None` when sealing is off.
+    /// `rsa2048` has no counterpart in this scheme and is rejected rather
+    /// than silently swapped for something else.
+    pub fn cipher(&self) -> Result<Option<Cipher>, SealError> {
+        match self {
+            EncryptionMode::Aes256 => Ok(Some(Cipher::Aes256Gcm)),
+            EncryptionMode::Rsa2048 => Err(SealError::Unsupported("rsa2048")),
+            EncryptionMode::None => Ok(None),
+        }
+    }
+}
+
+impl FromStr for EncryptionMode {
+    type Err = SealError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "aes256" => Ok(EncryptionMode::Aes256),
+            "rsa2048" => Ok(EncryptionMode::Rsa2048),
+            "none" | "" => Ok(EncryptionMode::None),
+            other => Err(SealError::UnknownMode(other.to_string())),
+        }
+    }
+}
+
+/// An agent's X25519 key for opening sealed payloads.
+pub struct SealKey {
+    secret: StaticSecret,
+}
+
+impl SealKey {
+    pub fn generate() -> Self {
+        let mut bytes = Zeroizing::new([0u8; KEY_LEN]);
+        getrandom::getrandom(bytes.as_mut()).expect("system randomness");
+        Self::from_bytes(*bytes)
+    }
+
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self { secret: StaticSecret::from(bytes) }
+    }
+
+    pub fn public(&self) -> SealPublicKey {
+        SealPublicKey(X25519Public::from(&self.secret))
+    }
+}
+
+/// Public half of a `SealKey`, shared as base64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SealPublicKey(X25519Public);
+
+impl SealPublicKey {
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(self.0.as_bytes())
+    }
+
+    pub fn from_base64(s: &str) -> Result<Self, SealError> {
+        let bytes = STANDARD.decode(s).map_err(|e| SealError::Key(e.to_string()))?;
+        let bytes: [u8; KEY_LEN] = bytes.try_into().map_err(|_| SealError::Key("expected 32 bytes".into()))?;
+        Ok(Self(X25519Public::from(bytes)))
+    }
+}
+
+/// Someone allowed to open a sealed payload: an agent id and its key.
+#[derive(Debug, Clone)]
+pub struct Recipient {
+    pub id: String,
+    pub key: SealPublicKey,
+}
+
+/// Content key wrapped for one recipient, as carried in `enc-recipients`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WrappedKey {
+    kid: String,
+    /// base64(nonce || wrapped content key)
+    wk: String,
+}
+
+/// Who a sealed envelope is for and how to seal it.
+#[derive(Debug, Clone)]
+pub struct SealPolicy {
+    pub cipher: Cipher,
+    pub recipients: Vec<Recipient>,
+}
+
+impl SealPolicy {
+    /// Encrypt `env`'s payload under a fresh content key, wrapped for each
+    /// recipient via X25519 with a per-envelope ephemeral key. The sealed
+    /// payload is a base64 string; the AEAD binds it to the envelope's id,
+    /// source, destination, content type and `scopes` header. Seal before
+    /// signing so `sig` covers the ciphertext.
+    pub fn seal<T: Serialize>(&self, env: &Envelope<T>) -> Result<Envelope<Value>, SealError> {
+        if self.recipients.is_empty() {
+            return Err(SealError::NoRecipients);
+        }
+        let plaintext = Zeroizing::new(serde_json::to_vec(&env.payload).map_err(|e| SealError::Malformed(e.to_string()))?);
+        let cek = Zeroizing::new(random::<KEY_LEN>());
+        let nonce = random::<NONCE_LEN>();
+        let aad = aad(env);
+        let mut body = nonce.to_vec();
+        body.extend(self.cipher.seal(&cek, &nonce, &plaintext, &aad)?);
+
+        let ephemeral = StaticSecret::from(random::<KEY_LEN>());
+        let epk = X25519Public::from(&ephemeral);
+        let shared: Vec<_> = self.recipients.iter().map(|r| (r, ephemeral.diffie_hellman(&r.key.0))).collect();
+        let mut wrapped = Vec::with_capacity(shared.len());
+        for (r, secret) in shared {
+            let kek = kek(secret.as_bytes(), &epk, &r.key.0, &r.id)?;
+            let nonce = random::<NONCE_LEN>();
+            let mut wk = nonce.to_vec();
+            wk.extend(self.cipher.seal(&kek, &nonce, cek.as_ref(), &aad)?);
+            wrapped.push(WrappedKey { kid: r.id.clone(), wk: STANDARD.encode(wk) });
+        }
+
+        let mut out = env.with_payload(Value::String(STANDARD.encode(body)));
+        out.headers.insert(header::ENC.into(), format!("x25519+{}", self.cipher));
+        out.headers.insert(header::ENC_EPK.into(), STANDARD.encode(epk.as_bytes()));
+        out.headers.insert(
+            header::ENC_RECIPIENTS.into(),
+            serde_json::to_string(&wrapped).map_err(|e| SealError::Malformed(e.to_string()))?,
+        );
+        Ok(out)
+    }
+}
+
+/// Whether `env` claims to be sealed. Use `check_sealed` where cleartext
+/// must not get through.
+pub fn is_sealed<T>(env: &Envelope<T>) -> bool {
+    env.headers.contains_key(header::ENC)
+}
+
+/// Check that `env` is structurally a sealed envelope: a known algorithm,
+/// an ephemeral key, at least one wrapped key and a base64 ciphertext
+/// payload. Only recipients can tell whether it opens.
+pub fn check_sealed(env: &Envelope<Value>) -> Result<(), SealError> {
+    let alg = env.headers.get(header::ENC).ok_or_else(|| SealError::Malformed("missing enc".into()))?;
+    alg.strip_prefix("x25519+")
+        .ok_or_else(|| SealError::Malformed(format!("unsupported key agreement in {:?}", alg)))?
+        .parse::<Cipher>()?;
+    let epk = env.headers.get(header::ENC_EPK).ok_or_else(|| SealError::Malformed("missing enc-epk".into()))?;
+    SealPublicKey::from_base64(epk)?;
+    if wrapped_keys(env)?.is_empty() {
+        return Err(SealError::Malformed("no enc-recipients".into()));
+    }
+    let Value::String(body) = &env.payload else {
+        return Err(SealError::Malformed("payload is not a sealed body".into()));
+    };
+    let body = STANDARD.decode(body).map_err(|e| SealError::Malformed(e.to_string()))?;
+    // Nonce plus at least the AEAD tag.
+    if body.len() < NONCE_LEN + 16 {
+        return Err(SealError::Malformed("sealed body is too short".into()));
+    }
+    Ok(())
+}
+
+/// Recipient ids a sealed envelope was wrapped for.
+pub fn recipients<T>(env: &Envelope<T>) -> Vec<String> {
+    wrapped_keys(env).map(|w| w.into_iter().map(|k| k.kid).collect()).unwrap_or_default()
+}
+
+/// Decrypt a sealed payload as recipient `id`. Unsealed envelopes are
+/// returned untouched. Verify signatures before opening.
+pub fn open(mut env: Envelope<Value>, id: &str, key: &SealKey) -> Result<Envelope<Value>, SealError> {
+    let Some(alg) = env.headers.get(header::ENC) else {
+        return Ok(env);
+    };
+    let cipher: Cipher = alg
+        .strip_prefix("x25519+")
+        .ok_or_else(|| SealError::Malformed(format!("unsupported key agreement in {:?}", alg)))?
+        .parse()?;
+    let epk = env.headers.get(header::ENC_EPK).ok_or_else(|| SealError::Malformed("missing enc-epk".into()))?;
+    let epk = SealPublicKey::from_base64(epk)?.0;
+    let wrapped = wrapped_keys(&env)?
+        .into_iter()
+        .find(|w| w.kid == id)
+        .ok_or_else(|| SealError::NotRecipient(id.to_string()))?;
+    let aad = aad(&env);
+
+    let shared = key.secret.diffie_hellman(&epk);
+    let kek = kek(shared.as_bytes(), &epk, &key.public().0, id)?;
+    let wk = STANDARD.decode(&wrapped.wk).map_err(|e| SealError::Malformed(e.to_string()))?;
+    let (nonce, ct) = wk.split_at(NONCE_LEN.min(wk.len()));
+    let cek = Zeroizing::new(cipher.open(&kek, nonce, ct, &aad)?);
+    let cek: &[u8; KEY_LEN] = cek.as_slice().try_into().map_err(|_| SealError::Malformed("bad content key".into()))?;
+
+    let Value::String(body) = &env.payload else {
+        return Err(SealError::Malformed("expected base64 payload".into()));
+    };
+    let body = STANDARD.decode(body).map_err(|e| SealError::Malformed(e.to_string()))?;
+    let (nonce, ct) = body.split_at(NONCE_LEN.min(body.len()));
+    let plaintext = Zeroizing::new(cipher.open(cek, nonce, ct, &aad)?);
+    env.payload = serde_json::from_slice(&plaintext).map_err(|e| SealError::Malformed(e.to_string()))?;
+    for h in [header::ENC, header::ENC_EPK, header::ENC_RECIPIENTS] {
+        env.headers.remove(h);
+    }
+    Ok(env)
+}
+
+fn wrapped_keys<T>(env: &Envelope<T>) -> Result<Vec<WrappedKey>, SealError> {
+    let raw = env
+        .headers
+        .get(header::ENC_RECIPIENTS)
+        .ok_or_else(|| SealError::Malformed("missing enc-recipients".into()))?;
+    serde_json::from_str(raw).map_err(|e| SealError::Malformed(e.to_string()))
+}
+
+fn aad<T>(env: &Envelope<T>) -> Vec<u8> {
+    let scopes = env.headers.get(header::SCOPES).map(String::as_str).unwrap_or_default();
+    format!("{}\n{}\n{}\n{}\n{}", env.id, env.src, env.dest, env.ctype, scopes).into_bytes()
+}
+
+fn kek(shared: &[u8], epk: &X25519Public, rpk: &X25519Public, kid: &str) -> Result<[u8; KEY_LEN], SealError> {
+    if shared.iter().all(|b| *b == 0) {
+        return Err(SealError::Key("low-order public key".into()));
+    }
+    let mut salt = epk.as_bytes().to_vec();
+    salt.extend_from_slice(rpk.as_bytes());
+    let mut info = WRAP_INFO.to_vec();
+    info.extend_from_slice(kid.as_bytes());
+    let mut out = [0u8; KEY_LEN];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(&info, &mut out)
+        .map_err(|e| SealError::Key(e.to_string()))?;
+    Ok(out)
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut out = [0u8; N];
+    getrandom::getrandom(&mut out).expect("system randomness");
+    out
+}
+
+/// Rejects cleartext payloads on topics that must be sealed.
+#[derive(Debug, Default, Clone)]
+pub struct RequireSealed {
+    patterns: Vec<String>,
+}
+
+impl RequireSealed {
+    pub fn new(patterns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self { patterns: patterns.into_iter().map(Into::into).collect() }
+    }
+
+    pub fn applies_to(&self, topic: &str) -> bool {
+        self.patterns.iter().any(|p| matches(p, topic))
+    }
+}
+
+impl DeliveryFilter for RequireSealed {
+    fn name(&self) -> &'static str {
+        "require-sealed"
+    }
+
+    fn check(&self, topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        if !self.applies_to(topic) {
+            return Ok(());
+        }
+        match check_sealed(env) {
+            Ok(()) => Ok(()),
+            Err(_) if !is_sealed(env) => Err(SealError::Required(topic.to_string()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ContentType;
+    use serde_json::json;
+
+    fn sealed(key: &SealKey) -> Envelope<Value> {
+        let mut env = Envelope::new("agent://local/a", "topic://t", ContentType::mime("application", "json"), json!({"x": 1}));
+        env.headers.insert(header::SCOPES.into(), "phi:read".into());
+        let policy = SealPolicy {
+            cipher: Cipher::Aes256Gcm,
+            recipients: vec![Recipient { id: "agent://local/b".into(), key: key.public() }],
+        };
+        policy.seal(&env).unwrap()
+    }
+
+    #[test]
+    fn a_recipient_opens_the_payload() {
+        let key = SealKey::generate();
+        let env = sealed(&key);
+        assert!(is_sealed(&env));
+        assert_eq!(recipients(&env), vec!["agent://local/b".to_string()]);
+        let opened = open(env, "agent://local/b", &key).unwrap();
+        assert_eq!(opened.payload, json!({"x": 1}));
+        assert!(!is_sealed(&opened));
+    }
+
+    #[test]
+    fn others_cannot_open_it() {
+        let key = SealKey::generate();
+        assert!(matches!(open(sealed(&key), "agent://local/c", &key), Err(SealError::NotRecipient(_))));
+        // Claiming the recipient's id without its key fails to unwrap.
+        assert!(open(sealed(&key), "agent://local/b", &SealKey::generate()).is_err());
+    }
+
+    #[test]
+    fn only_well_formed_sealed_envelopes_pass_require_sealed() {
+        let filter = RequireSealed::new(["topic://*"]);
+        let key = SealKey::generate();
+        let env = sealed(&key);
+        check_sealed(&env).unwrap();
+        filter.check("topic://t", &env).unwrap();
+
+        let mut cleartext = Envelope::new("agent://local/a", "topic://t", ContentType::mime("application", "json"), json!({"mrn": 1}));
+        assert!(matches!(filter.check("topic://t", &cleartext), Err(DeliveryError::Seal(SealError::Required(_)))));
+        // Claiming to be sealed is not enough.
+        cleartext.headers.insert(header::ENC.into(), "x".into());
+        assert!(matches!(filter.check("topic://t", &cleartext), Err(DeliveryError::Seal(SealError::Malformed(_)))));
+        for h in [header::ENC, header::ENC_EPK, header::ENC_RECIPIENTS] {
+            cleartext.headers.insert(h.into(), env.headers[h].clone());
+        }
+        assert!(filter.check("topic://t", &cleartext).is_err());
+        cleartext.payload = json!("not base64!");
+        assert!(filter.check("topic://t", &cleartext).is_err());
+
+        let mut forged = env.clone();
+        forged.headers.insert(header::ENC_RECIPIENTS.into(), "[]".into());
+        assert!(filter.check("topic://t", &forged).is_err());
+        let mut forged = env.clone();
+        forged.headers.insert(header::ENC.into(), "x25519+rot13".into());
+        assert!(filter.check("topic://t", &forged).is_err());
+        let mut forged = env;
+        forged.headers.remove(header::ENC_EPK);
+        assert!(filter.check("topic://t", &forged).is_err());
+    }
+
+    #[test]
+    fn tampering_with_the_bound_fields_fails() {
+        let key = SealKey::generate();
+        let mut env = sealed(&key);
+        env.dest = "topic://other".into();
+        assert!(open(env, "agent://local/b", &key).is_err());
+        let mut env = sealed(&key);
+        env.headers.insert(header::SCOPES.into(), "schema:read".into());
+        assert!(open(env, "agent://local/b", &key).is_err());
+        let mut env = sealed(&key);
+        env.src = "agent://local/mallory".into();
+        assert!(open(env, "agent://local/b", &key).is_err());
+    }
+}
@@ -17,7 +17,11 @@ impl Keypair {
         // conflicts between different `rand_core` versions pulled in by deps.
         let mut seed = [0u8; 32];
         getrandom(&mut seed).expect("getrandom");
-        let signing = SigningKey::from_bytes(&seed);
+        Self::from_seed(&seed)
+    }
+
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        let signing = SigningKey::from_bytes(seed);
         let verify = signing.verifying_key();
         Keypair { signing, verify }
     }
@@ -27,20 +31,48 @@ impl Keypair {
     }
 }
 
+/// Source of Ed25519 signatures for a `Signer`.
+///
+/// Implemented by in-memory `Keypair`s and unlocked keystore entries; an
+/// HSM or remote KMS backend only needs to expose its public key and sign.
+pub trait SigningBackend: Send + Sync {
+    fn verifying_key(&self) -> VerifyingKey;
+    fn sign(&self, bytes: &[u8]) -> anyhow::Result<DalekSignature>;
+}
+
+impl SigningBackend for Keypair {
+    fn verifying_key(&self) -> VerifyingKey {
+        self.verify
+    }
+
+    fn sign(&self, bytes: &[u8]) -> anyhow::Result<DalekSignature> {
+        Ok(self.signing.sign(bytes))
+    }
+}
+
 pub struct Signer {
-    kp: Keypair,
+    backend: Box<dyn SigningBackend>,
 }
 
+#[derive(Clone)]
 pub struct Verifier {
     vk: VerifyingKey,
 }
 
 impl Signer {
-    pub fn new(kp: Keypair) -> Self { Self { kp } }
+    pub fn new(kp: Keypair) -> Self { Self::with_backend(kp) }
+
+    pub fn with_backend(backend: impl SigningBackend + 'static) -> Self {
+        Self { backend: Box::new(backend) }
+    }
+
+    pub fn public_key_base64(&self) -> String {
+        general_purpose::STANDARD.encode(self.backend.verifying_key().as_bytes())
+    }
 
-    pub fn sign_bytes(&self, bytes: &[u8]) -> String {
-        let sig: DalekSignature = self.kp.signing.sign(bytes);
-        general_purpose::STANDARD.encode(sig.to_bytes())
+    pub fn sign_bytes(&self, bytes: &[u8]) -> anyhow::Result<String> {
+        let sig = self.backend.sign(bytes)?;
+        Ok(general_purpose::STANDARD.encode(sig.to_bytes()))
     }
 }
 
@@ -0,0 +1,143 @@
+use getrandom::getrandom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// W3C `traceparent` value: `00-<trace-id>-<parent-id>-<flags>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: [u8; 16],
+    pub parent_id: [u8; 8],
+    pub flags: u8,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("invalid traceparent {0:?}")]
+pub struct TraceParentError(pub String);
+
+pub const SAMPLED: u8 = 0x01;
+
+impl TraceParent {
+    /// Start a new sampled trace.
+    pub fn new_root() -> Self {
+        let mut trace_id = [0u8; 16];
+        fill_nonzero(&mut trace_id);
+        let mut parent_id = [0u8; 8];
+        fill_nonzero(&mut parent_id);
+        Self { trace_id, parent_id, flags: SAMPLED }
+    }
+
+    /// Same trace, fresh span id for the next hop.
+    pub fn child(&self) -> Self {
+        let mut parent_id = [0u8; 8];
+        fill_nonzero(&mut parent_id);
+        Self { parent_id, ..*self }
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        hex(&self.trace_id)
+    }
+
+    pub fn parent_id_hex(&self) -> String {
+        hex(&self.parent_id)
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.flags & SAMPLED != 0
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{}-{}-{:02x}", hex(&self.trace_id), hex(&self.parent_id), self.flags)
+    }
+}
+
+impl FromStr for TraceParent {
+    type Err = TraceParentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || TraceParentError(s.to_string());
+        let parts: Vec<&str> = s.trim().split('-').collect();
+        // Future versions may append fields; version ff is forbidden.
+        if parts.len() < 4 || parts[0].len() != 2 || parts[0] == "ff" || (parts[0] == "00" && parts.len() != 4) {
+            return Err(bad());
+        }
+        let mut trace_id = [0u8; 16];
+        let mut parent_id = [0u8; 8];
+        let mut flags = [0u8; 1];
+        unhex(parts[0], &mut [0u8; 1]).ok_or_else(bad)?;
+        unhex(parts[1], &mut trace_id).ok_or_else(bad)?;
+        unhex(parts[2], &mut parent_id).ok_or_else(bad)?;
+        unhex(parts[3], &mut flags).ok_or_else(bad)?;
+        if trace_id == [0; 16] || parent_id == [0; 8] {
+            return Err(bad());
+        }
+        Ok(Self { trace_id, parent_id, flags: flags[0] })
+    }
+}
+
+fn fill_nonzero(buf: &mut [u8]) {
+    loop {
+        getrandom(buf).expect("getrandom");
+        if buf.iter().any(|b| *b != 0) {
+            return;
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str, out: &mut [u8]) -> Option<()> {
+    if s.len() != out.len() * 2 || !s.bytes().all(|c| c.is_ascii_digit() || (b'a'..=b'f').contains(&c)) {
+        return None;
+    }
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn round_trips_through_display() {
+        let tp: TraceParent = VALID.parse().unwrap();
+        assert_eq!(tp.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(tp.parent_id_hex(), "00f067aa0ba902b7");
+        assert!(tp.sampled());
+        assert_eq!(tp.to_string(), VALID);
+        let child = tp.child();
+        assert_eq!(child.trace_id, tp.trace_id);
+        assert_ne!(child.parent_id, tp.parent_id);
+        // Later versions may append fields.
+        assert!("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra".parse::<TraceParent>().is_ok());
+    }
+
+    #[test]
+    fn malformed_values_are_rejected() {
+        for bad in [
+            "",
+            "00",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "zz-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "0-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902bx-01",
+        ] {
+            assert_eq!(bad.parse::<TraceParent>(), Err(TraceParentError(bad.to_string())), "{:?}", bad);
+        }
+    }
+}
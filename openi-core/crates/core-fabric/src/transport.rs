@@ -0,0 +1,337 @@
+use crate::codec::{Codec, CodecError};
+use crate::compression::{self, CompressionError, CompressionPolicy};
+use crate::envelope::ENVELOPE_VERSION;
+use crate::upcast::{UpcastError, Upcaster};
+use crate::Envelope;
+use std::sync::Arc;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Default cap on a single frame, to bound allocation on hostile input.
+pub const DEFAULT_MAX_FRAME: usize = 16 * 1024 * 1024;
+
+/// Which side of the connection we are; the initiator's codec
+/// preferences win when both sides support several.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Initiator,
+    Acceptor,
+}
+
+#[derive(Debug, Error)]
+pub enum TransportError {
+    #[error("transport io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+    #[error("frame of {len} bytes exceeds limit of {max}")]
+    FrameTooLarge { len: usize, max: usize },
+    #[error("handshake: {0}")]
+    Handshake(String),
+    #[error(transparent)]
+    Compression(#[from] CompressionError),
+    #[error(transparent)]
+    Upcast(#[from] UpcastError),
+}
+
+/// First frame on every connection, always JSON.
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    codecs: Vec<String>,
+}
+
+/// The part of an envelope `FramedReader::recv` needs before decoding it;
+/// everything else is skipped without being allocated.
+#[derive(Deserialize)]
+struct Peek {
+    v: Option<u64>,
+    #[serde(default)]
+    headers: PeekHeaders,
+}
+
+#[derive(Default, Deserialize)]
+struct PeekHeaders {
+    #[serde(rename = "compression")]
+    compression: Option<IgnoredAny>,
+}
+
+/// Envelope stream over any byte pipe (TCP, UDS, stdio), framed as a
+/// 4-byte big-endian length followed by the encoded envelope.
+pub struct FramedConnection<R, W> {
+    reader: FramedReader<R>,
+    writer: FramedWriter<W>,
+}
+
+/// Receiving half of a `FramedConnection`, from `into_split`.
+pub struct FramedReader<R> {
+    inner: R,
+    codec: Codec,
+    max_frame: usize,
+    max_decompressed: usize,
+    upcaster: Option<Arc<Upcaster>>,
+}
+
+/// Sending half of a `FramedConnection`, from `into_split`.
+pub struct FramedWriter<W> {
+    inner: W,
+    codec: Codec,
+    max_frame: usize,
+    compression: Option<CompressionPolicy>,
+}
+
+impl<R, W> FramedConnection<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    /// Exchange codec lists with the peer and settle on one wire codec.
+    pub async fn handshake(reader: R, writer: W, role: Role, supported: &[Codec]) -> Result<Self, TransportError> {
+        let mut conn = Self {
+            reader: FramedReader {
+                inner: reader,
+                codec: Codec::Json,
+                max_frame: DEFAULT_MAX_FRAME,
+                max_decompressed: compression::DEFAULT_MAX_DECOMPRESSED,
+                upcaster: None,
+            },
+            writer: FramedWriter { inner: writer, codec: Codec::Json, max_frame: DEFAULT_MAX_FRAME, compression: None },
+        };
+        let hello = Hello { codecs: supported.iter().map(|c| c.name().to_string()).collect() };
+        let bytes = serde_json::to_vec(&hello).map_err(|e| TransportError::Handshake(e.to_string()))?;
+        conn.writer.write_frame(&bytes).await?;
+
+        let bytes = conn
+            .reader
+            .read_frame()
+            .await?
+            .ok_or_else(|| TransportError::Handshake("peer closed before hello".into()))?;
+        let peer: Hello = serde_json::from_slice(&bytes).map_err(|e| TransportError::Handshake(e.to_string()))?;
+        // Codecs we don't know are ignored; JSON remains the common floor.
+        let theirs: Vec<Codec> = peer.codecs.iter().filter_map(|c| c.parse().ok()).collect();
+        let codec = match role {
+            Role::Initiator => Codec::negotiate(supported, &theirs),
+            Role::Acceptor => Codec::negotiate(&theirs, supported),
+        };
+        conn.reader.codec = codec;
+        conn.writer.codec = codec;
+        Ok(conn)
+    }
+
+    pub fn with_max_frame(mut self, max_frame: usize) -> Self {
+        self.reader.max_frame = max_frame;
+        self.writer.max_frame = max_frame;
+        self
+    }
+
+    /// Compress outgoing payloads over the policy threshold. Incoming
+    /// compressed payloads are always inflated, bounded by the policy's
+    /// `max_decompressed` (or the default limit without a policy).
+    pub fn with_compression(mut self, policy: CompressionPolicy) -> Self {
+        self.reader.max_decompressed = policy.max_decompressed;
+        self.writer.compression = Some(policy);
+        self
+    }
+
+    /// Accept envelopes from peers on an older envelope version. Only those
+    /// are migrated: current envelopes are decoded as sent, and payloads are
+    /// left for typed subscribers to upcast once the bus has checked them.
+    pub fn with_upcaster(mut self, upcaster: Arc<Upcaster>) -> Self {
+        self.reader.upcaster = Some(upcaster);
+        self
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.writer.codec
+    }
+
+    pub async fn send<T: Serialize>(&mut self, env: &Envelope<T>) -> Result<(), TransportError> {
+        self.writer.send(env).await
+    }
+
+    /// Next envelope, or `None` once the peer closes cleanly between frames.
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<Option<Envelope<T>>, TransportError> {
+        self.reader.recv().await
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader.inner, self.writer.inner)
+    }
+
+    /// Halves that can be driven from separate tasks, so that waiting on
+    /// `recv` never holds up a `send`.
+    pub fn into_split(self) -> (FramedReader<R>, FramedWriter<W>) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<R: AsyncRead + Unpin> FramedReader<R> {
+    /// Next envelope, or `None` once the peer closes cleanly between frames.
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<Option<Envelope<T>>, TransportError> {
+        let Some(bytes) = self.read_frame().await? else {
+            return Ok(None);
+        };
+        let max = self.max_decompressed;
+        // A compressed payload has to be inflated before it can be decoded
+        // into its real type. Only the version and compression header are
+        // peeked at; the envelope itself is decoded once, straight into `T`
+        // when it can be, so byte-string payloads keep their native form and
+        // signed bytes are left as they were.
+        let peek: Peek = self.codec.decode_as(&bytes)?;
+        if let Some(upcaster) = self.upcaster.as_ref().filter(|_| peek.v != Some(ENVELOPE_VERSION.into())) {
+            let env = compression::decompress(upcaster.read(self.codec, &bytes)?, max)?;
+            return Ok(Some(self.typed(env)?));
+        }
+        if peek.headers.compression.is_none() {
+            return Ok(Some(self.codec.decode(&bytes)?));
+        }
+        let env = compression::decompress(self.codec.decode(&bytes)?, max)?;
+        Ok(Some(self.typed(env)?))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn typed<T: DeserializeOwned>(&self, env: Envelope<serde_json::Value>) -> Result<Envelope<T>, TransportError> {
+        env.map_payload(serde_json::from_value)
+            .map_err(|e| TransportError::Codec(CodecError::Decode { codec: self.codec, msg: e.to_string() }))
+    }
+
+    async fn read_frame(&mut self) -> Result<Option<Vec<u8>>, TransportError> {
+        let mut len = [0u8; 4];
+        match self.inner.read_exact(&mut len).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > self.max_frame {
+            return Err(TransportError::FrameTooLarge { len, max: self.max_frame });
+        }
+        let mut buf = vec![0u8; len];
+        self.inner.read_exact(&mut buf).await?;
+        Ok(Some(buf))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> FramedWriter<W> {
+    pub async fn send<T: Serialize>(&mut self, env: &Envelope<T>) -> Result<(), TransportError> {
+        let compressed = match &self.compression {
+            Some(policy) => policy.compress(env)?,
+            None => None,
+        };
+        let bytes = match compressed {
+            Some(c) => self.codec.encode(&c)?,
+            None => self.codec.encode(env)?,
+        };
+        self.write_frame(&bytes).await
+    }
+
+    /// Flush and shut down the underlying writer, signalling EOF to the peer.
+    pub async fn shutdown(&mut self) -> Result<(), TransportError> {
+        self.inner.shutdown().await?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    async fn write_frame(&mut self, bytes: &[u8]) -> Result<(), TransportError> {
+        if bytes.len() > self.max_frame || bytes.len() > u32::MAX as usize {
+            return Err(TransportError::FrameTooLarge { len: bytes.len(), max: self.max_frame });
+        }
+        self.inner.write_all(&(bytes.len() as u32).to_be_bytes()).await?;
+        self.inner.write_all(bytes).await?;
+        self.inner.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::{Keypair, Signer, Verifier};
+    use crate::ContentType;
+    use serde_json::{json, Value};
+    use tokio::io::{duplex, DuplexStream, ReadHalf, WriteHalf};
+
+    type Conn = FramedConnection<ReadHalf<DuplexStream>, WriteHalf<DuplexStream>>;
+
+    async fn pair(a: &[Codec], b: &[Codec]) -> (Conn, Conn) {
+        let (left, right) = duplex(1 << 20);
+        let (lr, lw) = tokio::io::split(left);
+        let (rr, rw) = tokio::io::split(right);
+        let (l, r) = tokio::join!(
+            FramedConnection::handshake(lr, lw, Role::Initiator, a),
+            FramedConnection::handshake(rr, rw, Role::Acceptor, b)
+        );
+        (l.unwrap(), r.unwrap())
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Blob {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    }
+
+    #[tokio::test]
+    async fn envelopes_round_trip_on_the_negotiated_codec() {
+        for codec in Codec::ALL {
+            let (mut a, mut b) = pair(&[codec, Codec::Json], &Codec::ALL).await;
+            assert_eq!(a.codec(), codec);
+            let ctype = ContentType::mime("application", "octet-stream");
+            let sent = Envelope::new("agent://local/a", "topic://t", ctype, Blob { data: vec![0, 1, 255] });
+            a.send(&sent).await.unwrap();
+            let got: Envelope<Blob> = b.recv().await.unwrap().unwrap();
+            assert_eq!(got.payload, sent.payload);
+            assert_eq!(got.canonical_bytes(), sent.canonical_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn compressed_payloads_are_inflated_on_receipt() {
+        let (a, mut b) = pair(&[Codec::Cbor], &[Codec::Cbor]).await;
+        let policy = CompressionPolicy { threshold: 16, ..CompressionPolicy::default() };
+        let mut a = a.with_compression(policy);
+        let payload = json!({ "text": "x".repeat(4096) });
+        let sent = Envelope::new("agent://local/a", "topic://t", ContentType::mime("application", "json"), payload);
+        a.send(&sent).await.unwrap();
+        let got: Envelope<Value> = b.recv().await.unwrap().unwrap();
+        assert_eq!(got.payload, sent.payload);
+        assert!(!compression::is_compressed(&got));
+        drop(a);
+        assert!(b.recv::<Value>().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn current_envelopes_bypass_the_upcaster() {
+        let upcaster = Arc::new(Upcaster::new().envelope(0, |_| Err("not a v0 peer".into())));
+        let (mut a, b) = pair(&[Codec::Cbor], &[Codec::Cbor]).await;
+        let mut b = b.with_upcaster(upcaster);
+        let signer = Signer::new(Keypair::from_seed(&[7; 32]));
+        let ctype = ContentType::mime("application", "octet-stream");
+        let mut sent = Envelope::new("agent://local/a", "topic://t", ctype, Blob { data: vec![0, 1, 255] });
+        sent.sign(&signer).unwrap();
+        a.send(&sent).await.unwrap();
+        let got: Envelope<Blob> = b.recv().await.unwrap().unwrap();
+        assert_eq!(got.payload, sent.payload);
+        got.verify(&Verifier::from_base64(&signer.public_key_base64()).unwrap()).unwrap();
+
+        let mut old = Envelope::new("agent://local/a", "topic://t", ContentType::json(), json!(1));
+        old.v = 0;
+        a.send(&old).await.unwrap();
+        assert!(matches!(b.recv::<Value>().await, Err(TransportError::Upcast(UpcastError::Migration { .. }))));
+    }
+
+    #[tokio::test]
+    async fn oversized_frames_are_refused() {
+        let (mut a, b) = pair(&[Codec::Json], &[Codec::Json]).await;
+        let mut b = b.with_max_frame(64);
+        let sent = Envelope::new("agent://local/a", "topic://t", ContentType::mime("application", "json"), json!(1));
+        a.send(&sent).await.unwrap();
+        assert!(matches!(b.recv::<Value>().await, Err(TransportError::FrameTooLarge { max: 64, .. })));
+    }
+}
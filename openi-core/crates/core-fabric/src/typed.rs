@@ -0,0 +1,222 @@
+use crate::bus::Subscription;
+use crate::content::{Compatibility, ContentType};
+use crate::upcast::Upcaster;
+use crate::Envelope;
+use serde::de::DeserializeOwned;
+use std::any::{type_name, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Maps content types to the Rust types that carry them, one to one.
+#[derive(Debug, Default, Clone)]
+pub struct TypeRegistry {
+    by_type: HashMap<TypeId, (ContentType, &'static str)>,
+    by_ctype: HashMap<ContentType, TypeId>,
+}
+
+#[derive(Debug, Error)]
+pub enum PayloadTypeError {
+    #[error("{ctype} is already registered to {existing}")]
+    Conflict { ctype: String, existing: &'static str },
+    #[error("{0} has no registered content type")]
+    Unregistered(&'static str),
+    #[error("envelope {id}: expected {expected}, got {found}")]
+    WrongType { id: String, expected: String, found: String },
+    #[error("envelope {id}: cannot decode {ctype} as {type_name}: {msg}")]
+    Decode { id: String, ctype: String, type_name: &'static str, msg: String },
+    #[error("envelope {id}: {msg}")]
+    Upcast { id: String, msg: String },
+    #[error("cannot encode {type_name}: {msg}")]
+    Encode { type_name: &'static str, msg: String },
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind `T` to `ctype`. Re-registering the same pair is a no-op; binding
+    /// either side to something else is a conflict.
+    pub fn register<T: 'static>(&mut self, ctype: ContentType) -> Result<(), PayloadTypeError> {
+        let id = TypeId::of::<T>();
+        if let Some((existing, name)) = self.by_type.get(&id) {
+            if *existing == ctype {
+                return Ok(());
+            }
+            return Err(PayloadTypeError::Conflict { ctype: existing.to_string(), existing: name });
+        }
+        if let Some(other) = self.by_ctype.get(&ctype) {
+            let existing = self.by_type[other].1;
+            return Err(PayloadTypeError::Conflict { ctype: ctype.to_string(), existing });
+        }
+        self.by_ctype.insert(ctype.clone(), id);
+        self.by_type.insert(id, (ctype, type_name::<T>()));
+        Ok(())
+    }
+
+    pub fn ctype_of<T: 'static>(&self) -> Option<&ContentType> {
+        self.by_type.get(&TypeId::of::<T>()).map(|(c, _)| c)
+    }
+
+    /// Rust type name registered for `ctype`.
+    pub fn type_name(&self, ctype: &ContentType) -> Option<&'static str> {
+        self.by_ctype.get(ctype).map(|id| self.by_type[id].1)
+    }
+
+    pub fn ctypes(&self) -> impl Iterator<Item = &ContentType> {
+        self.by_ctype.keys()
+    }
+}
+
+/// A subscription whose envelopes are decoded into `T`.
+///
+/// When `T` has a registered content type, envelopes of any other type
+/// are reported as `WrongType` rather than decoded. Older versions of that
+/// type are upcast first when the bus has an `Upcaster`.
+pub struct TypedSubscription<T> {
+    inner: Subscription,
+    expected: Option<ContentType>,
+    upcaster: Option<Arc<Upcaster>>,
+    _payload: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> TypedSubscription<T> {
+    pub(crate) fn new(inner: Subscription, expected: Option<ContentType>, upcaster: Option<Arc<Upcaster>>) -> Self {
+        Self { inner, expected, upcaster, _payload: PhantomData }
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.inner.pattern
+    }
+
+    /// Next envelope, or `None` once the bus is gone. A decode failure
+    /// affects only that envelope; keep calling `recv` for the rest.
+    pub async fn recv(&mut self) -> Option<Result<Envelope<T>, PayloadTypeError>> {
+        let env = self.inner.rx.recv().await?;
+        Some(decode(env, self.expected.as_ref(), self.upcaster.as_deref()))
+    }
+}
+
+/// Decode `env` into `T`, checking it against the `expected` content type
+/// and upcasting older versions when an upcaster is given.
+pub fn decode<T: DeserializeOwned>(
+    mut env: Envelope<serde_json::Value>,
+    expected: Option<&ContentType>,
+    upcaster: Option<&Upcaster>,
+) -> Result<Envelope<T>, PayloadTypeError> {
+    if let Some(expected) = expected {
+        let compat = expected.compatibility(&env.ctype);
+        if let (Compatibility::Older, Some(upcaster)) = (compat, upcaster) {
+            let id = env.id.clone();
+            env = upcaster
+                .upcast_payload(env, expected)
+                .map_err(|e| PayloadTypeError::Upcast { id, msg: e.to_string() })?;
+        } else if compat != Compatibility::Exact {
+            return Err(PayloadTypeError::WrongType {
+                id: env.id,
+                expected: expected.to_string(),
+                found: env.ctype.to_string(),
+            });
+        }
+    }
+    let (id, ctype) = (env.id.clone(), env.ctype.to_string());
+    env.map_payload(serde_json::from_value).map_err(|e| PayloadTypeError::Decode {
+        id,
+        ctype,
+        type_name: type_name::<T>(),
+        msg: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bus, DeliveryError};
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        value: u32,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Order {
+        id: String,
+    }
+
+    fn reading(version: u32) -> ContentType {
+        ContentType::logical("lab", "reading", version)
+    }
+
+    #[test]
+    fn types_and_ctypes_bind_one_to_one() {
+        let mut reg = TypeRegistry::new();
+        reg.register::<Reading>(reading(2)).unwrap();
+        reg.register::<Reading>(reading(2)).unwrap();
+        assert_eq!(reg.ctype_of::<Reading>(), Some(&reading(2)));
+        assert_eq!(reg.type_name(&reading(2)), Some(type_name::<Reading>()));
+
+        let Err(PayloadTypeError::Conflict { ctype, .. }) = reg.register::<Reading>(reading(3)) else {
+            panic!("rebinding a type accepted")
+        };
+        assert_eq!(ctype, "lab.reading.v2");
+        assert!(matches!(reg.register::<Order>(reading(2)), Err(PayloadTypeError::Conflict { .. })));
+        assert_eq!(reg.ctype_of::<Order>(), None);
+    }
+
+    #[tokio::test]
+    async fn typed_round_trip_and_bad_envelopes_are_reported() {
+        let bus = Bus::new();
+        bus.register_type::<Reading>(reading(2)).unwrap();
+        let mut sub = bus.subscribe_typed::<Reading>("topic://lab");
+
+        bus.publish_typed("agent://local/a", "topic://lab", Reading { value: 5 }).await.unwrap();
+        let env = sub.recv().await.unwrap().unwrap();
+        assert_eq!(env.payload, Reading { value: 5 });
+        assert_eq!(env.ctype, reading(2));
+
+        let other = Envelope::new("agent://local/a", "topic://lab", ContentType::logical("lab", "order", 1), json!({}));
+        bus.publish("topic://lab", other).await.unwrap();
+        assert!(matches!(sub.recv().await.unwrap(), Err(PayloadTypeError::WrongType { .. })));
+        let newer = Envelope::new("agent://local/a", "topic://lab", reading(3), json!({ "value": 1 }));
+        bus.publish("topic://lab", newer).await.unwrap();
+        assert!(matches!(sub.recv().await.unwrap(), Err(PayloadTypeError::WrongType { .. })));
+        let garbled = Envelope::new("agent://local/a", "topic://lab", reading(2), json!({ "value": "five" }));
+        bus.publish("topic://lab", garbled).await.unwrap();
+        assert!(matches!(sub.recv().await.unwrap(), Err(PayloadTypeError::Decode { .. })));
+
+        // The subscription keeps going after failures.
+        bus.publish_typed("agent://local/a", "topic://lab", Reading { value: 6 }).await.unwrap();
+        assert_eq!(sub.recv().await.unwrap().unwrap().payload, Reading { value: 6 });
+    }
+
+    #[tokio::test]
+    async fn unregistered_types_cannot_be_published() {
+        let bus = Bus::new();
+        let order = || Order { id: "o1".into() };
+        let unregistered = bus.typed_envelope("agent://local/a", "topic://o", order());
+        assert!(matches!(unregistered, Err(PayloadTypeError::Unregistered(_))));
+        let err = bus.publish_typed("agent://local/a", "topic://o", order()).await.unwrap_err();
+        assert!(matches!(err, DeliveryError::Type(PayloadTypeError::Unregistered(_))), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn older_versions_are_upcast_for_typed_subscribers() {
+        let bus = Bus::new();
+        bus.register_type::<Reading>(reading(2)).unwrap();
+        bus.set_upcaster(Some(Arc::new(Upcaster::new().payload(&reading(1), |p| Ok(json!({ "value": p["v"] }))))));
+        let mut sub = bus.subscribe_typed::<Reading>("topic://lab");
+        let old = Envelope::new("agent://local/a", "topic://lab", reading(1), json!({ "v": 7 }));
+        bus.publish("topic://lab", old).await.unwrap();
+        let env = sub.recv().await.unwrap().unwrap();
+        assert_eq!(env.payload, Reading { value: 7 });
+        assert_eq!(env.ctype, reading(2));
+
+        let ancient = Envelope::new("agent://local/a", "topic://lab", reading(0), json!({}));
+        bus.publish("topic://lab", ancient).await.unwrap();
+        assert!(matches!(sub.recv().await.unwrap(), Err(PayloadTypeError::Upcast { .. })));
+    }
+}
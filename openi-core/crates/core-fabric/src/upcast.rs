@@ -0,0 +1,229 @@
+use crate::codec::{Codec, CodecError};
+use crate::content::{Compatibility, ContentType};
+use crate::envelope::{header, ENVELOPE_VERSION};
+use crate::Envelope;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+/// Rewrites a JSON document from one version to the next.
+pub type Migration = Box<dyn Fn(Value) -> Result<Value, String> + Send + Sync>;
+
+/// Reads envelopes written by older nodes and brings them up to date, so
+/// producers and consumers can be upgraded independently.
+///
+/// Envelope migrations rewrite the whole envelope document from `v` to
+/// `v + 1`; payload migrations rewrite a payload from `family.vN` to
+/// `family.vN+1`. Chains are applied one step at a time. Upcasting changes
+/// the signed bytes, so a signed envelope on an older `v` is refused rather
+/// than migrated, and payloads are upcast only for typed subscribers, after
+/// the bus filters have verified the original.
+#[derive(Default)]
+pub struct Upcaster {
+    envelope: BTreeMap<u8, Migration>,
+    payload: HashMap<(String, u32), Migration>,
+}
+
+#[derive(Debug, Error)]
+pub enum UpcastError {
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+    #[error("envelope has no valid `v`")]
+    MissingVersion,
+    #[error("envelope v{found} is newer than this node (v{supported})")]
+    NewerEnvelope { found: u8, supported: u8 },
+    #[error("no migration from envelope v{0}")]
+    NoEnvelopeMigration(u8),
+    #[error("signed envelope v{0} would no longer verify once upcast")]
+    Signed(u8),
+    #[error("{found} is newer than {target}")]
+    NewerPayload { found: String, target: String },
+    #[error("{found} cannot be read as {target}")]
+    Incompatible { found: String, target: String },
+    #[error("no migration from {0}")]
+    NoPayloadMigration(String),
+    #[error("migrating {from}: {msg}")]
+    Migration { from: String, msg: String },
+}
+
+impl Upcaster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the envelope migration from `from` to `from + 1`.
+    pub fn envelope<F>(mut self, from: u8, migrate: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.envelope.insert(from, Box::new(migrate));
+        self
+    }
+
+    /// Register the payload migration from `from` to the next version of
+    /// the same type, e.g. `lab.result.v1` → `lab.result.v2`.
+    pub fn payload<F>(mut self, from: &ContentType, migrate: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        let version = from.version().unwrap_or(0);
+        self.payload.insert((from.family(), version), Box::new(migrate));
+        self
+    }
+
+    /// Decode an envelope of any supported `v` and upcast it to the current one.
+    pub fn read(&self, codec: Codec, bytes: &[u8]) -> Result<Envelope<Value>, UpcastError> {
+        let mut env = self.read_value(codec.decode_value(bytes)?)?;
+        env.headers.insert(header::CONTENT_ENCODING.into(), codec.name().into());
+        Ok(env)
+    }
+
+    pub fn read_value(&self, mut doc: Value) -> Result<Envelope<Value>, UpcastError> {
+        let mut v = doc
+            .get("v")
+            .and_then(Value::as_u64)
+            .and_then(|v| u8::try_from(v).ok())
+            .ok_or(UpcastError::MissingVersion)?;
+        if v > ENVELOPE_VERSION {
+            return Err(UpcastError::NewerEnvelope { found: v, supported: ENVELOPE_VERSION });
+        }
+        let signed = |key: &str| {
+            doc.get(key).is_some_and(|s| !s.is_null() && s.as_array().is_none_or(|a| !a.is_empty()))
+        };
+        if v < ENVELOPE_VERSION && (signed("sig") || signed("countersigs")) {
+            return Err(UpcastError::Signed(v));
+        }
+        while v < ENVELOPE_VERSION {
+            let migrate = self.envelope.get(&v).ok_or(UpcastError::NoEnvelopeMigration(v))?;
+            doc = migrate(doc).map_err(|msg| UpcastError::Migration { from: format!("envelope v{}", v), msg })?;
+            v += 1;
+            doc["v"] = Value::from(v);
+        }
+        serde_json::from_value(doc).map_err(|e| UpcastError::Migration {
+            from: format!("envelope v{}", ENVELOPE_VERSION),
+            msg: e.to_string(),
+        })
+    }
+
+    /// Bring `env`'s payload up to `target`. Envelopes already at `target`
+    /// pass through; upcast ones record their original type in `upcast_from`.
+    pub fn upcast_payload(&self, mut env: Envelope<Value>, target: &ContentType) -> Result<Envelope<Value>, UpcastError> {
+        let (found, wanted) = (env.ctype.to_string(), target.to_string());
+        match target.compatibility(&env.ctype) {
+            Compatibility::Exact => return Ok(env),
+            Compatibility::Older => {}
+            Compatibility::Newer => return Err(UpcastError::NewerPayload { found, target: wanted }),
+            Compatibility::Incompatible => return Err(UpcastError::Incompatible { found, target: wanted }),
+        }
+        let family = env.ctype.family();
+        let mut version = env.ctype.version().unwrap_or(0);
+        let goal = target.version().unwrap_or(0);
+        while version < goal {
+            let from = env.ctype.with_version(version).to_string();
+            let migrate = self
+                .payload
+                .get(&(family.clone(), version))
+                .ok_or_else(|| UpcastError::NoPayloadMigration(from.clone()))?;
+            let payload = std::mem::take(&mut env.payload);
+            env.payload = migrate(payload).map_err(|msg| UpcastError::Migration { from, msg })?;
+            version += 1;
+        }
+        env.ctype = target.clone();
+        env.headers.insert(header::UPCAST_FROM.into(), found);
+        Ok(env)
+    }
+
+    /// Whether a `from` payload can be upcast all the way to `target`.
+    pub fn can_upcast(&self, from: &ContentType, target: &ContentType) -> bool {
+        match target.compatibility(from) {
+            Compatibility::Exact => true,
+            Compatibility::Older => {
+                let family = from.family();
+                let goal = target.version().unwrap_or(0);
+                (from.version().unwrap_or(0)..goal).all(|v| self.payload.contains_key(&(family.clone(), v)))
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn lab(version: u32) -> ContentType {
+        ContentType::logical("lab", "result", version)
+    }
+
+    fn upcaster() -> Upcaster {
+        Upcaster::new()
+            .payload(&lab(1), |mut p| {
+                p["unit"] = json!("mg/dL");
+                Ok(p)
+            })
+            .payload(&lab(2), |p| match p.get("value") {
+                Some(v) => Ok(json!({ "reading": { "value": v, "unit": p["unit"] } })),
+                None => Err("no value".into()),
+            })
+    }
+
+    #[test]
+    fn payloads_are_migrated_one_version_at_a_time() {
+        let up = upcaster();
+        let env = Envelope::new("agent://local/lab", "topic://lab", lab(1), json!({ "value": 5 }));
+        let out = up.upcast_payload(env, &lab(3)).unwrap();
+        assert_eq!(out.payload, json!({ "reading": { "value": 5, "unit": "mg/dL" } }));
+        assert_eq!(out.ctype, lab(3));
+        assert_eq!(out.headers.get(header::UPCAST_FROM).map(String::as_str), Some("lab.result.v1"));
+        assert!(up.can_upcast(&lab(1), &lab(3)) && !up.can_upcast(&lab(0), &lab(3)));
+
+        let current = Envelope::new("agent://local/lab", "topic://lab", lab(3), json!(1));
+        assert!(!up.upcast_payload(current, &lab(3)).unwrap().headers.contains_key(header::UPCAST_FROM));
+    }
+
+    #[test]
+    fn newer_unknown_and_failing_payloads_are_refused() {
+        let up = upcaster();
+        let env = |ctype: ContentType, payload: Value| Envelope::new("agent://local/lab", "topic://lab", ctype, payload);
+        assert!(matches!(up.upcast_payload(env(lab(4), json!(1)), &lab(3)), Err(UpcastError::NewerPayload { .. })));
+        assert!(matches!(up.upcast_payload(env(lab(0), json!(1)), &lab(3)), Err(UpcastError::NoPayloadMigration(_))));
+        let other = ContentType::logical("lab", "order", 1);
+        assert!(matches!(up.upcast_payload(env(other, json!(1)), &lab(3)), Err(UpcastError::Incompatible { .. })));
+        let Err(UpcastError::Migration { from, msg }) = up.upcast_payload(env(lab(2), json!({})), &lab(3)) else {
+            panic!("migration did not fail")
+        };
+        assert_eq!((from.as_str(), msg.as_str()), ("lab.result.v2", "no value"));
+    }
+
+    #[test]
+    fn envelope_documents_are_versioned() {
+        let current = Envelope::new("agent://local/a", "topic://t", ContentType::json(), json!(1));
+        let doc = serde_json::to_value(&current).unwrap();
+        assert_eq!(Upcaster::new().read_value(doc.clone()).unwrap().canonical_bytes(), current.canonical_bytes());
+
+        // A v0 peer called the payload `body`.
+        let mut v0 = doc.clone();
+        v0["v"] = json!(0);
+        let body = v0.as_object_mut().unwrap().remove("payload").unwrap();
+        v0["body"] = body;
+        assert!(matches!(Upcaster::new().read_value(v0.clone()), Err(UpcastError::NoEnvelopeMigration(0))));
+        let up = Upcaster::new().envelope(0, |mut doc| {
+            let body = doc.as_object_mut().and_then(|d| d.remove("body")).ok_or("no body")?;
+            doc["payload"] = body;
+            Ok(doc)
+        });
+        assert_eq!(up.read_value(v0.clone()).unwrap().canonical_bytes(), current.canonical_bytes());
+
+        let mut signed = v0;
+        signed["sig"] = json!("c2ln");
+        assert!(matches!(up.read_value(signed), Err(UpcastError::Signed(0))));
+
+        let mut newer = doc.clone();
+        newer["v"] = json!(ENVELOPE_VERSION + 1);
+        assert!(matches!(Upcaster::new().read_value(newer), Err(UpcastError::NewerEnvelope { .. })));
+        let mut unversioned = doc;
+        unversioned.as_object_mut().unwrap().remove("v");
+        assert!(matches!(Upcaster::new().read_value(unversioned), Err(UpcastError::MissingVersion)));
+    }
+}
@@ -0,0 +1,926 @@
+//! Agent lifecycle: registration, start/stop/restart, scaling and state
+//! tracking.
+//!
+//! Each agent runs as `replicas` instances, each with its own lifecycle.
+//! A replica that exits on its own is restarted according to the
+//! manifest's restart policy, with exponential backoff; one that keeps
+//! exiting is parked in `CrashLoop` until it is started again by hand.
+//! Replicas of agents with a `health_check_interval` are probed while up
+//! (see `health`).
+
+use crate::health::{self, Health, HealthError, HealthEvent, Probe, ProbeReply, FAILURE_THRESHOLD, HEALTH_TOPIC};
+use crate::manifest::{self, AgentManifest, ManifestError};
+use crate::identity::NodeIdentity;
+use crate::policy::{Action, Decision, PolicyEngine, Resource};
+use crate::rbac::{AccessLevel, RbacError};
+use crate::scopes::ScopeGuard;
+use crate::resources::{LimitBreach, ResourceError, ResourceLimits};
+use crate::supervisor::{RestartPolicy, Restarts, SupervisorConfig, SupervisorError, Verdict};
+use async_trait::async_trait;
+use openi_core_fabric::{BlobStore, Bus, ContentType, EncryptionMode, Envelope, RequireSealed, SealError};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use time::OffsetDateTime;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Topic lifecycle transitions are published on.
+pub const LIFECYCLE_TOPIC: &str = "fabric.lifecycle";
+/// Source of envelopes the kernel publishes about agents.
+pub const KERNEL_SRC: &str = "agent://fabric/kernel";
+
+/// Where a replica is in its lifecycle.
+///
+/// ```text
+/// Pending ─► Starting ─► Running ◄─► Degraded
+///               │           │           │
+///               ▼           ▼           ▼
+///             Failed ◄── Stopping ──► Stopped
+///               │                       ▲
+///               ▼                       │
+///           CrashLoop ──────────────────┘
+/// ```
+/// `Stopped`, `Failed` and `CrashLoop` replicas may be started again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AgentState {
+    Pending,
+    Starting,
+    Running,
+    Degraded,
+    Stopping,
+    Stopped,
+    Failed,
+    /// Exited too often to be restarted automatically.
+    CrashLoop,
+}
+
+impl AgentState {
+    pub fn can_transition(self, to: AgentState) -> bool {
+        use AgentState::*;
+        matches!(
+            (self, to),
+            (Pending | Stopped | Failed | CrashLoop, Starting)
+                | (Starting, Running | Failed | Stopping)
+                | (Running, Degraded | Stopping | Failed | Stopped)
+                | (Degraded, Running | Stopping | Failed | Stopped)
+                | (Stopping, Stopped | Failed)
+                | (Failed | CrashLoop, Stopped)
+                | (Failed | Stopped, CrashLoop)
+        )
+    }
+
+    /// Running or degraded: an instance exists.
+    pub fn is_up(self) -> bool {
+        matches!(self, AgentState::Running | AgentState::Degraded)
+    }
+
+    /// Which state speaks for an agent whose replicas disagree.
+    fn rank(self) -> u8 {
+        match self {
+            AgentState::Pending => 0,
+            AgentState::Stopped => 1,
+            AgentState::Failed => 2,
+            AgentState::CrashLoop => 3,
+            AgentState::Stopping => 4,
+            AgentState::Starting => 5,
+            AgentState::Degraded => 6,
+            AgentState::Running => 7,
+        }
+    }
+}
+
+impl fmt::Display for AgentState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Payload of `fabric.lifecycle.transition.v1` envelopes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LifecycleEvent {
+    pub agent: String,
+    #[serde(default)]
+    pub replica: u32,
+    pub from: AgentState,
+    pub to: AgentState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Set when the agent failed by going over a resource limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breach: Option<LimitBreach>,
+}
+
+impl LifecycleEvent {
+    pub fn ctype() -> ContentType {
+        ContentType::logical("fabric.lifecycle", "transition", 1)
+    }
+}
+
+/// Snapshot of one agent. `state` is that of its most active replica, or
+/// `Degraded` when only some of its replicas are up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentStatus {
+    pub name: String,
+    pub runtime: String,
+    pub state: AgentState,
+    pub reason: Option<String>,
+    /// RFC3339 time of the last transition.
+    pub since: String,
+    /// Starts across all replicas.
+    pub starts: u32,
+    pub replicas: Vec<ReplicaStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicaStatus {
+    pub replica: u32,
+    pub state: AgentState,
+    pub reason: Option<String>,
+    pub since: String,
+    pub starts: u32,
+    /// From the last probe, for agents with a `health_check_interval`.
+    pub health: Option<Health>,
+}
+
+/// How an agent instance ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exit {
+    /// Exited on its own with success.
+    Completed,
+    Failed(String),
+    /// Stopped for going over a resource limit.
+    Breach(LimitBreach),
+}
+
+/// A running agent, owned by the manager until it exits or is stopped.
+#[async_trait]
+pub trait AgentInstance: Send {
+    /// Resolve once the instance exits on its own.
+    async fn wait(&mut self) -> Exit;
+    /// Ask the instance to shut down and wait for it.
+    async fn stop(&mut self) -> anyhow::Result<()>;
+    /// A way to ping the instance while it runs, if its runtime has one.
+    fn probe(&self) -> Option<Arc<dyn Probe>> {
+        None
+    }
+}
+
+/// Launches agents for one or more manifest runtimes.
+#[async_trait]
+pub trait AgentAdapter: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn supports(&self, manifest: &AgentManifest) -> bool;
+    /// Start replica `replica` of the agent. Replicas share the agent's bus
+    /// identity, and its subscriptions as a queue group.
+    async fn start(&self, manifest: &AgentManifest, replica: u32) -> anyhow::Result<Box<dyn AgentInstance>>;
+}
+
+#[derive(Debug, Error)]
+pub enum AgentError {
+    #[error("unknown agent {0}")]
+    Unknown(String),
+    #[error("agent {0} is already registered")]
+    Duplicate(String),
+    #[error("agent {agent}: cannot go from {from} to {to}")]
+    Transition { agent: String, from: AgentState, to: AgentState },
+    #[error("no adapter for runtime {runtime:?} (agent {agent})")]
+    NoAdapter { agent: String, runtime: String },
+    #[error("agent {agent} failed to start: {msg}")]
+    Start { agent: String, msg: String },
+    #[error(transparent)]
+    Manifest(#[from] ManifestError),
+    #[error(transparent)]
+    Supervisor(#[from] SupervisorError),
+    #[error("observability.health_check_interval: {0}")]
+    Health(#[from] HealthError),
+    #[error("security.{0}")]
+    Rbac(#[from] RbacError),
+    #[error("security.encryption: {0}")]
+    Encryption(#[from] SealError),
+    #[error("agent {agent} requires sealed payloads, which the {adapter} adapter cannot produce")]
+    Unsealed { agent: String, adapter: &'static str },
+    #[error(transparent)]
+    Resources(#[from] ResourceError),
+}
+
+struct Entry {
+    manifest: Arc<AgentManifest>,
+    policy: RestartPolicy,
+    health_check_interval: Option<Duration>,
+    replicas: BTreeMap<u32, Replica>,
+    /// Registration, or the last scale; reported while there are no replicas.
+    since: OffsetDateTime,
+}
+
+struct Replica {
+    state: AgentState,
+    reason: Option<String>,
+    since: OffsetDateTime,
+    starts: u32,
+    /// When the replica last came up.
+    up_since: Option<Instant>,
+    restarts: Restarts,
+    health: Option<Health>,
+    /// Bumped by every start and stop, so a scheduled restart can tell
+    /// whether it still applies.
+    generation: u64,
+    /// Stops the instance; a reason means it is being failed.
+    stop_tx: Option<oneshot::Sender<Option<String>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Replica {
+    fn new() -> Self {
+        Self {
+            state: AgentState::Pending,
+            reason: None,
+            since: OffsetDateTime::now_utc(),
+            starts: 0,
+            up_since: None,
+            restarts: Restarts::default(),
+            health: None,
+            generation: 0,
+            stop_tx: None,
+            task: None,
+        }
+    }
+}
+
+/// Registers agents from manifests, keeps their replicas running and drives
+/// them through `AgentState`, publishing every transition on
+/// `fabric.lifecycle`.
+pub struct AgentManager {
+    bus: Arc<Bus>,
+    config: SupervisorConfig,
+    adapters: RwLock<Vec<Arc<dyn AgentAdapter>>>,
+    policy: RwLock<Option<Arc<PolicyEngine>>>,
+    identity: RwLock<Option<Arc<NodeIdentity>>>,
+    blobs: RwLock<Option<Arc<dyn BlobStore>>>,
+    scopes: Arc<ScopeGuard>,
+    agents: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl AgentManager {
+    pub fn new(bus: Arc<Bus>) -> Arc<Self> {
+        Self::with_config(bus, SupervisorConfig::default())
+    }
+
+    pub fn with_config(bus: Arc<Bus>, config: SupervisorConfig) -> Arc<Self> {
+        let scopes = Arc::new(ScopeGuard::new(&bus));
+        bus.add_filter(scopes.clone());
+        Arc::new(Self {
+            bus,
+            config,
+            adapters: RwLock::new(Vec::new()),
+            policy: RwLock::new(None),
+            identity: RwLock::new(None),
+            blobs: RwLock::new(None),
+            scopes,
+            agents: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn bus(&self) -> &Arc<Bus> {
+        &self.bus
+    }
+
+    pub fn config(&self) -> &SupervisorConfig {
+        &self.config
+    }
+
+    /// Adapters are tried in the order they were added.
+    pub fn add_adapter(&self, adapter: Arc<dyn AgentAdapter>) {
+        self.adapters.write().push(adapter);
+    }
+
+    /// Enforce `policy` on the bus, with every registered agent (and every
+    /// agent registered later) as a known subject.
+    pub fn set_policy(&self, policy: Arc<PolicyEngine>) {
+        for entry in self.agents.lock().values() {
+            policy.admit(&entry.manifest);
+        }
+        self.bus.add_filter(policy.clone());
+        *self.policy.write() = Some(policy);
+    }
+
+    pub fn policy(&self) -> Option<Arc<PolicyEngine>> {
+        self.policy.read().clone()
+    }
+
+    /// Run agents registered from now on under `identity`'s
+    /// `agent://tenant/node/...` URIs, and verify signed envelopes on the
+    /// bus. Set it before registering agents: earlier ones keep
+    /// `agent://local/...`.
+    pub fn set_identity(&self, identity: Arc<NodeIdentity>) {
+        self.bus.add_filter(identity.verifier().clone());
+        *self.identity.write() = Some(identity);
+    }
+
+    pub fn identity(&self) -> Option<Arc<NodeIdentity>> {
+        self.identity.read().clone()
+    }
+
+    /// The node's claim-check store, for agents and SDK clients on this node
+    /// (`Agent::with_claim_check`).
+    pub fn set_blob_store(&self, store: Arc<dyn BlobStore>) {
+        *self.blobs.write() = Some(store);
+    }
+
+    pub fn blob_store(&self) -> Option<Arc<dyn BlobStore>> {
+        self.blobs.read().clone()
+    }
+
+    /// Whether `agent` (a registered name or an agent URI) may take
+    /// `action` on `resource`, and why.
+    pub fn can(&self, agent: &str, action: Action, resource: Resource) -> Result<Decision, AgentError> {
+        let uri = match self.manifest(agent) {
+            Some(manifest) => manifest.agent_uri(),
+            None if agent.starts_with("agent://") => agent.to_string(),
+            None => return Err(AgentError::Unknown(agent.to_string())),
+        };
+        Ok(match self.policy() {
+            Some(policy) => policy.check(&uri, action, resource),
+            None => Decision {
+                allowed: true,
+                rule: None,
+                explanation: format!("allowed, no policy is loaded: {} {} on {}", uri, action, resource),
+            },
+        })
+    }
+
+    /// Register an agent with `replicas` replicas, all `Pending`.
+    pub async fn register(&self, mut manifest: AgentManifest) -> Result<(), AgentError> {
+        if let Some(identity) = self.identity.read().as_ref() {
+            manifest.authority = Some(identity.authority());
+        }
+        let name = manifest.name.clone();
+        let policy = RestartPolicy::of(&manifest)?;
+        AccessLevel::of(&manifest)?;
+        ResourceLimits::from_map(&manifest.limits)?;
+        let mode: EncryptionMode = manifest.security.encryption.as_deref().unwrap_or_default().parse()?;
+        let sealed = mode.cipher()?.is_some();
+        // The kernel's adapters have no recipient keys to seal with, so an
+        // `aes256` agent would have every publish refused.
+        if let Some(adapter) = self.adapters.read().iter().find(|a| sealed && a.supports(&manifest)) {
+            return Err(AgentError::Unsealed { agent: name, adapter: adapter.name() });
+        }
+        let health_check_interval =
+            manifest.observability.health_check_interval.as_deref().map(health::parse_duration).transpose()?;
+        {
+            let mut agents = self.agents.lock();
+            if agents.contains_key(&name) {
+                return Err(AgentError::Duplicate(name));
+            }
+            if let Some(errors) = manifest.errors.first() {
+                self.bus.set_errors_topic(manifest.agent_uri(), errors.clone());
+            }
+            self.scopes.admit(&manifest);
+            if let Some(policy) = self.policy.read().as_ref() {
+                policy.admit(&manifest);
+            }
+            if sealed && !manifest.publish.is_empty() {
+                self.bus.add_filter(Arc::new(RequireSealed::new(manifest.publish.clone())));
+            }
+            let replicas = (0..manifest.replicas).map(|r| (r, Replica::new())).collect();
+            agents.insert(
+                name.clone(),
+                Entry {
+                    manifest: Arc::new(manifest),
+                    policy,
+                    health_check_interval,
+                    replicas,
+                    since: OffsetDateTime::now_utc(),
+                },
+            );
+        }
+        info!("agent {} registered", name);
+        self.publish(LifecycleEvent {
+            agent: name,
+            replica: 0,
+            from: AgentState::Pending,
+            to: AgentState::Pending,
+            reason: Some("registered".into()),
+            breach: None,
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Register a manifest file, or every agent of a `kind: Deployment` file.
+    pub async fn register_path(&self, path: impl AsRef<Path>) -> Result<Vec<String>, AgentError> {
+        let path = path.as_ref();
+        let manifests = if path.is_dir() {
+            manifest::load_dir(path)?
+        } else {
+            match AgentManifest::load(path) {
+                Ok(m) => vec![m],
+                Err(ManifestError::Kind { found: Some(k), .. }) if k == "Deployment" => manifest::load_deployment(path)?,
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let mut names = Vec::new();
+        for m in manifests {
+            names.push(m.name.clone());
+            self.register(m).await?;
+        }
+        Ok(names)
+    }
+
+    pub fn manifest(&self, name: &str) -> Option<Arc<AgentManifest>> {
+        self.agents.lock().get(name).map(|e| e.manifest.clone())
+    }
+
+    pub fn status(&self, name: &str) -> Option<AgentStatus> {
+        self.agents.lock().get(name).map(|e| status(name, e))
+    }
+
+    pub fn list(&self) -> Vec<AgentStatus> {
+        self.agents.lock().iter().map(|(n, e)| status(n, e)).collect()
+    }
+
+    /// Start every replica that isn't already up or on its way.
+    pub async fn start(self: &Arc<Self>, name: &str) -> Result<(), AgentError> {
+        let ids = {
+            let agents = self.agents.lock();
+            let entry = agents.get(name).ok_or_else(|| AgentError::Unknown(name.to_string()))?;
+            let ids: Vec<u32> =
+                entry.replicas.iter().filter(|(_, r)| r.state.can_transition(AgentState::Starting)).map(|(id, _)| *id).collect();
+            if ids.is_empty() && !entry.replicas.is_empty() {
+                let from = status(name, entry).state;
+                return Err(AgentError::Transition { agent: name.to_string(), from, to: AgentState::Starting });
+            }
+            ids
+        };
+        let mut result = Ok(());
+        for id in ids {
+            if let Err(e) = self.start_replica(name, id).await {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Stop every replica, waiting until their instances are gone, and
+    /// cancel any pending restarts.
+    pub async fn stop(&self, name: &str) -> Result<(), AgentError> {
+        let ids: Vec<u32> = {
+            let agents = self.agents.lock();
+            let entry = agents.get(name).ok_or_else(|| AgentError::Unknown(name.to_string()))?;
+            entry.replicas.keys().copied().collect()
+        };
+        for id in ids {
+            self.halt(name, id, None).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn restart(self: &Arc<Self>, name: &str) -> Result<(), AgentError> {
+        self.stop(name).await?;
+        self.start(name).await
+    }
+
+    /// Stop every agent.
+    pub async fn stop_all(&self) {
+        let names: Vec<String> = self.agents.lock().keys().cloned().collect();
+        for name in names {
+            if let Err(e) = self.stop(&name).await {
+                warn!("stopping {}: {}", name, e);
+            }
+        }
+    }
+
+    /// Add or remove replicas until there are `replicas`. Added replicas
+    /// start if any of the agent's replicas is up or starting; removed ones
+    /// (the newest first) are stopped. Other replicas are left alone.
+    pub async fn scale(self: &Arc<Self>, name: &str, replicas: u32) -> Result<(), AgentError> {
+        let (added, removed, active) = {
+            let mut agents = self.agents.lock();
+            let entry = agents.get_mut(name).ok_or_else(|| AgentError::Unknown(name.to_string()))?;
+            let active = entry.replicas.values().any(|r| r.state.is_up() || r.state == AgentState::Starting);
+            let current = entry.replicas.len() as u32;
+            let removed: Vec<u32> = entry.replicas.keys().rev().take(current.saturating_sub(replicas) as usize).copied().collect();
+            let next = entry.replicas.keys().next_back().map_or(0, |id| id + 1);
+            let added: Vec<u32> = (next..next + replicas.saturating_sub(current)).collect();
+            for id in &added {
+                entry.replicas.insert(*id, Replica::new());
+            }
+            entry.since = OffsetDateTime::now_utc();
+            (added, removed, active)
+        };
+        if !added.is_empty() || !removed.is_empty() {
+            info!("agent {}: scaling to {} replica(s)", name, replicas);
+        }
+        for id in removed {
+            self.halt(name, id, None).await?;
+            let uri = self.agents.lock().get_mut(name).map(|entry| {
+                entry.replicas.remove(&id);
+                entry.manifest.agent_uri()
+            });
+            if let Some(uri) = uri {
+                self.bus.set_queue_ready(&uri, &id.to_string(), true);
+            }
+        }
+        for &id in &added {
+            self.publish(LifecycleEvent {
+                agent: name.to_string(),
+                replica: id,
+                from: AgentState::Pending,
+                to: AgentState::Pending,
+                reason: Some("scaled up".into()),
+                breach: None,
+            })
+            .await;
+        }
+        if active {
+            for id in added {
+                if let Err(e) = self.start_replica(name, id).await {
+                    warn!("{}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark every running replica degraded (or recovered, with `reason: None`).
+    pub async fn set_degraded(&self, name: &str, reason: Option<String>) -> Result<(), AgentError> {
+        let to = if reason.is_some() { AgentState::Degraded } else { AgentState::Running };
+        let ids: Vec<u32> = {
+            let agents = self.agents.lock();
+            let entry = agents.get(name).ok_or_else(|| AgentError::Unknown(name.to_string()))?;
+            entry.replicas.iter().filter(|(_, r)| r.state.is_up() && r.state != to).map(|(id, _)| *id).collect()
+        };
+        for id in ids {
+            self.transition(name, id, to, reason.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Boxed so the `start_replica` → `exited` → `start_replica` cycle has a
+    /// nameable type.
+    fn start_replica<'a>(
+        self: &'a Arc<Self>,
+        name: &'a str,
+        id: u32,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let manifest = self.transition(name, id, AgentState::Starting, None).await?;
+            let generation = self
+                .with_replica(name, id, |r| {
+                    r.generation += 1;
+                    r.health = None;
+                    r.generation
+                })
+                .unwrap_or_default();
+            self.bus.set_queue_ready(&manifest.agent_uri(), &id.to_string(), true);
+            let adapter = self.adapters.read().iter().find(|a| a.supports(&manifest)).cloned();
+            let Some(adapter) = adapter else {
+                let err = AgentError::NoAdapter { agent: name.to_string(), runtime: manifest.runtime.clone() };
+                self.transition(name, id, AgentState::Failed, Some(err.to_string())).await?;
+                return Err(err);
+            };
+            let mut instance = match adapter.start(&manifest, id).await {
+                Ok(i) => i,
+                Err(e) => {
+                    let msg = format!("{:#}", e);
+                    self.transition(name, id, AgentState::Failed, Some(msg.clone())).await?;
+                    self.supervise(name, id, &Exit::Failed(msg.clone()), None).await;
+                    return Err(AgentError::Start { agent: name.to_string(), msg });
+                }
+            };
+
+            let probe = instance.probe();
+            let (stop_tx, stop_rx) = oneshot::channel::<Option<String>>();
+            let manager = self.clone();
+            let agent = name.to_string();
+            let task = tokio::spawn(async move {
+                tokio::select! {
+                    exit = instance.wait() => manager.exited(&agent, id, exit).await,
+                    failure = stop_rx => {
+                        let result = instance.stop().await;
+                        if let Ok(Some(reason)) = failure {
+                            return manager.failed(&agent, id, reason).await;
+                        }
+                        let (to, reason) = match result {
+                            Ok(()) => (AgentState::Stopped, None),
+                            Err(e) => (AgentState::Failed, Some(format!("stop failed: {:#}", e))),
+                        };
+                        let _ = manager.transition(&agent, id, to, reason).await;
+                    }
+                }
+            });
+            let stale = self.with_replica(name, id, |r| {
+                if r.generation != generation {
+                    return Some(stop_tx);
+                }
+                r.starts += 1;
+                r.stop_tx = Some(stop_tx);
+                r.task = Some(task);
+                None
+            });
+            // Stopped while starting: the instance is no longer wanted.
+            if let Some(Some(stop_tx)) = stale {
+                let _ = stop_tx.send(None);
+            }
+            // A stop request may have raced the start; only then is this refused.
+            match self.transition(name, id, AgentState::Running, None).await {
+                Ok(_) => {}
+                Err(AgentError::Transition { .. }) | Err(AgentError::Unknown(_)) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            let every = self.agents.lock().get(name).and_then(|e| e.health_check_interval);
+            if let (Some(probe), Some(every)) = (probe, every) {
+                tokio::spawn(health::watch(self.clone(), name.to_string(), id, generation, probe, every));
+            }
+            Ok(())
+        })
+    }
+
+    /// Stop a replica, or with `failure`, fail it and leave it to its
+    /// restart policy.
+    async fn halt(&self, name: &str, id: u32, failure: Option<String>) -> Result<(), AgentError> {
+        let Some(state) = self.with_replica(name, id, |r| {
+            r.generation += 1;
+            r.state
+        }) else {
+            return Ok(());
+        };
+        match state {
+            AgentState::Starting | AgentState::Running | AgentState::Degraded => {}
+            AgentState::Failed | AgentState::CrashLoop => {
+                self.transition(name, id, AgentState::Stopped, Some("stopped".into())).await?;
+                return Ok(());
+            }
+            AgentState::Pending | AgentState::Stopping | AgentState::Stopped => return Ok(()),
+        }
+        self.transition(name, id, AgentState::Stopping, None).await?;
+        let (stop_tx, task) = self.with_replica(name, id, |r| (r.stop_tx.take(), r.task.take())).unwrap_or_default();
+        match (stop_tx, task) {
+            (Some(tx), Some(task)) => {
+                // If the instance already exited, the task has recorded it.
+                let _ = tx.send(failure);
+                if let Err(e) = task.await {
+                    warn!("agent {} replica {} supervisor task: {}", name, id, e);
+                }
+            }
+            _ => {
+                self.transition(name, id, AgentState::Stopped, None).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn exited(self: &Arc<Self>, name: &str, id: u32, exit: Exit) {
+        let (to, reason, breach) = match &exit {
+            Exit::Completed => (AgentState::Stopped, Some("exited".to_string()), None),
+            Exit::Failed(reason) => (AgentState::Failed, Some(reason.clone()), None),
+            Exit::Breach(breach) => (AgentState::Failed, Some(breach.to_string()), Some(breach.clone())),
+        };
+        let ran_for = self
+            .with_replica(name, id, |r| {
+                r.stop_tx = None;
+                r.task = None;
+                r.up_since.take().map(|t| t.elapsed())
+            })
+            .flatten();
+        if let Err(e) = self.transition_with(name, id, to, reason, breach).await {
+            warn!("{}", e);
+            return;
+        }
+        self.supervise(name, id, &exit, ran_for).await;
+    }
+
+    /// Record a replica the manager stopped for failing, and supervise it.
+    async fn failed(self: &Arc<Self>, name: &str, id: u32, reason: String) {
+        let ran_for = self.with_replica(name, id, |r| r.up_since.take().map(|t| t.elapsed())).flatten();
+        if let Err(e) = self.transition(name, id, AgentState::Failed, Some(reason.clone())).await {
+            warn!("{}", e);
+            return;
+        }
+        self.supervise(name, id, &Exit::Failed(reason), ran_for).await;
+    }
+
+    /// Whether `generation` is still the replica's current, running instance.
+    pub(crate) fn is_current(&self, name: &str, id: u32, generation: u64) -> bool {
+        self.with_replica(name, id, |r| r.generation == generation && r.state.is_up()) == Some(true)
+    }
+
+    /// Record a probe of a replica's current instance, acting on changes in
+    /// its health. Returns whether to keep probing it.
+    pub(crate) async fn probed(
+        self: &Arc<Self>,
+        name: &str,
+        id: u32,
+        generation: u64,
+        result: Result<ProbeReply, String>,
+    ) -> bool {
+        let checked = rfc3339(OffsetDateTime::now_utc());
+        let update = {
+            let mut agents = self.agents.lock();
+            let Some(entry) = agents.get_mut(name) else { return false };
+            let manifest = entry.manifest.clone();
+            let Some(replica) = entry.replicas.get_mut(&id) else { return false };
+            if replica.generation != generation || !replica.state.is_up() {
+                return false;
+            }
+            let previous = replica.health.take();
+            let health = match result {
+                Ok(reply) => Health { live: true, ready: reply.ready, detail: reply.detail, failures: 0, checked },
+                Err(detail) => {
+                    let failures = previous.as_ref().map_or(0, |h| h.failures) + 1;
+                    Health { live: failures < FAILURE_THRESHOLD, ready: false, detail: Some(detail), failures, checked }
+                }
+            };
+            replica.health = Some(health.clone());
+            let changed = previous.is_none_or(|p| (p.live, p.ready) != (health.live, health.ready));
+            (manifest, replica.state, health, changed)
+        };
+        let (manifest, state, health, changed) = update;
+        if !changed {
+            return true;
+        }
+        self.bus.set_queue_ready(&manifest.agent_uri(), &id.to_string(), health.live && health.ready);
+        self.publish_health(&manifest, id, &health).await;
+        let detail = health.detail.clone().unwrap_or_default();
+        if !health.live {
+            let reason = format!("failed {} health checks: {}", health.failures, detail);
+            if let Err(e) = self.halt(name, id, Some(reason)).await {
+                warn!("{}", e);
+            }
+            return false;
+        }
+        let result = match (health.ready, state) {
+            (false, AgentState::Running) => {
+                let reason = if detail.is_empty() { "not ready".to_string() } else { format!("not ready: {}", detail) };
+                self.transition(name, id, AgentState::Degraded, Some(reason)).await
+            }
+            (true, AgentState::Degraded) => self.transition(name, id, AgentState::Running, None).await,
+            _ => return true,
+        };
+        if let Err(e) = result {
+            warn!("{}", e);
+        }
+        true
+    }
+
+    async fn publish_health(&self, manifest: &AgentManifest, id: u32, health: &Health) {
+        let event = HealthEvent { agent: manifest.name.clone(), replica: id, health: health.clone() };
+        let payload = match serde_json::to_value(&event) {
+            Ok(p) => p,
+            Err(e) => return warn!("health event: {}", e),
+        };
+        let topics = std::iter::once(HEALTH_TOPIC).chain(manifest.observability.metrics_topic.as_deref());
+        for topic in topics {
+            let env = Envelope::new(KERNEL_SRC, topic, HealthEvent::ctype(), payload.clone()).ensure_trace();
+            if let Err(e) = self.bus.publish(topic, env).await {
+                warn!("publishing health of {}/{} to {}: {}", manifest.name, id, topic, e);
+            }
+        }
+    }
+
+    /// Schedule a restart of a replica that exited, if its policy wants one,
+    /// or park it in `CrashLoop`.
+    async fn supervise(self: &Arc<Self>, name: &str, id: u32, exit: &Exit, ran_for: Option<Duration>) {
+        let verdict = {
+            let mut agents = self.agents.lock();
+            let Some(entry) = agents.get_mut(name) else { return };
+            let policy = entry.policy;
+            let Some(replica) = entry.replicas.get_mut(&id) else { return };
+            if !policy.restarts(exit) {
+                return;
+            }
+            (replica.restarts.record(&self.config, Instant::now(), ran_for), replica.generation)
+        };
+        match verdict {
+            (Verdict::Restart(delay), generation) => {
+                info!("agent {} replica {}: restarting in {:?}", name, id, delay);
+                tokio::spawn(self.clone().restart_after(name.to_string(), id, generation, delay));
+            }
+            (Verdict::CrashLoop(exits), _) => {
+                let reason = format!("exited {} times within {:?}", exits, self.config.crash_loop_window);
+                if let Err(e) = self.transition(name, id, AgentState::CrashLoop, Some(reason)).await {
+                    warn!("{}", e);
+                }
+            }
+        }
+    }
+
+    async fn restart_after(self: Arc<Self>, name: String, id: u32, generation: u64, delay: Duration) {
+        tokio::time::sleep(delay).await;
+        // Stopped, restarted or scaled away in the meantime.
+        let current = self.with_replica(&name, id, |r| r.generation == generation && r.state.can_transition(AgentState::Starting));
+        if current != Some(true) {
+            return;
+        }
+        if let Err(e) = self.start_replica(&name, id).await {
+            warn!("restarting {} replica {}: {}", name, id, e);
+        }
+    }
+
+    fn with_replica<T>(&self, name: &str, id: u32, f: impl FnOnce(&mut Replica) -> T) -> Option<T> {
+        self.agents.lock().get_mut(name).and_then(|e| e.replicas.get_mut(&id)).map(f)
+    }
+
+    /// Apply a transition and publish it. Returns the agent's manifest.
+    async fn transition(
+        &self,
+        name: &str,
+        id: u32,
+        to: AgentState,
+        reason: Option<String>,
+    ) -> Result<Arc<AgentManifest>, AgentError> {
+        self.transition_with(name, id, to, reason, None).await
+    }
+
+    async fn transition_with(
+        &self,
+        name: &str,
+        id: u32,
+        to: AgentState,
+        reason: Option<String>,
+        breach: Option<LimitBreach>,
+    ) -> Result<Arc<AgentManifest>, AgentError> {
+        let (from, manifest) = {
+            let mut agents = self.agents.lock();
+            let entry = agents.get_mut(name).ok_or_else(|| AgentError::Unknown(name.to_string()))?;
+            let replica = entry.replicas.get_mut(&id).ok_or_else(|| AgentError::Unknown(format!("{}/{}", name, id)))?;
+            let from = replica.state;
+            if !from.can_transition(to) {
+                return Err(AgentError::Transition { agent: name.to_string(), from, to });
+            }
+            replica.state = to;
+            replica.reason = reason.clone();
+            replica.since = OffsetDateTime::now_utc();
+            if to == AgentState::Running && from == AgentState::Starting {
+                replica.up_since = Some(Instant::now());
+            }
+            (from, entry.manifest.clone())
+        };
+        match &reason {
+            Some(r) if matches!(to, AgentState::Failed | AgentState::CrashLoop) => {
+                warn!("agent {}/{}: {} → {} ({})", name, id, from, to, r)
+            }
+            Some(r) => info!("agent {}/{}: {} → {} ({})", name, id, from, to, r),
+            None => info!("agent {}/{}: {} → {}", name, id, from, to),
+        }
+        self.publish(LifecycleEvent { agent: name.to_string(), replica: id, from, to, reason, breach }).await;
+        Ok(manifest)
+    }
+
+    async fn publish(&self, event: LifecycleEvent) {
+        let payload = match serde_json::to_value(&event) {
+            Ok(p) => p,
+            Err(e) => return warn!("lifecycle event: {}", e),
+        };
+        let env = Envelope::new(KERNEL_SRC, LIFECYCLE_TOPIC, LifecycleEvent::ctype(), payload).ensure_trace();
+        if let Err(e) = self.bus.publish(LIFECYCLE_TOPIC, env).await {
+            warn!("publishing lifecycle event for {}: {}", event.agent, e);
+        }
+    }
+}
+
+fn rfc3339(t: OffsetDateTime) -> String {
+    t.format(&time::format_description::well_known::Rfc3339).unwrap_or_default()
+}
+
+fn status(name: &str, e: &Entry) -> AgentStatus {
+    let replicas: Vec<ReplicaStatus> = e
+        .replicas
+        .iter()
+        .map(|(id, r)| ReplicaStatus {
+            replica: *id,
+            state: r.state,
+            reason: r.reason.clone(),
+            since: rfc3339(r.since),
+            starts: r.starts,
+            health: r.health.clone(),
+        })
+        .collect();
+    let up = replicas.iter().filter(|r| r.state.is_up()).count();
+    let (state, reason, since) = match replicas.iter().max_by_key(|r| r.state.rank()) {
+        None => (AgentState::Stopped, Some("no replicas".to_string()), rfc3339(e.since)),
+        Some(lead) if up > 0 && up < replicas.len() => {
+            (AgentState::Degraded, Some(format!("{}/{} replicas up", up, replicas.len())), lead.since.clone())
+        }
+        Some(lead) => (lead.state, lead.reason.clone(), lead.since.clone()),
+    };
+    AgentStatus {
+        name: name.to_string(),
+        runtime: e.manifest.runtime.clone(),
+        state,
+        reason,
+        since,
+        starts: replicas.iter().map(|r| r.starts).sum(),
+        replicas,
+    }
+}
@@ -0,0 +1,200 @@
+//! Node-local, content-addressed blob store backing envelope claim checks.
+
+use openi_core_fabric::{BlobError, BlobRef, BlobStore};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Filesystem `BlobStore`.
+///
+/// Layout under `root`: `sha256/<aa>/<hex>` holds the bytes and
+/// `sha256/<aa>/<hex>.leases` a JSON map of holder → expiry (epoch ms, or
+/// null for "until released"). Writes go through a temp file and rename so
+/// a crash never leaves a partially written blob at its address.
+pub struct FsBlobStore {
+    root: PathBuf,
+    // Serialises lease read-modify-write cycles within this process.
+    leases: Mutex<()>,
+}
+
+type Leases = BTreeMap<String, Option<u64>>;
+
+impl FsBlobStore {
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self, BlobError> {
+        let root = root.into();
+        fs::create_dir_all(root.join("sha256"))?;
+        fs::create_dir_all(root.join("tmp"))?;
+        Ok(Self { root, leases: Mutex::new(()) })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Run `gc` every `every` on the current tokio runtime.
+    pub fn spawn_gc(self: Arc<Self>, every: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(every);
+            loop {
+                ticker.tick().await;
+                match self.gc(SystemTime::now()) {
+                    Ok(0) => {}
+                    Ok(n) => info!("blob gc removed {} unreferenced blob(s)", n),
+                    Err(e) => warn!("blob gc failed: {}", e),
+                }
+            }
+        })
+    }
+
+    fn blob_path(&self, blob: &BlobRef) -> PathBuf {
+        let hex = blob.hex();
+        self.root.join("sha256").join(&hex[..2]).join(hex)
+    }
+
+    fn lease_path(&self, blob: &BlobRef) -> PathBuf {
+        self.blob_path(blob).with_extension("leases")
+    }
+
+    fn read_leases(&self, blob: &BlobRef) -> Result<Leases, BlobError> {
+        match fs::read(self.lease_path(blob)) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| BlobError::Payload(e.to_string())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Leases::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_leases(&self, blob: &BlobRef, leases: &Leases) -> Result<(), BlobError> {
+        let bytes = serde_json::to_vec(leases).map_err(|e| BlobError::Payload(e.to_string()))?;
+        self.write_atomic(&self.lease_path(blob), &bytes)
+    }
+
+    /// Add or extend `holder`'s lease; the caller holds `leases`.
+    fn add_lease(&self, blob: &BlobRef, holder: &str, until: Option<SystemTime>) -> Result<(), BlobError> {
+        let mut leases = self.read_leases(blob)?;
+        let until = until.map(epoch_ms);
+        // Never shorten an existing lease held by the same holder.
+        let merged = match leases.get(holder) {
+            Some(None) => None,
+            Some(Some(cur)) => until.map(|u| u.max(*cur)),
+            None => until,
+        };
+        leases.insert(holder.to_string(), merged);
+        self.write_leases(blob, &leases)
+    }
+
+    fn write_atomic(&self, path: &Path, bytes: &[u8]) -> Result<(), BlobError> {
+        let tmp = self.root.join("tmp").join(ulid::Ulid::new().to_string());
+        fs::write(&tmp, bytes)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+fn epoch_ms(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+fn live(until: &Option<u64>, now_ms: u64) -> bool {
+    until.is_none_or(|u| u > now_ms)
+}
+
+impl BlobStore for FsBlobStore {
+    fn put(&self, bytes: &[u8]) -> Result<BlobRef, BlobError> {
+        let blob = BlobRef::of(bytes);
+        let path = self.blob_path(&blob);
+        if !path.exists() {
+            self.write_atomic(&path, bytes)?;
+        }
+        Ok(blob)
+    }
+
+    fn put_retained(&self, bytes: &[u8], holder: &str, until: Option<SystemTime>) -> Result<BlobRef, BlobError> {
+        // `gc` takes the same lock, so the blob is never unleased on disk.
+        let _guard = self.leases.lock();
+        let blob = self.put(bytes)?;
+        self.add_lease(&blob, holder, until)?;
+        Ok(blob)
+    }
+
+    fn get(&self, blob: &BlobRef) -> Result<Vec<u8>, BlobError> {
+        let bytes = match fs::read(self.blob_path(blob)) {
+            Ok(b) => b,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(BlobError::NotFound(*blob)),
+            Err(e) => return Err(e.into()),
+        };
+        if !blob.matches(&bytes) {
+            return Err(BlobError::Corrupt(*blob));
+        }
+        Ok(bytes)
+    }
+
+    fn contains(&self, blob: &BlobRef) -> bool {
+        self.blob_path(blob).exists()
+    }
+
+    fn retain(&self, blob: &BlobRef, holder: &str, until: Option<SystemTime>) -> Result<(), BlobError> {
+        let _guard = self.leases.lock();
+        if !self.contains(blob) {
+            return Err(BlobError::NotFound(*blob));
+        }
+        self.add_lease(blob, holder, until)
+    }
+
+    fn release(&self, blob: &BlobRef, holder: &str) -> Result<(), BlobError> {
+        let _guard = self.leases.lock();
+        let mut leases = self.read_leases(blob)?;
+        if leases.remove(holder).is_some() {
+            self.write_leases(blob, &leases)?;
+        }
+        Ok(())
+    }
+
+    fn refcount(&self, blob: &BlobRef) -> Result<usize, BlobError> {
+        let now_ms = epoch_ms(SystemTime::now());
+        Ok(self.read_leases(blob)?.values().filter(|u| live(u, now_ms)).count())
+    }
+
+    fn gc(&self, now: SystemTime) -> Result<usize, BlobError> {
+        let now_ms = epoch_ms(now);
+        let _guard = self.leases.lock();
+        let mut removed = 0;
+        for shard in fs::read_dir(self.root.join("sha256"))? {
+            let shard = shard?.path();
+            if !shard.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&shard)? {
+                let path = entry?.path();
+                if path.extension().is_some() {
+                    continue;
+                }
+                let Some(blob) = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .and_then(|hex| format!("blob://sha256/{}", hex).parse::<BlobRef>().ok())
+                else {
+                    continue;
+                };
+                let mut leases = self.read_leases(&blob)?;
+                let before = leases.len();
+                leases.retain(|_, until| live(until, now_ms));
+                if leases.is_empty() {
+                    fs::remove_file(&path)?;
+                    let _ = fs::remove_file(self.lease_path(&blob));
+                    removed += 1;
+                } else if leases.len() != before {
+                    self.write_leases(&blob, &leases)?;
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
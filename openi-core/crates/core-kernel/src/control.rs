@@ -0,0 +1,164 @@
+//! Control socket: how the `openi` CLI queries and steers a running node.
+//!
+//! The node listens on a Unix socket (`OPENI_CONTROL_SOCKET`, by default
+//! `node.sock` in the node's runtime dir, see `rundir`). The socket's
+//! directory must be private to the node's user, and only clients running
+//! as that user are served. A client runs the `FramedConnection`
+//! handshake as initiator, then sends `fabric.control.request.v1` envelopes
+//! and gets a `fabric.control.reply.v1` envelope back for each.
+
+use crate::agents::{AgentManager, AgentStatus};
+use crate::policy::{Action, Decision, Resource};
+use crate::rundir;
+use openi_core_fabric::{Codec, ContentType, Envelope, FramedConnection, Role, TransportError};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::net::UnixStream;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Topic control requests are addressed to and kernel reports are published on.
+pub const CONTROL_TOPIC: &str = "fabric.control";
+/// Source of envelopes the control socket replies with.
+pub const CONTROL_SRC: &str = "agent://fabric/control";
+/// Source the CLI sends requests from.
+pub const CLI_SRC: &str = "agent://local/cli";
+
+#[derive(Debug, Error)]
+pub enum ControlError {
+    #[error("control socket {path}: {source}")]
+    Connect { path: PathBuf, source: std::io::Error },
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+    #[error("control payload: {0}")]
+    Payload(#[from] serde_json::Error),
+    #[error("node closed the control connection")]
+    Closed,
+}
+
+/// Payload of `fabric.control.request.v1` envelopes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Status of every agent.
+    Agents,
+    /// Add or remove replicas of an agent.
+    Scale { agent: String, replicas: u32 },
+    /// Ask the node's policy whether an agent may take an action.
+    Can { agent: String, action: Action, resource: Resource },
+}
+
+impl ControlRequest {
+    pub fn ctype() -> ContentType {
+        ContentType::logical("fabric.control", "request", 1)
+    }
+}
+
+/// Payload of `fabric.control.reply.v1` envelopes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ControlReply {
+    Agents { agents: Vec<AgentStatus> },
+    Decision { decision: Decision },
+    Done,
+    Error { message: String },
+}
+
+impl ControlReply {
+    pub fn ctype() -> ContentType {
+        ContentType::logical("fabric.control", "reply", 1)
+    }
+}
+
+/// `OPENI_CONTROL_SOCKET`, or the default path.
+pub fn socket_path() -> PathBuf {
+    std::env::var_os("OPENI_CONTROL_SOCKET")
+        .map_or_else(|| rundir::dir().join("node.sock"), PathBuf::from)
+}
+
+/// Listen on `path`, answering requests against `manager`.
+pub fn serve(manager: Arc<AgentManager>, path: &Path) -> std::io::Result<JoinHandle<()>> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        rundir::ensure(dir)?;
+    }
+    let listener = rundir::bind(path)?;
+    info!("Control socket listening on {}", path.display());
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => match rundir::check_peer(&stream, None) {
+                    Ok(()) => {
+                        tokio::spawn(session(manager.clone(), stream));
+                    }
+                    Err(e) => warn!("control socket: refusing connection: {}", e),
+                },
+                Err(e) => {
+                    warn!("control socket: {}", e);
+                    break;
+                }
+            }
+        }
+    }))
+}
+
+async fn session(manager: Arc<AgentManager>, stream: UnixStream) {
+    let (reader, writer) = stream.into_split();
+    let mut conn = match FramedConnection::handshake(reader, writer, Role::Acceptor, &Codec::ALL).await {
+        Ok(conn) => conn,
+        Err(e) => return warn!("control socket: {}", e),
+    };
+    loop {
+        let env = match conn.recv::<serde_json::Value>().await {
+            Ok(Some(env)) => env,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("control socket: {}", e);
+                break;
+            }
+        };
+        let reply = match serde_json::from_value(env.payload.clone()) {
+            Ok(request) => handle(&manager, request).await,
+            Err(e) => ControlReply::Error { message: format!("bad request: {}", e) },
+        };
+        let reply = match serde_json::to_value(&reply) {
+            Ok(payload) => Envelope::new(CONTROL_SRC, env.src.clone(), ControlReply::ctype(), payload).child_of(&env),
+            Err(e) => return warn!("control reply: {}", e),
+        };
+        if let Err(e) = conn.send(&reply).await {
+            warn!("control socket: {}", e);
+            break;
+        }
+    }
+}
+
+async fn handle(manager: &Arc<AgentManager>, request: ControlRequest) -> ControlReply {
+    let result = match request {
+        ControlRequest::Agents => return ControlReply::Agents { agents: manager.list() },
+        ControlRequest::Scale { agent, replicas } => manager.scale(&agent, replicas).await,
+        ControlRequest::Can { agent, action, resource } => {
+            return match manager.can(&agent, action, resource) {
+                Ok(decision) => ControlReply::Decision { decision },
+                Err(e) => ControlReply::Error { message: e.to_string() },
+            }
+        }
+    };
+    match result {
+        Ok(()) => ControlReply::Done,
+        Err(e) => ControlReply::Error { message: e.to_string() },
+    }
+}
+
+/// Send one request to the node listening on `path`.
+pub async fn request(path: &Path, request: &ControlRequest) -> Result<ControlReply, ControlError> {
+    let stream =
+        UnixStream::connect(path).await.map_err(|source| ControlError::Connect { path: path.to_path_buf(), source })?;
+    let (reader, writer) = stream.into_split();
+    let mut conn = FramedConnection::handshake(reader, writer, Role::Initiator, &Codec::ALL).await?;
+    let env = Envelope::new(CLI_SRC, CONTROL_TOPIC, ControlRequest::ctype(), serde_json::to_value(request)?)
+        .ensure_trace();
+    conn.send(&env).await?;
+    let reply = conn.recv::<serde_json::Value>().await?.ok_or(ControlError::Closed)?;
+    Ok(serde_json::from_value(reply.payload)?)
+}
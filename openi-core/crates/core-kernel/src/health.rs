@@ -0,0 +1,137 @@
+//! Liveness and readiness probes for agent replicas.
+//!
+//! Agents whose manifest sets `observability.health_check_interval` are
+//! probed on that interval for as long as each replica is up. A probe is an
+//! RPC ping through the agent's runtime; each probe must finish within the
+//! interval. A replica that answers but isn't ready is marked `Degraded`
+//! and taken out of its queue group until it is; one that misses
+//! `FAILURE_THRESHOLD` probes in a row is failed and restarted under its
+//! restart policy. Every change is published on `fabric.health` (and the
+//! agent's `metrics_topic`, if it has one).
+
+use crate::agents::AgentManager;
+use async_trait::async_trait;
+use openi_core_fabric::ContentType;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::{interval, timeout, MissedTickBehavior};
+
+/// Topic health changes are published on.
+pub const HEALTH_TOPIC: &str = "fabric.health";
+/// Source of the pings the kernel sends agents.
+pub const HEALTH_SRC: &str = "agent://fabric/health";
+/// Consecutive failed probes before a replica counts as dead.
+pub const FAILURE_THRESHOLD: u32 = 3;
+
+#[derive(Debug, Error)]
+pub enum HealthError {
+    #[error("invalid duration {0:?} (expected e.g. 500ms, 30s, 5m)")]
+    Duration(String),
+}
+
+/// Parse a `health_check_interval` such as `500ms`, `30s`, `5m` or `1h`. A
+/// bare number is seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, HealthError> {
+    let s = s.trim();
+    let err = || HealthError::Duration(s.to_string());
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: f64 = number.parse().map_err(|_| err())?;
+    let seconds = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(err()),
+    };
+    Duration::try_from_secs_f64(seconds).ok().filter(|d| !d.is_zero()).ok_or_else(err)
+}
+
+/// What an agent answers a ping with. Payload of
+/// `fabric.health.pong.v1` envelopes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbeReply {
+    pub ready: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ProbeReply {
+    pub fn ready() -> Self {
+        Self { ready: true, detail: None }
+    }
+
+    pub fn ping_ctype() -> ContentType {
+        ContentType::logical("fabric.health", "ping", 1)
+    }
+
+    pub fn pong_ctype() -> ContentType {
+        ContentType::logical("fabric.health", "pong", 1)
+    }
+}
+
+/// Pings a running instance.
+#[async_trait]
+pub trait Probe: Send + Sync {
+    /// An answer within `within` means the instance is live; an error or
+    /// no answer means it isn't.
+    async fn probe(&self, within: Duration) -> anyhow::Result<ProbeReply>;
+}
+
+/// Last known health of a replica.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Health {
+    pub live: bool,
+    pub ready: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Probes failed in a row.
+    pub failures: u32,
+    /// RFC3339 time of the last probe.
+    pub checked: String,
+}
+
+/// Payload of `fabric.health.status.v1` envelopes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthEvent {
+    pub agent: String,
+    pub replica: u32,
+    #[serde(flatten)]
+    pub health: Health,
+}
+
+impl HealthEvent {
+    pub fn ctype() -> ContentType {
+        ContentType::logical("fabric.health", "status", 1)
+    }
+}
+
+/// Probe one replica every `every` until it is stopped or restarted.
+pub(crate) async fn watch(
+    manager: Arc<AgentManager>,
+    agent: String,
+    replica: u32,
+    generation: u64,
+    probe: Arc<dyn Probe>,
+    every: Duration,
+) {
+    let mut ticks = interval(every);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        if !manager.is_current(&agent, replica, generation) {
+            return;
+        }
+        let result = match timeout(every, probe.probe(every)).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(e)) => Err(format!("{:#}", e)),
+            Err(_) => Err(format!("no answer within {:?}", every)),
+        };
+        if !manager.probed(&agent, replica, generation, result).await {
+            return;
+        }
+    }
+}
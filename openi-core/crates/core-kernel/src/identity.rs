@@ -1,5 +1,408 @@
-use tracing::info;
+//! Node and agent identity.
+//!
+//! A tenant vouches for each of its nodes with a `NodeCertificate`: the
+//! node's Ed25519 key, signed by the tenant's key. A node in turn issues
+//! each agent it launches a short-lived `AgentCredential` binding
+//! `agent://tenant/node/agent` to a fresh Ed25519 key and to the digest of
+//! the manifest the agent was launched from. Envelopes an agent publishes
+//! are signed with that key.
+//!
+//! `IdentityVerifier` holds the trusted tenant keys and the certificates
+//! and credentials presented to it, and checks that an envelope's `src` is
+//! the agent that signed it. Installed on the bus, it checks every signed
+//! envelope and refuses unsigned ones on the topics it `require`s.
 
-pub fn init() {
-    info!("Identity subsystem initialized (stub)");
+use crate::manifest::AgentManifest;
+use crate::policy::{glob, SYSTEM_PREFIX};
+use openi_core_fabric::{DeliveryError, DeliveryFilter, Envelope, Keypair, Signer, Verifier};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Lifetime of agent credentials unless set with `NodeIdentity::with_ttl`.
+pub const CREDENTIAL_TTL: Duration = Duration::from_secs(60 * 60);
+/// How far ahead of our clock an `issued_at` may be before a certificate or
+/// credential is refused as not yet valid.
+pub const CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Error)]
+pub enum IdentityError {
+    #[error("{path}: {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("identity document: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("bad key: {0}")]
+    Key(String),
+    #[error("tenant {0} is not trusted")]
+    UnknownTenant(String),
+    #[error("no certificate for node {0}")]
+    UnknownNode(String),
+    #[error("no credential for {0}")]
+    UnknownAgent(String),
+    #[error("{0} does not verify")]
+    BadSignature(String),
+    #[error("{0} has expired")]
+    Expired(String),
+    #[error("{0} is not valid yet")]
+    NotYetValid(String),
+    #[error("{0}")]
+    Mismatch(String),
+    #[error("envelope {0} is unsigned")]
+    Unsigned(String),
+    #[error("invalid time {0:?}")]
+    Time(String),
+}
+
+/// A tenant's statement that `public_key` is node `node`'s key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeCertificate {
+    pub tenant: String,
+    pub node: String,
+    /// Ed25519, base64.
+    pub public_key: String,
+    /// RFC3339.
+    pub issued_at: String,
+    pub expires_at: String,
+    /// The tenant's signature over the other fields.
+    #[serde(default)]
+    pub sig: String,
+}
+
+impl NodeCertificate {
+    /// Certify `public_key` as `tenant/node` for `ttl`, signed by `tenant_key`.
+    pub fn issue(
+        tenant_key: &Signer,
+        tenant: impl Into<String>,
+        node: impl Into<String>,
+        public_key: impl Into<String>,
+        ttl: Duration,
+    ) -> Result<Self, IdentityError> {
+        let now = OffsetDateTime::now_utc();
+        let mut cert = Self {
+            tenant: tenant.into(),
+            node: node.into(),
+            public_key: public_key.into(),
+            issued_at: rfc3339(now),
+            expires_at: rfc3339(now + ttl),
+            sig: String::new(),
+        };
+        cert.sig = tenant_key.sign_bytes(&cert.signed_bytes()).map_err(bad_key)?;
+        Ok(cert)
+    }
+
+    /// Read a JSON certificate.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, IdentityError> {
+        Ok(serde_json::from_slice(&read(path.as_ref())?)?)
+    }
+
+    /// `tenant/node`, the authority of the node's agent URIs.
+    pub fn authority(&self) -> String {
+        format!("{}/{}", self.tenant, self.node)
+    }
+
+    /// The bytes `sig` covers: the certificate without its signature.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(&Self { sig: String::new(), ..self.clone() }).expect("serialize certificate")
+    }
+}
+
+/// A node's statement that `public_key` is `agent`'s key, for an agent
+/// launched from the manifest with digest `manifest`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentCredential {
+    /// `agent://tenant/node/agent`.
+    pub agent: String,
+    /// Ed25519, base64.
+    pub public_key: String,
+    /// `sha256:<hex>` of the manifest.
+    pub manifest: String,
+    /// RFC3339.
+    pub issued_at: String,
+    pub expires_at: String,
+    /// The issuing node's signature over the other fields.
+    #[serde(default)]
+    pub sig: String,
+}
+
+impl AgentCredential {
+    /// `tenant/node` of the node that issued it.
+    pub fn authority(&self) -> Option<&str> {
+        authority_of(&self.agent)
+    }
+
+    /// The bytes `sig` covers: the credential without its signature.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(&Self { sig: String::new(), ..self.clone() }).expect("serialize credential")
+    }
+}
+
+/// Trust anchors plus the certificates and credentials vouched for by them.
+#[derive(Default)]
+pub struct IdentityVerifier {
+    tenants: RwLock<HashMap<String, Arc<Verifier>>>,
+    nodes: RwLock<HashMap<String, NodeCertificate>>,
+    agents: RwLock<HashMap<String, (AgentCredential, Arc<Verifier>)>>,
+    required: RwLock<Vec<String>>,
+}
+
+impl IdentityVerifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust `public_key` (base64) to certify `tenant`'s nodes.
+    pub fn trust_tenant(&self, tenant: impl Into<String>, public_key: &str) -> Result<(), IdentityError> {
+        let verifier = Verifier::from_base64(public_key).map_err(bad_key)?;
+        self.tenants.write().insert(tenant.into(), Arc::new(verifier));
+        Ok(())
+    }
+
+    /// Accept a node certificate signed by a trusted tenant.
+    pub fn admit_node(&self, cert: NodeCertificate) -> Result<(), IdentityError> {
+        let authority = cert.authority();
+        let tenant = self.tenants.read().get(&cert.tenant).cloned();
+        let tenant = tenant.ok_or_else(|| IdentityError::UnknownTenant(cert.tenant.clone()))?;
+        tenant
+            .verify_bytes(&cert.signed_bytes(), &cert.sig)
+            .map_err(|_| IdentityError::BadSignature(format!("certificate of node {}", authority)))?;
+        if expired(&cert.expires_at)? {
+            return Err(IdentityError::Expired(format!("certificate of node {}", authority)));
+        }
+        if not_yet_valid(&cert.issued_at)? {
+            return Err(IdentityError::NotYetValid(format!("certificate of node {}", authority)));
+        }
+        self.nodes.write().insert(authority, cert);
+        Ok(())
+    }
+
+    /// Accept an agent credential issued by an admitted node, replacing any
+    /// earlier one for the same agent.
+    pub fn present(&self, credential: AgentCredential) -> Result<(), IdentityError> {
+        let authority = credential.authority().ok_or_else(|| {
+            IdentityError::Mismatch(format!("{} is not an agent://tenant/node/agent URI", credential.agent))
+        })?;
+        let node = self.node(authority)?;
+        let issuer = Verifier::from_base64(&node.public_key).map_err(bad_key)?;
+        issuer
+            .verify_bytes(&credential.signed_bytes(), &credential.sig)
+            .map_err(|_| IdentityError::BadSignature(format!("credential of {}", credential.agent)))?;
+        if expired(&credential.expires_at)? {
+            return Err(IdentityError::Expired(format!("credential of {}", credential.agent)));
+        }
+        if not_yet_valid(&credential.issued_at)? {
+            return Err(IdentityError::NotYetValid(format!("credential of {}", credential.agent)));
+        }
+        let key = Verifier::from_base64(&credential.public_key).map_err(bad_key)?;
+        self.agents.write().insert(credential.agent.clone(), (credential, Arc::new(key)));
+        Ok(())
+    }
+
+    /// Require a valid signature on every topic matching `pattern`.
+    pub fn require(&self, pattern: impl Into<String>) {
+        self.required.write().push(pattern.into());
+    }
+
+    pub fn credential(&self, agent: &str) -> Option<AgentCredential> {
+        self.agents.read().get(agent).map(|(c, _)| c.clone())
+    }
+
+    /// Check that `env` was signed by the agent named in its `src`, under a
+    /// credential and node certificate that are still valid.
+    pub fn verify<T>(&self, env: &Envelope<T>) -> Result<AgentCredential, IdentityError>
+    where
+        T: Serialize + for<'de> Deserialize<'de>,
+    {
+        if env.sig.is_none() {
+            return Err(IdentityError::Unsigned(env.id.clone()));
+        }
+        let (credential, key) =
+            self.agents.read().get(&env.src).cloned().ok_or_else(|| IdentityError::UnknownAgent(env.src.clone()))?;
+        if expired(&credential.expires_at)? {
+            return Err(IdentityError::Expired(format!("credential of {}", credential.agent)));
+        }
+        if not_yet_valid(&credential.issued_at)? {
+            return Err(IdentityError::NotYetValid(format!("credential of {}", credential.agent)));
+        }
+        let node = self.node(credential.authority().unwrap_or_default())?;
+        if expired(&node.expires_at)? {
+            return Err(IdentityError::Expired(format!("certificate of node {}", node.authority())));
+        }
+        env.verify(&key).map_err(|_| IdentityError::BadSignature(format!("envelope {} from {}", env.id, env.src)))?;
+        Ok(credential)
+    }
+
+    fn node(&self, authority: &str) -> Result<NodeCertificate, IdentityError> {
+        self.nodes.read().get(authority).cloned().ok_or_else(|| IdentityError::UnknownNode(authority.to_string()))
+    }
+}
+
+impl DeliveryFilter for IdentityVerifier {
+    fn name(&self) -> &'static str {
+        "identity"
+    }
+
+    fn check(&self, topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        let required = !env.src.starts_with(SYSTEM_PREFIX) && self.required.read().iter().any(|p| glob(p, topic));
+        if env.sig.is_none() && !required {
+            return Ok(());
+        }
+        self.verify(env).map(|_| ()).map_err(|e| DeliveryError::Identity(e.to_string()))
+    }
+}
+
+struct AgentKey {
+    keypair: Keypair,
+    credential: AgentCredential,
+}
+
+/// A node's certificate and key, issuing and renewing credentials for the
+/// agents it launches and signing on their behalf.
+pub struct NodeIdentity {
+    cert: NodeCertificate,
+    key: Signer,
+    ttl: Duration,
+    verifier: Arc<IdentityVerifier>,
+    agents: Mutex<HashMap<String, AgentKey>>,
+}
+
+impl NodeIdentity {
+    /// `cert` must certify `key` and be signed by a tenant `verifier`
+    /// trusts; it is admitted there.
+    pub fn new(cert: NodeCertificate, key: Keypair, verifier: Arc<IdentityVerifier>) -> Result<Self, IdentityError> {
+        if cert.public_key != key.public_key_base64() {
+            return Err(IdentityError::Mismatch(format!("certificate of node {} is for another key", cert.authority())));
+        }
+        verifier.admit_node(cert.clone())?;
+        Ok(Self { cert, key: Signer::new(key), ttl: CREDENTIAL_TTL, verifier, agents: Mutex::new(HashMap::new()) })
+    }
+
+    /// Load a JSON certificate and the node's 32-byte Ed25519 seed.
+    pub fn load(
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+        verifier: Arc<IdentityVerifier>,
+    ) -> Result<Self, IdentityError> {
+        Self::new(NodeCertificate::load(cert)?, load_key(key)?, verifier)
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn certificate(&self) -> &NodeCertificate {
+        &self.cert
+    }
+
+    pub fn authority(&self) -> String {
+        self.cert.authority()
+    }
+
+    pub fn verifier(&self) -> &Arc<IdentityVerifier> {
+        &self.verifier
+    }
+
+    /// Issue the agent launched from `manifest` a credential, presented to
+    /// the verifier. The agent keeps its key across calls unless its
+    /// manifest changed.
+    pub fn issue(&self, manifest: &AgentManifest) -> Result<AgentCredential, IdentityError> {
+        let agent = manifest.agent_uri();
+        if authority_of(&agent) != Some(self.authority().as_str()) {
+            return Err(IdentityError::Mismatch(format!("{} is not an agent of node {}", agent, self.authority())));
+        }
+        let digest = match &manifest.digest {
+            Some(d) => d.clone(),
+            None => format!("sha256:{}", hex::encode(Sha256::digest(serde_json::to_vec(manifest)?))),
+        };
+        let mut agents = self.agents.lock();
+        let keypair = match agents.get(&agent) {
+            Some(k) if k.credential.manifest == digest => k.keypair.clone(),
+            _ => Keypair::generate(),
+        };
+        let credential = self.credential(&agent, &keypair, digest)?;
+        agents.insert(agent, AgentKey { keypair, credential: credential.clone() });
+        Ok(credential)
+    }
+
+    /// Sign `env` as its `src`, renewing the credential once less than half
+    /// of its lifetime is left.
+    pub fn sign<T>(&self, env: &mut Envelope<T>) -> Result<(), IdentityError>
+    where
+        T: Serialize + for<'de> Deserialize<'de>,
+    {
+        let keypair = {
+            let mut agents = self.agents.lock();
+            let key = agents.get_mut(&env.src).ok_or_else(|| IdentityError::UnknownAgent(env.src.clone()))?;
+            let renew_at = parse(&key.credential.expires_at)? - self.ttl / 2;
+            if OffsetDateTime::now_utc() >= renew_at {
+                key.credential = self.credential(&env.src, &key.keypair, key.credential.manifest.clone())?;
+            }
+            key.keypair.clone()
+        };
+        env.sign(&Signer::new(keypair)).map_err(bad_key)
+    }
+
+    fn credential(&self, agent: &str, keypair: &Keypair, manifest: String) -> Result<AgentCredential, IdentityError> {
+        let now = OffsetDateTime::now_utc();
+        let mut credential = AgentCredential {
+            agent: agent.to_string(),
+            public_key: keypair.public_key_base64(),
+            manifest,
+            issued_at: rfc3339(now),
+            expires_at: rfc3339(now + self.ttl),
+            sig: String::new(),
+        };
+        credential.sig = self.key.sign_bytes(&credential.signed_bytes()).map_err(bad_key)?;
+        self.verifier.present(credential.clone())?;
+        Ok(credential)
+    }
+}
+
+/// Read a raw 32-byte Ed25519 seed, as written for `OPENI_NODE_KEY`.
+pub fn load_key(path: impl AsRef<Path>) -> Result<Keypair, IdentityError> {
+    let path = path.as_ref();
+    let seed: [u8; 32] = read(path)?
+        .try_into()
+        .map_err(|_| IdentityError::Key(format!("{} is not a 32-byte Ed25519 seed", path.display())))?;
+    Ok(Keypair::from_seed(&seed))
+}
+
+/// `tenant/node` of `agent://tenant/node/agent`.
+fn authority_of(agent: &str) -> Option<&str> {
+    let (authority, name) = agent.strip_prefix("agent://")?.rsplit_once('/')?;
+    let (tenant, node) = authority.split_once('/')?;
+    let valid = |s: &str| !s.is_empty() && !s.contains('/');
+    (valid(tenant) && valid(node) && valid(name)).then_some(authority)
+}
+
+fn bad_key(e: anyhow::Error) -> IdentityError {
+    IdentityError::Key(e.to_string())
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, IdentityError> {
+    std::fs::read(path).map_err(|source| IdentityError::Io { path: path.to_path_buf(), source })
+}
+
+fn rfc3339(t: OffsetDateTime) -> String {
+    t.format(&Rfc3339).unwrap_or_default()
+}
+
+fn parse(t: &str) -> Result<OffsetDateTime, IdentityError> {
+    OffsetDateTime::parse(t, &Rfc3339).map_err(|_| IdentityError::Time(t.to_string()))
+}
+
+fn expired(at: &str) -> Result<bool, IdentityError> {
+    Ok(OffsetDateTime::now_utc() >= parse(at)?)
+}
+
+fn not_yet_valid(issued_at: &str) -> Result<bool, IdentityError> {
+    Ok(parse(issued_at)? > OffsetDateTime::now_utc() + CLOCK_SKEW)
 }
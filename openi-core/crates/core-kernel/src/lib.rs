@@ -7,23 +7,36 @@ use serde_json::json;
 
 use openi_core_reflex::{FabricBus, BusSubscription, Envelope};
 
-/// ---------------------------------------------------------------------------
-/// Kernel Runtime Entrypoint
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Kernel Runtime Entrypoint
+// ---------------------------------------------------------------------------
 
 pub mod runtime;
 pub mod identity;
 pub mod policy;
-
-/// Starts the OpenI kernel node (stubbed for now).
-pub async fn start_node() -> Result<()> {
+pub mod rbac;
+pub mod scopes;
+pub mod blobstore;
+pub mod manifest;
+pub mod agents;
+pub mod health;
+pub mod control;
+pub mod process;
+pub mod resources;
+pub mod rundir;
+pub mod supervisor;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// Starts the OpenI kernel node and returns its agent manager.
+pub async fn start_node() -> Result<Arc<agents::AgentManager>> {
     info!("Starting OpenI kernel node (stub)...");
     runtime::start().await
 }
 
-/// ---------------------------------------------------------------------------
-/// Mock Fabric Bus — with Reflex Simulation Mode
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Mock Fabric Bus — with Reflex Simulation Mode
+// ---------------------------------------------------------------------------
 
 #[derive(Clone)]
 pub struct MockBus {
@@ -45,9 +58,9 @@ impl FabricBus for MockBus {
     }
 }
 
-/// ---------------------------------------------------------------------------
-/// Subscription Wrapper
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Subscription Wrapper
+// ---------------------------------------------------------------------------
 
 pub struct MockSub {
     rx: broadcast::Receiver<Envelope>,
@@ -56,16 +69,13 @@ pub struct MockSub {
 #[async_trait::async_trait]
 impl BusSubscription for MockSub {
     async fn next(&mut self) -> Option<Envelope> {
-        match self.rx.recv().await {
-            Ok(msg) => Some(msg),
-            Err(_) => None,
-        }
+        self.rx.recv().await.ok()
     }
 }
 
-/// ---------------------------------------------------------------------------
-/// Bus Factory + Reflex Simulation
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Bus Factory + Reflex Simulation
+// ---------------------------------------------------------------------------
 
 /// Returns a mock Fabric bus handle (Arc<MockBus>) and starts the reflex simulator.
 pub async fn get_bus() -> Result<Arc<MockBus>> {
@@ -89,7 +99,7 @@ async fn simulate_reflex_events(bus: Arc<MockBus>) {
         counter += 1;
 
         // Alternate between valid and invalid policy headers every 10th event
-        let headers = if counter % 10 == 0 {
+        let headers = if counter.is_multiple_of(10) {
             json!({
                 "identity": { "verified": false },
                 "policy": { "allowed": false }
@@ -112,7 +122,7 @@ async fn simulate_reflex_events(bus: Arc<MockBus>) {
         let _ = bus.publish("fabric.events.*", &evt).await;
 
         // Every 100 cycles, trigger a flood to test RateLimitReflex
-        if counter % 100 == 0 {
+        if counter.is_multiple_of(100) {
             println!("⚡ Generating burst load to trip RateLimitReflex");
             for _ in 0..550 {
                 let evt2 = Envelope {
@@ -133,18 +143,9 @@ async fn simulate_reflex_events(bus: Arc<MockBus>) {
     }
 }
 
-/// ---------------------------------------------------------------------------
-/// Mock Policy Loader
-/// ---------------------------------------------------------------------------
-
-pub fn load_policy(path: &str) -> Result<()> {
-    println!("[MOCK POLICY] loaded from {}", path);
-    Ok(())
-}
-
-/// ---------------------------------------------------------------------------
-/// Utility
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Utility
+// ---------------------------------------------------------------------------
 
 fn epoch_ms() -> u64 {
     use std::time::{SystemTime, UNIX_EPOCH};
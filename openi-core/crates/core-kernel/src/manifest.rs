@@ -0,0 +1,304 @@
+//! Agent manifests, normalised from the formats found in the manifests tree.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Which manifest layout a file used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ManifestFormat {
+    /// `apiVersion` + `metadata` + `spec` with `capabilities` and `routes`
+    /// (e.g. schema-mapper).
+    Spec,
+    /// Top-level `kind`/`name`/`topics` (the healthcare agents).
+    Flat,
+    /// `apiVersion` + `spec` with an `image`, typed `inputs`/`outputs` and
+    /// `permissions` (the DevOps and database agents).
+    Class,
+}
+
+/// Everything the kernel needs from a manifest, whatever its layout.
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentManifest {
+    pub name: String,
+    pub version: Option<String>,
+    pub class: Option<String>,
+    pub format: ManifestFormat,
+    /// `rust`, `wasm`, `oci`, `python`, ...
+    pub runtime: String,
+    /// `spec.source` (a registry or file reference).
+    pub source: Option<String>,
+    pub image: Option<String>,
+    /// `stdio` or `uds`, for agents run as native processes.
+    pub transport: Option<String>,
+    pub subscribe: Vec<String>,
+    pub publish: Vec<String>,
+    pub errors: Vec<String>,
+    /// Content types (or, for class manifests, data types) consumed and produced.
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    pub scopes: Vec<String>,
+    /// `resource: access` pairs from class manifests.
+    pub permissions: Vec<(String, String)>,
+    pub replicas: u32,
+    /// `restartPolicy`: `Always`, `OnFailure` or `Never`.
+    pub restart_policy: Option<String>,
+    /// `resources.limits` / `resources.requests`, unparsed (`500m`, `256Mi`).
+    pub limits: BTreeMap<String, String>,
+    pub requests: BTreeMap<String, String>,
+    pub security: Security,
+    pub observability: Observability,
+    pub env: BTreeMap<String, String>,
+    /// Where the manifest was loaded from, if it came from a file.
+    pub path: Option<PathBuf>,
+    /// `sha256:<hex>` of the document it was parsed from (the bytes
+    /// `openi package` signs).
+    pub digest: Option<String>,
+    /// `tenant/node` the agent runs under; `local` until a node identity
+    /// is set.
+    pub authority: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Security {
+    pub role: Option<String>,
+    pub access_level: Option<String>,
+    pub encryption: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Observability {
+    pub metrics_topic: Option<String>,
+    pub health_check_interval: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("{path}: {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("manifest yaml: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("expected kind {expected}, found {found:?}")]
+    Kind { expected: &'static str, found: Option<String> },
+    #[error("manifest has no agent name")]
+    MissingName,
+    #[error("agent name {0:?} must be letters, digits, '.', '_' or '-', not starting with '.'")]
+    Name(String),
+}
+
+impl AgentManifest {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ManifestError> {
+        let path = path.as_ref();
+        let text = read(path)?;
+        let mut manifest = Self::from_yaml(&text)?;
+        manifest.path = Some(path.to_path_buf());
+        Ok(manifest)
+    }
+
+    pub fn from_yaml(text: &str) -> Result<Self, ManifestError> {
+        let doc: Value = serde_yaml::from_str(text)?;
+        let mut manifest = Self::from_value(&doc)?;
+        manifest.digest = Some(format!("sha256:{}", hex::encode(Sha256::digest(text.as_bytes()))));
+        Ok(manifest)
+    }
+
+    pub fn from_value(doc: &Value) -> Result<Self, ManifestError> {
+        let kind = str_at(doc, &["kind"]);
+        if kind.as_deref() != Some("Agent") {
+            return Err(ManifestError::Kind { expected: "Agent", found: kind });
+        }
+        let format = if doc.get("apiVersion").is_none() {
+            ManifestFormat::Flat
+        } else if at(doc, &["spec", "capabilities"]).is_some() || at(doc, &["spec", "routes"]).is_some() {
+            ManifestFormat::Spec
+        } else {
+            ManifestFormat::Class
+        };
+        // Flat manifests keep everything at the top level; the others under
+        // `metadata` (identity) and `spec` (everything else).
+        let (meta, spec) = match format {
+            ManifestFormat::Flat => (doc, doc),
+            _ => (at(doc, &["metadata"]).unwrap_or(&Value::Null), at(doc, &["spec"]).unwrap_or(&Value::Null)),
+        };
+        let name = str_at(meta, &["name"]).ok_or(ManifestError::MissingName)?;
+        // The name becomes a file name for the agent's socket and cgroup.
+        if name.starts_with('.') || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+            return Err(ManifestError::Name(name));
+        }
+
+        let (subscribe, publish, errors, inputs, outputs) = match format {
+            ManifestFormat::Flat => (
+                strings(spec, &["topics", "subscribe"]),
+                strings(spec, &["topics", "publish"]),
+                strings(spec, &["topics", "errors"]),
+                Vec::new(),
+                Vec::new(),
+            ),
+            ManifestFormat::Spec => (
+                strings(spec, &["routes", "subscribe"]),
+                strings(spec, &["routes", "publish"]),
+                strings(spec, &["routes", "errors"]),
+                strings(spec, &["capabilities", "inputs"]),
+                strings(spec, &["capabilities", "outputs"]),
+            ),
+            ManifestFormat::Class => (
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                typed_items(spec, "inputs"),
+                typed_items(spec, "outputs"),
+            ),
+        };
+
+        let permissions = seq(spec, &["permissions"])
+            .iter()
+            .filter_map(Value::as_mapping)
+            .flat_map(|m| m.iter())
+            .filter_map(|(k, v)| Some((scalar(k)?, scalar(v)?)))
+            .collect();
+
+        Ok(Self {
+            name,
+            version: str_at(meta, &["version"]).or_else(|| str_at(spec, &["version"])),
+            class: str_at(meta, &["class"]),
+            format,
+            runtime: str_at(spec, &["runtime"]).unwrap_or_else(|| "rust".into()),
+            source: str_at(spec, &["source"]),
+            image: str_at(spec, &["image"]),
+            transport: str_at(spec, &["transport"]),
+            subscribe,
+            publish,
+            errors,
+            inputs,
+            outputs,
+            scopes: strings(spec, &["policies", "scopes"]),
+            permissions,
+            replicas: at(spec, &["replicas"]).and_then(Value::as_u64).map_or(1, |r| r as u32),
+            restart_policy: str_at(spec, &["restartPolicy"]).or_else(|| str_at(spec, &["restart_policy"])),
+            limits: string_map(spec, &["resources", "limits"]),
+            requests: string_map(spec, &["resources", "requests"]),
+            security: Security {
+                role: str_at(spec, &["security", "role"]),
+                access_level: str_at(spec, &["security", "access_level"]),
+                encryption: str_at(spec, &["security", "encryption"]),
+            },
+            observability: Observability {
+                metrics_topic: str_at(spec, &["observability", "metrics_topic"]),
+                health_check_interval: str_at(spec, &["observability", "health_check_interval"]),
+            },
+            env: string_map(spec, &["env"]),
+            path: None,
+            digest: None,
+            authority: None,
+        })
+    }
+
+    /// Bus identity for this agent: `agent://tenant/node/agent`, or
+    /// `agent://local/agent` on a node without an identity.
+    pub fn agent_uri(&self) -> String {
+        format!("agent://{}/{}", self.authority.as_deref().unwrap_or("local"), self.name)
+    }
+}
+
+/// Agents listed by a `kind: Deployment` file. Entries with a `path` are
+/// loaded from it (relative paths resolve against the working directory,
+/// as in the examples); entries with only a `name` are looked up as
+/// `<dir>/**/AgentManifest.yaml` under the deployment's directory. A
+/// deployment with no `agents` but a `spec.class` takes every manifest of
+/// that class found there.
+pub fn load_deployment(path: impl AsRef<Path>) -> Result<Vec<AgentManifest>, ManifestError> {
+    let path = path.as_ref();
+    let doc: Value = serde_yaml::from_str(&read(path)?)?;
+    let kind = str_at(&doc, &["kind"]);
+    if kind.as_deref() != Some("Deployment") {
+        return Err(ManifestError::Kind { expected: "Deployment", found: kind });
+    }
+    let agents = at(&doc, &["agents"]).or_else(|| at(&doc, &["spec", "agents"]));
+    let entries = agents.and_then(Value::as_sequence).cloned().unwrap_or_default();
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut nearby: Option<Vec<AgentManifest>> = None;
+    let mut out = Vec::new();
+    if entries.is_empty() {
+        if let Some(class) = str_at(&doc, &["spec", "class"]) {
+            let all = load_dir(dir.parent().unwrap_or(dir))?;
+            out.extend(all.into_iter().filter(|m| m.class.as_deref() == Some(class.as_str())));
+        }
+    }
+    for entry in entries {
+        if let Some(p) = str_at(&entry, &["path"]) {
+            out.push(AgentManifest::load(p)?);
+        } else if let Some(name) = str_at(&entry, &["name"]) {
+            let nearby = match &mut nearby {
+                Some(n) => n,
+                None => nearby.insert(load_dir(dir.parent().unwrap_or(dir))?),
+            };
+            match nearby.iter().find(|m| m.name == name) {
+                Some(m) => out.push(m.clone()),
+                None => tracing::warn!("deployment {}: no manifest found for {}", path.display(), name),
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Every `AgentManifest.yaml` under `dir`, recursively.
+pub fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<AgentManifest>, ManifestError> {
+    let dir = dir.as_ref();
+    let mut out = Vec::new();
+    let entries = std::fs::read_dir(dir).map_err(|source| ManifestError::Io { path: dir.to_path_buf(), source })?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            out.extend(load_dir(&path)?);
+        } else if path.file_name().is_some_and(|n| n == "AgentManifest.yaml") {
+            out.push(AgentManifest::load(&path)?);
+        }
+    }
+    out.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(out)
+}
+
+fn read(path: &Path) -> Result<String, ManifestError> {
+    std::fs::read_to_string(path).map_err(|source| ManifestError::Io { path: path.to_path_buf(), source })
+}
+
+fn at<'a>(v: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(v, |cur, key| cur.get(*key))
+}
+
+fn scalar(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn str_at(v: &Value, path: &[&str]) -> Option<String> {
+    at(v, path).and_then(scalar)
+}
+
+fn seq<'a>(v: &'a Value, path: &[&str]) -> &'a [Value] {
+    at(v, path).and_then(Value::as_sequence).map_or(&[], |s| s.as_slice())
+}
+
+fn strings(v: &Value, path: &[&str]) -> Vec<String> {
+    seq(v, path).iter().filter_map(scalar).collect()
+}
+
+/// `- type: metrics` entries in class manifests.
+fn typed_items(v: &Value, key: &str) -> Vec<String> {
+    seq(v, &[key]).iter().filter_map(|i| str_at(i, &["type"]).or_else(|| scalar(i))).collect()
+}
+
+fn string_map(v: &Value, path: &[&str]) -> BTreeMap<String, String> {
+    at(v, path)
+        .and_then(Value::as_mapping)
+        .map(|m| m.iter().filter_map(|(k, v)| Some((scalar(k)?, scalar(v)?))).collect())
+        .unwrap_or_default()
+}
@@ -1,5 +1,475 @@
-use tracing::info;
+//! Policy engine: allow/deny rules over who (subject) may do what (action)
+//! to which topic, content type or scope (resource).
+//!
+//! A policy is a YAML or JSON document:
+//!
+//! ```yaml
+//! default: deny            # when no rule matches; `deny` unless set
+//! rules:
+//!   - id: intake-publishes-encounters
+//!     effect: allow
+//!     subject: { role: intake }
+//!     action: publish
+//!     resource: { topic: "topic://clinical/encounters/*" }
+//!   - id: no-phi-for-rcm
+//!     effect: deny
+//!     description: billing never sees PHI
+//!     subject: { role: rcm }
+//!     action: [subscribe, access]
+//!     resource: { scope: "phi:*" }
+//! ```
+//!
+//! Every matcher field takes a pattern or a list of them, where `*` matches
+//! any run of characters; a field left out matches anything. A matching
+//! `deny` rule wins over any `allow`; with neither, the subject's role may
+//! grant the request (see `rbac`, declared under `roles`) before `default`
+//! applies. Sources under `agent://fabric/` are the node itself and are
+//! always allowed.
+//!
+//! Installed on the bus, the engine checks `publish` against an envelope's
+//! `src` and `subscribe` against each agent it would be delivered to.
 
-pub fn init() {
-    info!("Policy subsystem initialized (stub)");
+use crate::manifest::AgentManifest;
+use crate::rbac::{AccessLevel, RbacError, Roles};
+use openi_core_fabric::{DeliveryError, DeliveryFilter, Envelope};
+use parking_lot::RwLock;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+use tracing::warn;
+
+/// Prefix of the node's own identities, exempt from policy.
+pub const SYSTEM_PREFIX: &str = "agent://fabric/";
+
+#[derive(Debug, Error)]
+pub enum PolicyError {
+    #[error("{path}: {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("policy: {0}")]
+    Parse(#[from] serde_yaml::Error),
+    #[error("unknown action {0:?} (expected publish, subscribe or access)")]
+    Action(String),
+    #[error(transparent)]
+    Rbac(#[from] RbacError),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Allow,
+    #[default]
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Send envelopes to a topic.
+    Publish,
+    /// Receive envelopes from a topic.
+    Subscribe,
+    /// Anything else a component wants to ask about (e.g. reading a scope).
+    Access,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Publish => "publish",
+            Action::Subscribe => "subscribe",
+            Action::Access => "access",
+        })
+    }
+}
+
+impl FromStr for Action {
+    type Err = PolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "publish" => Ok(Action::Publish),
+            "subscribe" => Ok(Action::Subscribe),
+            "access" => Ok(Action::Access),
+            _ => Err(PolicyError::Action(s.to_string())),
+        }
+    }
+}
+
+/// Which subjects a rule applies to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubjectMatch {
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub agent: Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub role: Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub tenant: Vec<String>,
+}
+
+/// Which resources a rule applies to. `scope` matches if any of the
+/// request's scopes does.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceMatch {
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub topic: Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub ctype: Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub scope: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub effect: Effect,
+    #[serde(default)]
+    pub subject: SubjectMatch,
+    /// Empty means every action.
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub action: Vec<Action>,
+    #[serde(default)]
+    pub resource: ResourceMatch,
+}
+
+impl Rule {
+    fn matches(&self, request: &Request) -> bool {
+        let subject = &request.subject;
+        let resource = &request.resource;
+        any(&self.subject.agent, Some(&subject.agent))
+            && any(&self.subject.role, subject.role.as_deref())
+            && any(&self.subject.tenant, subject.tenant.as_deref())
+            && (self.action.is_empty() || self.action.contains(&request.action))
+            && any(&self.resource.topic, resource.topic.as_deref())
+            && any(&self.resource.ctype, resource.ctype.as_deref())
+            && (self.resource.scope.is_empty() || resource.scopes.iter().any(|s| any(&self.resource.scope, Some(s))))
+    }
+}
+
+/// A parsed policy document.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Policy {
+    #[serde(default)]
+    pub default: Effect,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub roles: Roles,
+}
+
+impl Policy {
+    /// Load a policy file; JSON is read as YAML.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PolicyError> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).map_err(|source| PolicyError::Io { path: path.to_path_buf(), source })?;
+        Self::from_yaml(&text)
+    }
+
+    pub fn from_yaml(text: &str) -> Result<Self, PolicyError> {
+        let policy: Self = serde_yaml::from_str(text)?;
+        policy.roles.validate()?;
+        Ok(policy)
+    }
+}
+
+/// Who is asking.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subject {
+    /// `agent://tenant/...` URI.
+    pub agent: String,
+    pub role: Option<String>,
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub access_level: AccessLevel,
+}
+
+impl Subject {
+    /// A subject known only by URI; the tenant is the URI's authority.
+    pub fn new(agent: impl Into<String>) -> Self {
+        let agent = agent.into();
+        let tenant = agent
+            .strip_prefix("agent://")
+            .and_then(|rest| rest.split('/').next())
+            .filter(|t| !t.is_empty())
+            .map(str::to_string);
+        Self { agent, role: None, tenant, access_level: AccessLevel::default() }
+    }
+
+    /// The subject an agent runs as, with its manifest's `security.role`
+    /// and `access_level`.
+    pub fn of(manifest: &AgentManifest) -> Self {
+        Self {
+            role: manifest.security.role.clone(),
+            access_level: AccessLevel::of(manifest).unwrap_or_default(),
+            ..Self::new(manifest.agent_uri())
+        }
+    }
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.agent)?;
+        if let Some(role) = &self.role {
+            write!(f, " (role {})", role)?;
+        }
+        Ok(())
+    }
+}
+
+/// What is being acted on.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resource {
+    pub topic: Option<String>,
+    pub ctype: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+impl Resource {
+    /// The topic an envelope travels on, its content type and `scopes` header.
+    pub fn envelope(topic: &str, env: &Envelope<Value>) -> Self {
+        Self {
+            topic: Some(topic.to_string()),
+            ctype: Some(env.ctype.to_string()),
+            scopes: env.scopes().into_iter().map(str::to_string).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(topic) = &self.topic {
+            parts.push(format!("topic {}", topic));
+        }
+        if let Some(ctype) = &self.ctype {
+            parts.push(format!("ctype {}", ctype));
+        }
+        if !self.scopes.is_empty() {
+            parts.push(format!("scopes {}", self.scopes.join(",")));
+        }
+        if parts.is_empty() {
+            parts.push("anything".into());
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request {
+    pub subject: Subject,
+    pub action: Action,
+    pub resource: Resource,
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} on {}", self.subject, self.action, self.resource)
+    }
+}
+
+/// The outcome of a request, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decision {
+    pub allowed: bool,
+    /// Id (or `#index`) of the deciding rule; `None` for the default.
+    pub rule: Option<String>,
+    pub explanation: String,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.explanation)
+    }
+}
+
+/// Evaluates requests against a `Policy`, knowing the subject behind each
+/// registered agent URI.
+pub struct PolicyEngine {
+    policy: RwLock<Policy>,
+    subjects: RwLock<HashMap<String, Subject>>,
+}
+
+impl PolicyEngine {
+    pub fn new(policy: Policy) -> Self {
+        Self { policy: RwLock::new(policy), subjects: RwLock::new(HashMap::new()) }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PolicyError> {
+        Ok(Self::new(Policy::load(path)?))
+    }
+
+    /// Swap in a new policy; known subjects are kept.
+    pub fn replace(&self, policy: Policy) {
+        *self.policy.write() = policy;
+    }
+
+    /// Record the subject an agent runs as.
+    pub fn admit(&self, manifest: &AgentManifest) {
+        let subject = Subject::of(manifest);
+        self.subjects.write().insert(subject.agent.clone(), subject);
+    }
+
+    /// The registered subject for `agent`, or one known by URI alone.
+    pub fn subject(&self, agent: &str) -> Subject {
+        self.subjects.read().get(agent).cloned().unwrap_or_else(|| Subject::new(agent))
+    }
+
+    pub fn evaluate(&self, request: &Request) -> Decision {
+        if request.subject.agent.starts_with(SYSTEM_PREFIX) {
+            return Decision {
+                allowed: true,
+                rule: None,
+                explanation: format!("{} is a fabric system identity", request.subject.agent),
+            };
+        }
+        let policy = self.policy.read();
+        let mut allowed_by = None;
+        for (i, rule) in policy.rules.iter().enumerate() {
+            if !rule.matches(request) {
+                continue;
+            }
+            let label = rule.id.clone().unwrap_or_else(|| format!("#{}", i));
+            match rule.effect {
+                Effect::Deny => {
+                    let mut explanation = format!("denied by rule {}: {}", label, request);
+                    if let Some(why) = &rule.description {
+                        explanation.push_str(&format!(" ({})", why));
+                    }
+                    return Decision { allowed: false, rule: Some(label), explanation };
+                }
+                Effect::Allow => {
+                    allowed_by.get_or_insert(label);
+                }
+            }
+        }
+        let subject = &request.subject;
+        // The subject's access_level caps every allow, whichever grants it.
+        let permitted = subject.access_level.permits(request.action);
+        if let Some(label) = allowed_by {
+            if !permitted {
+                return Decision {
+                    explanation: format!(
+                        "denied: rule {} allows it but access_level is {}: {}",
+                        label, subject.access_level, request
+                    ),
+                    allowed: false,
+                    rule: Some(label),
+                };
+            }
+            return Decision {
+                explanation: format!("allowed by rule {}: {}", label, request),
+                allowed: true,
+                rule: Some(label),
+            };
+        }
+        let mut unmatched = match &subject.role {
+            Some(role) => format!("no rule or role {} grants it", role),
+            None => "no rule matches and the subject has no role".to_string(),
+        };
+        if let Some(role) = &subject.role {
+            if let Some(via) = policy.roles.grants(role, request.action, &request.resource) {
+                let label = format!("role {}", role);
+                let inherited = via.iter().filter(|r| *r != role).cloned().collect::<Vec<_>>();
+                let from = if inherited.is_empty() { String::new() } else { format!(" (from {})", inherited.join(", ")) };
+                if permitted {
+                    return Decision {
+                        explanation: format!("allowed by {}{}: {}", label, from, request),
+                        allowed: true,
+                        rule: Some(label),
+                    };
+                }
+                unmatched = format!("{} grants it but access_level is {}", label, subject.access_level);
+            }
+        }
+        if policy.default == Effect::Allow && !permitted {
+            return Decision {
+                allowed: false,
+                rule: None,
+                explanation: format!("denied: default allows it but access_level is {}: {}", subject.access_level, request),
+            };
+        }
+        let allowed = policy.default == Effect::Allow;
+        let verdict = if allowed { "allowed" } else { "denied" };
+        Decision { allowed, rule: None, explanation: format!("{} by default, {}: {}", verdict, unmatched, request) }
+    }
+
+    /// Evaluate `action` by the agent at URI `agent` on `resource`.
+    pub fn check(&self, agent: &str, action: Action, resource: Resource) -> Decision {
+        self.evaluate(&Request { subject: self.subject(agent), action, resource })
+    }
+
+    fn enforce(&self, agent: &str, action: Action, topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        let decision = self.check(agent, action, Resource::envelope(topic, env));
+        if decision.allowed {
+            return Ok(());
+        }
+        warn!("policy: {} ({})", decision, env.id);
+        Err(DeliveryError::Denied(decision.explanation))
+    }
+}
+
+impl DeliveryFilter for PolicyEngine {
+    fn name(&self) -> &'static str {
+        "policy"
+    }
+
+    fn check(&self, topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        self.enforce(&env.src, Action::Publish, topic, env)
+    }
+
+    fn check_receiver(&self, receiver: &str, topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        self.enforce(receiver, Action::Subscribe, topic, env)
+    }
+}
+
+fn any(patterns: &[String], value: Option<&str>) -> bool {
+    patterns.is_empty() || value.is_some_and(|v| patterns.iter().any(|p| glob(p, v)))
+}
+
+/// `*` matches any run of characters, including none.
+pub fn glob(pattern: &str, value: &str) -> bool {
+    let (p, v) = (pattern.as_bytes(), value.as_bytes());
+    let (mut pi, mut vi) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while vi < v.len() {
+        if pi < p.len() && p[pi] == b'*' {
+            star = Some((pi, vi));
+            pi += 1;
+        } else if pi < p.len() && p[pi] == v[vi] {
+            pi += 1;
+            vi += 1;
+        } else if let Some((sp, sv)) = star {
+            pi = sp + 1;
+            vi = sv + 1;
+            star = Some((sp, sv + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
+pub(crate) fn one_or_many<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match OneOrMany::deserialize(d)? {
+        OneOrMany::One(v) => vec![v],
+        OneOrMany::Many(v) => v,
+    })
 }
@@ -0,0 +1,584 @@
+//! Native agents: executables run as child processes, exchanging envelopes
+//! with the node over stdio or a per-agent Unix socket. Sockets live in a
+//! per-node directory under the runtime dir (see `rundir`), and only the
+//! spawned child itself may connect to its socket.
+//!
+//! The child inherits the manifest's `env`, plus:
+//! - `OPENI_AGENT` / `OPENI_AGENT_URI`: its name and bus identity
+//! - `OPENI_REPLICA`: which replica of the agent it is, from 0
+//! - `OPENI_MANIFEST`: the normalised manifest, as JSON
+//! - `OPENI_TRANSPORT`: `stdio` or `uds`
+//! - `OPENI_SOCKET`: the socket to connect to, for `uds`
+//!
+//! Both sides run the `FramedConnection` handshake (the node as initiator)
+//! and then exchange length-prefixed envelopes: the node forwards whatever
+//! matches the manifest's `subscribe` patterns (round-robin between the
+//! agent's replicas), and publishes what the child sends to its `dest`.
+//! Envelopes whose `src` is not the agent's own URI are dropped. Stderr (and
+//! stdout, when it isn't the transport) is logged line by line. To stop an
+//! agent the node closes its end of the connection and kills the process if
+//! it hasn't exited within the grace period.
+//!
+//! Agents with a `health_check_interval` are pinged over the same
+//! connection: a `fabric.health.ping.v1` envelope from `agent://fabric/health`,
+//! to be answered with a `fabric.health.pong.v1` child of it carrying a
+//! `ProbeReply`. Pongs go to the prober, not the bus.
+//!
+//! `resources.limits` are enforced with a cgroup v2 group per replica, which
+//! the child joins before it execs; failing to join fails the start. Where
+//! the node can't create one, memory falls back to `RLIMIT_AS` and the CPU
+//! limit goes unenforced. An agent OOM-killed in its cgroup exits with a
+//! memory `LimitBreach`.
+
+use crate::agents::{AgentAdapter, AgentInstance, Exit};
+use crate::health::{Probe, ProbeReply, HEALTH_SRC};
+use crate::identity::NodeIdentity;
+use crate::manifest::AgentManifest;
+use crate::resources::{self, Cgroup, LimitBreach, ResourceLimits};
+use crate::rundir;
+use anyhow::{anyhow, bail, Context};
+use async_trait::async_trait;
+use openi_core_fabric::envelope::header;
+use openi_core_fabric::redact::default_redactor;
+use openi_core_fabric::{Bus, Codec, Envelope, FramedConnection, FramedReader, FramedWriter, Role, Subscription};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
+use tokio::net::UnixListener;
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::{error, info, warn};
+
+type Reader = FramedReader<Box<dyn AsyncRead + Unpin + Send>>;
+type Writer = FramedWriter<Box<dyn AsyncWrite + Unpin + Send>>;
+/// Pings awaiting a pong, by ping id.
+type Pending = Arc<Mutex<HashMap<String, oneshot::Sender<ProbeReply>>>>;
+
+/// How a native agent talks to the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Stdio,
+    Uds,
+}
+
+impl Transport {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transport::Stdio => "stdio",
+            Transport::Uds => "uds",
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Transport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdio" => Ok(Transport::Stdio),
+            "uds" | "unix" => Ok(Transport::Uds),
+            other => bail!("unknown transport {:?}", other),
+        }
+    }
+}
+
+/// Runs `runtime: rust` (or `native`/`process`) agents from the executable
+/// named by `spec.source` or `spec.image`. Relative paths resolve against
+/// the manifest's directory; a bare name is looked up on `PATH`.
+pub struct ProcessAdapter {
+    bus: Arc<Bus>,
+    transport: Transport,
+    socket_dir: PathBuf,
+    grace: Duration,
+    connect_timeout: Duration,
+    cgroup_root: PathBuf,
+    identity: Option<Arc<NodeIdentity>>,
+}
+
+impl ProcessAdapter {
+    pub fn new(bus: Arc<Bus>) -> Self {
+        Self {
+            bus,
+            transport: Transport::Stdio,
+            socket_dir: rundir::dir().join(format!("agents-{}", std::process::id())),
+            grace: Duration::from_secs(5),
+            connect_timeout: Duration::from_secs(10),
+            cgroup_root: Cgroup::root(),
+            identity: None,
+        }
+    }
+
+    /// Issue each agent a credential at start and sign what it sends on its
+    /// behalf.
+    pub fn with_identity(mut self, identity: Arc<NodeIdentity>) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Transport for manifests that don't set `spec.transport`.
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Where per-agent sockets are created. Must be private to the node's
+    /// user; it is created `0700` if missing.
+    pub fn with_socket_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.socket_dir = dir.into();
+        self
+    }
+
+    /// How long a stopped agent has to exit before it is killed.
+    pub fn with_grace(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    /// How long a new agent has to connect and complete the handshake.
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Parent of the per-agent cgroups (`OPENI_CGROUP_ROOT` by default).
+    pub fn with_cgroup_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.cgroup_root = root.into();
+        self
+    }
+
+    /// Have the agent join a cgroup before it execs, or set up rlimits on
+    /// `cmd` if there is none.
+    fn limit(&self, instance: &str, limits: &ResourceLimits, cmd: &mut Command) -> Option<Cgroup> {
+        if limits.is_empty() {
+            return None;
+        }
+        match Cgroup::create(&self.cgroup_root, instance, limits).and_then(|c| Ok((c.procs()?, c))) {
+            Ok((procs, cgroup)) => {
+                // SAFETY: the closure only calls `open`, `write` and `close`
+                // on a path allocated beforehand, which is safe between fork
+                // and exec.
+                unsafe {
+                    cmd.pre_exec(move || resources::join(&procs));
+                }
+                return Some(cgroup);
+            }
+            Err(e) => warn!(
+                "agent {}: no cgroup under {} ({}); falling back to rlimits",
+                instance,
+                self.cgroup_root.display(),
+                e
+            ),
+        }
+        if let Some(bytes) = limits.memory_bytes {
+            // SAFETY: the closure only calls `setrlimit`, which is safe
+            // between fork and exec.
+            unsafe {
+                cmd.pre_exec(move || resources::limit_address_space(bytes));
+            }
+        }
+        if let Some(millis) = limits.cpu_millis {
+            warn!("agent {}: cpu limit {}m needs cgroups and is not enforced", instance, millis);
+        }
+        None
+    }
+
+    fn program(&self, manifest: &AgentManifest) -> anyhow::Result<PathBuf> {
+        let source = manifest
+            .source
+            .as_deref()
+            .or(manifest.image.as_deref())
+            .ok_or_else(|| anyhow!("manifest has neither spec.source nor spec.image"))?;
+        let path = Path::new(source.strip_prefix("file://").unwrap_or(source));
+        if path.components().count() == 1 {
+            return Ok(path.to_path_buf());
+        }
+        let path = match manifest.path.as_deref().and_then(Path::parent) {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path.to_path_buf(),
+        };
+        if !path.is_file() {
+            bail!("{} is not an executable file (OCI images need the OCI adapter)", path.display());
+        }
+        Ok(path)
+    }
+
+    async fn connect(
+        &self,
+        instance: &str,
+        child: &mut Child,
+        listener: Option<UnixListener>,
+    ) -> anyhow::Result<(Reader, Writer)> {
+        type Pipes = (Box<dyn AsyncRead + Unpin + Send>, Box<dyn AsyncWrite + Unpin + Send>);
+        let (reader, writer): Pipes = match listener {
+            None => {
+                let stdout = child.stdout.take().ok_or_else(|| anyhow!("no stdout"))?;
+                let stdin = child.stdin.take().ok_or_else(|| anyhow!("no stdin"))?;
+                (Box::new(stdout), Box::new(stdin))
+            }
+            Some(listener) => {
+                let pid = child.id();
+                let stream = loop {
+                    let (stream, _) = tokio::select! {
+                        accepted = listener.accept() => accepted?,
+                        status = child.wait() => bail!("exited before connecting ({})", status?),
+                    };
+                    match rundir::check_peer(&stream, pid) {
+                        Ok(()) => break stream,
+                        Err(e) => warn!("agent {}: refusing connection: {}", instance, e),
+                    }
+                };
+                let (r, w) = stream.into_split();
+                (Box::new(r), Box::new(w))
+            }
+        };
+        let conn = FramedConnection::handshake(reader, writer, Role::Initiator, &Codec::ALL);
+        let conn = tokio::select! {
+            conn = conn => conn,
+            status = child.wait() => bail!("exited during handshake ({})", status?),
+        };
+        // A child that exits right away closes its pipe before its exit
+        // status is reaped; report the status rather than the closed pipe.
+        let conn = match conn {
+            Ok(conn) => conn,
+            Err(e) => match timeout(Duration::from_millis(200), child.wait()).await {
+                Ok(Ok(status)) => bail!("exited during handshake ({})", status),
+                _ => return Err(e.into()),
+            },
+        };
+        info!("agent {} connected ({})", instance, conn.codec().name());
+        Ok(conn.into_split())
+    }
+}
+
+#[async_trait]
+impl AgentAdapter for ProcessAdapter {
+    fn name(&self) -> &'static str {
+        "process"
+    }
+
+    fn supports(&self, manifest: &AgentManifest) -> bool {
+        matches!(manifest.runtime.as_str(), "rust" | "native" | "process")
+    }
+
+    async fn start(&self, manifest: &AgentManifest, replica: u32) -> anyhow::Result<Box<dyn AgentInstance>> {
+        let program = self.program(manifest)?;
+        if let Some(identity) = &self.identity {
+            identity.issue(manifest)?;
+        }
+        // Names the replica's socket, cgroup and log lines.
+        let instance = format!("{}-{}", manifest.name, replica);
+        let transport = match &manifest.transport {
+            Some(t) => t.parse()?,
+            None => self.transport,
+        };
+        let mut cmd = Command::new(&program);
+        cmd.envs(&manifest.env)
+            .env("OPENI_AGENT", &manifest.name)
+            .env("OPENI_AGENT_URI", manifest.agent_uri())
+            .env("OPENI_REPLICA", replica.to_string())
+            .env("OPENI_MANIFEST", serde_json::to_string(manifest)?)
+            .env("OPENI_TRANSPORT", transport.as_str())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let mut socket = None;
+        let listener = match transport {
+            Transport::Stdio => {
+                cmd.stdin(Stdio::piped());
+                None
+            }
+            Transport::Uds => {
+                rundir::ensure(&self.socket_dir).with_context(|| format!("socket dir {}", self.socket_dir.display()))?;
+                let path = self.socket_dir.join(format!("{}.sock", instance));
+                let listener = rundir::bind(&path).with_context(|| format!("binding {}", path.display()))?;
+                cmd.stdin(Stdio::null()).env("OPENI_SOCKET", &path);
+                socket = Some(path);
+                Some(listener)
+            }
+        };
+
+        let limits = ResourceLimits::from_map(&manifest.limits)?;
+        let cgroup = self.limit(&instance, &limits, &mut cmd);
+        let mut child = match (cmd.spawn(), &cgroup) {
+            (Ok(child), _) => child,
+            (Err(e), Some(cgroup)) => {
+                // The child may have failed to join its cgroup rather than to exec.
+                error!("agent {}: could not start in cgroup {}: {}", instance, cgroup.path().display(), e);
+                return Err(anyhow!(e).context(format!("spawning {} in {}", program.display(), cgroup.path().display())));
+            }
+            (Err(e), None) => return Err(anyhow!(e).context(format!("spawning {}", program.display()))),
+        };
+        let mut logs = Vec::new();
+        if let Some(stderr) = child.stderr.take() {
+            logs.push(tokio::spawn(log_lines(instance.clone(), "stderr", stderr)));
+        }
+        if listener.is_some() {
+            if let Some(stdout) = child.stdout.take() {
+                logs.push(tokio::spawn(log_lines(instance.clone(), "stdout", stdout)));
+            }
+        }
+
+        let (reader, writer) = match timeout(self.connect_timeout, self.connect(&instance, &mut child, listener)).await {
+            Ok(Ok(halves)) => halves,
+            Ok(Err(e)) => return Err(e),
+            Err(_) => bail!("did not connect within {:?}", self.connect_timeout),
+        };
+        info!("agent {} started: {} (pid {:?}, {})", instance, program.display(), child.id(), transport);
+
+        let pending = Pending::default();
+        let mut pumps = vec![tokio::spawn(outbound(
+            self.bus.clone(),
+            self.identity.clone(),
+            manifest.agent_uri(),
+            reader,
+            pending.clone(),
+        ))];
+        let (close_tx, close_rx) = oneshot::channel();
+        let (ping_tx, pings) = mpsc::channel(4);
+        let member = replica.to_string();
+        let subs = manifest
+            .subscribe
+            .iter()
+            .map(|p| self.bus.subscribe_queue(p.clone(), manifest.agent_uri(), member.clone()))
+            .collect();
+        pumps.push(tokio::spawn(inbound(instance, subs, pings, writer, close_rx)));
+        let probe = Arc::new(ProcessProbe { agent: manifest.agent_uri(), pings: ping_tx, pending });
+        Ok(Box::new(ProcessInstance {
+            probe,
+            child,
+            close: Some(close_tx),
+            pumps,
+            logs,
+            socket,
+            grace: self.grace,
+            cgroup,
+            limits,
+        }))
+    }
+}
+
+/// Publish what the agent sends until it closes the connection.
+async fn outbound(
+    bus: Arc<Bus>,
+    identity: Option<Arc<NodeIdentity>>,
+    agent: String,
+    mut reader: Reader,
+    pending: Pending,
+) {
+    loop {
+        let env = match reader.recv().await {
+            Ok(Some(env)) => env,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("{}: {}", agent, e);
+                break;
+            }
+        };
+        if env.src != agent {
+            warn!("{}: dropping envelope {} claiming src {}", agent, env.id, env.src);
+            continue;
+        }
+        if env.ctype == ProbeReply::pong_ctype() {
+            let ping = env.headers.get(header::CAUSATION_ID).and_then(|id| pending.lock().remove(id));
+            match (ping, serde_json::from_value(env.payload)) {
+                (Some(waiting), Ok(reply)) => {
+                    let _ = waiting.send(reply);
+                }
+                (Some(_), Err(e)) => warn!("{}: bad pong: {}", agent, e),
+                (None, _) => {}
+            }
+            continue;
+        }
+        let mut env = env;
+        if let Some(Err(e)) = identity.as_ref().map(|i| i.sign(&mut env)) {
+            warn!("{}: could not sign {}: {}", agent, env.id, e);
+            continue;
+        }
+        let topic = env.dest.clone();
+        if let Err(e) = bus.publish(&topic, env).await {
+            warn!("{} publish to {}: {}", agent, topic, e);
+        }
+    }
+}
+
+/// Forward the agent's subscriptions and pings to it until asked to close.
+async fn inbound(
+    agent: String,
+    subs: Vec<Subscription>,
+    mut pings: mpsc::Receiver<Envelope<Value>>,
+    mut writer: Writer,
+    mut close: oneshot::Receiver<()>,
+) {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1024);
+    let mut forwarders = Vec::new();
+    for mut sub in subs {
+        let tx = tx.clone();
+        forwarders.push(tokio::spawn(async move {
+            loop {
+                // Drop the subscription as soon as the pump goes, so the
+                // queue group stops routing to this replica.
+                let env = tokio::select! {
+                    _ = tx.closed() => break,
+                    env = sub.rx.recv() => match env {
+                        Some(env) => env,
+                        None => break,
+                    },
+                };
+                if tx.send(env).await.is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+    drop(tx);
+    loop {
+        let env = tokio::select! {
+            _ = &mut close => break,
+            Some(ping) = pings.recv() => ping,
+            env = rx.recv() => match env {
+                Some(env) => env,
+                None => break,
+            },
+        };
+        if let Err(e) = writer.send(&env).await {
+            warn!("{}: {}", agent, e);
+            break;
+        }
+    }
+    for f in forwarders {
+        f.abort();
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Log what the agent writes to `pipe`, a line at a time, until it closes.
+/// Bytes that aren't UTF-8 are replaced rather than ending the stream.
+async fn log_lines(agent: String, stream: &'static str, pipe: impl AsyncRead + Unpin) {
+    let mut pipe = BufReader::new(pipe);
+    let mut line = Vec::new();
+    while let Ok(n) = pipe.read_until(b'\n', &mut line).await {
+        if n == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = default_redactor().redact_str(text.trim_end_matches(['\n', '\r']));
+        info!(target: "openi::agent", %agent, %stream, "{}", text);
+        line.clear();
+    }
+}
+
+/// Pings the agent over its connection.
+struct ProcessProbe {
+    agent: String,
+    pings: mpsc::Sender<Envelope<Value>>,
+    pending: Pending,
+}
+
+#[async_trait]
+impl Probe for ProcessProbe {
+    async fn probe(&self, within: Duration) -> anyhow::Result<ProbeReply> {
+        let ping = Envelope::new(HEALTH_SRC, self.agent.clone(), ProbeReply::ping_ctype(), Value::Null).ensure_trace();
+        let id = ping.id.clone();
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().insert(id.clone(), tx);
+        let result = async {
+            self.pings.send(ping).await.map_err(|_| anyhow!("connection closed"))?;
+            timeout(within, rx).await.map_err(|_| anyhow!("no pong within {:?}", within))?.map_err(|_| anyhow!("connection closed"))
+        }
+        .await;
+        self.pending.lock().remove(&id);
+        result
+    }
+}
+
+struct ProcessInstance {
+    probe: Arc<ProcessProbe>,
+    child: Child,
+    close: Option<oneshot::Sender<()>>,
+    pumps: Vec<JoinHandle<()>>,
+    logs: Vec<JoinHandle<()>>,
+    socket: Option<PathBuf>,
+    grace: Duration,
+    cgroup: Option<Cgroup>,
+    limits: ResourceLimits,
+}
+
+impl ProcessInstance {
+    fn exit(&self, status: std::io::Result<std::process::ExitStatus>) -> Exit {
+        let oom_kills = self.cgroup.as_ref().map_or(0, Cgroup::oom_kills);
+        if let (true, Some(bytes)) = (oom_kills > 0, self.limits.memory_bytes) {
+            return Exit::Breach(LimitBreach::memory(bytes, format!("{} oom kill(s)", oom_kills)));
+        }
+        match status {
+            Ok(s) if s.success() => Exit::Completed,
+            Ok(s) => Exit::Failed(format!("exited with {}", s)),
+            Err(e) => Exit::Failed(format!("wait: {}", e)),
+        }
+    }
+
+    async fn cleanup(&mut self) {
+        for pump in self.pumps.drain(..) {
+            pump.abort();
+        }
+        // Let the log readers drain what the process wrote before exiting.
+        for log in self.logs.drain(..) {
+            let _ = timeout(Duration::from_secs(1), log).await;
+        }
+        if let Some(socket) = self.socket.take() {
+            let _ = std::fs::remove_file(socket);
+        }
+        self.cgroup = None;
+    }
+}
+
+#[async_trait]
+impl AgentInstance for ProcessInstance {
+    async fn wait(&mut self) -> Exit {
+        let status = self.child.wait().await;
+        let exit = self.exit(status);
+        self.cleanup().await;
+        exit
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        if let Some(close) = self.close.take() {
+            let _ = close.send(());
+        }
+        let status = match timeout(self.grace, self.child.wait()).await {
+            Ok(status) => status,
+            Err(_) => {
+                warn!("agent pid {:?} did not exit within {:?}; killing it", self.child.id(), self.grace);
+                self.child.kill().await?;
+                self.cleanup().await;
+                return Ok(());
+            }
+        };
+        let exit = self.exit(status);
+        self.cleanup().await;
+        match exit {
+            Exit::Failed(reason) => Err(anyhow!(reason)),
+            Exit::Breach(breach) => Err(anyhow!(breach.to_string())),
+            Exit::Completed => Ok(()),
+        }
+    }
+
+    fn probe(&self) -> Option<Arc<dyn Probe>> {
+        Some(self.probe.clone())
+    }
+}
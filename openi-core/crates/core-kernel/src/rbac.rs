@@ -0,0 +1,200 @@
+//! Role-based access control: which topics and scopes each
+//! `security.role` grants, with roles inheriting from one another.
+//!
+//! Roles are declared in the `roles` section of the policy file:
+//!
+//! ```yaml
+//! roles:
+//!   intake:
+//!     topics:
+//!       - { pattern: "topic://intake/*", actions: [publish, subscribe] }
+//!   triage:
+//!     inherits: intake
+//!     topics: ["topic://triage/*"]     # every action
+//!     scopes: ["phi:read"]
+//! ```
+//!
+//! An agent holds the role named in its manifest, narrowed by
+//! `security.access_level`: `read` agents may not publish and `write`
+//! agents may not subscribe.
+
+use crate::manifest::AgentManifest;
+use crate::policy::{glob, one_or_many, Action, Resource};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RbacError {
+    #[error("role {role} inherits unknown role {parent}")]
+    UnknownParent { role: String, parent: String },
+    #[error("role {0} inherits from itself")]
+    Cycle(String),
+    #[error("unknown access_level {0:?} (expected read, write or both)")]
+    AccessLevel(String),
+}
+
+/// `security.access_level`; `Both` unless set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLevel {
+    Read,
+    Write,
+    #[default]
+    Both,
+}
+
+impl AccessLevel {
+    pub fn of(manifest: &AgentManifest) -> Result<Self, RbacError> {
+        manifest.security.access_level.as_deref().map_or(Ok(Self::default()), str::parse)
+    }
+
+    pub fn permits(self, action: Action) -> bool {
+        match self {
+            AccessLevel::Read => action != Action::Publish,
+            AccessLevel::Write => action != Action::Subscribe,
+            AccessLevel::Both => true,
+        }
+    }
+}
+
+impl fmt::Display for AccessLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AccessLevel::Read => "read",
+            AccessLevel::Write => "write",
+            AccessLevel::Both => "both",
+        })
+    }
+}
+
+impl FromStr for AccessLevel {
+    type Err = RbacError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "read" => Ok(AccessLevel::Read),
+            "write" => Ok(AccessLevel::Write),
+            "both" => Ok(AccessLevel::Both),
+            _ => Err(RbacError::AccessLevel(s.to_string())),
+        }
+    }
+}
+
+/// A topic pattern and the actions allowed on it (all when empty). A bare
+/// string is a pattern with every action.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "TopicGrantDoc")]
+pub struct TopicGrant {
+    pub pattern: String,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TopicGrantDoc {
+    Pattern(String),
+    Full {
+        pattern: String,
+        #[serde(default, deserialize_with = "one_or_many")]
+        actions: Vec<Action>,
+    },
+}
+
+impl From<TopicGrantDoc> for TopicGrant {
+    fn from(doc: TopicGrantDoc) -> Self {
+        match doc {
+            TopicGrantDoc::Pattern(pattern) => Self { pattern, actions: Vec::new() },
+            TopicGrantDoc::Full { pattern, actions } => Self { pattern, actions },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoleDef {
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub inherits: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub topics: Vec<TopicGrant>,
+    /// Scope patterns (`phi:*`) the role holds.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+}
+
+/// Role definitions by name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Roles(pub BTreeMap<String, RoleDef>);
+
+impl Roles {
+    /// Every inherited role must exist, and none may inherit from itself.
+    pub fn validate(&self) -> Result<(), RbacError> {
+        for (role, def) in &self.0 {
+            if let Some(parent) = def.inherits.iter().find(|p| !self.0.contains_key(*p)) {
+                return Err(RbacError::UnknownParent { role: role.clone(), parent: parent.clone() });
+            }
+        }
+        for role in self.0.keys() {
+            let mut stack: Vec<&String> = self.0[role].inherits.iter().collect();
+            let mut seen = Vec::new();
+            while let Some(parent) = stack.pop() {
+                if parent == role {
+                    return Err(RbacError::Cycle(role.clone()));
+                }
+                if !seen.contains(&parent) {
+                    seen.push(parent);
+                    stack.extend(self.0.get(parent).into_iter().flat_map(|d| &d.inherits));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `role` followed by every role it inherits from, nearest first.
+    pub fn lineage<'a>(&'a self, role: &'a str) -> Vec<&'a str> {
+        let mut lineage = Vec::new();
+        let mut next = vec![role];
+        while !next.is_empty() {
+            let mut parents = Vec::new();
+            for r in next {
+                if self.0.contains_key(r) && !lineage.contains(&r) {
+                    lineage.push(r);
+                    parents.extend(self.0[r].inherits.iter().map(String::as_str));
+                }
+            }
+            next = parents;
+        }
+        lineage
+    }
+
+    /// The roles in `role`'s lineage whose permissions cover `action` on
+    /// `resource`, or `None` if they don't between them. The topic (if any)
+    /// must be granted for the action, and every scope must be held.
+    pub fn grants(&self, role: &str, action: Action, resource: &Resource) -> Option<Vec<String>> {
+        if resource.topic.is_none() && resource.scopes.is_empty() {
+            return None;
+        }
+        let lineage = self.lineage(role);
+        let mut via: Vec<String> = Vec::new();
+        let mut note = |r: &str| {
+            if !via.iter().any(|v| v == r) {
+                via.push(r.to_string());
+            }
+        };
+        if let Some(topic) = &resource.topic {
+            let granted = lineage.iter().find(|r| {
+                self.0[**r].topics.iter().any(|g| {
+                    (g.actions.is_empty() || g.actions.contains(&action)) && glob(&g.pattern, topic)
+                })
+            })?;
+            note(granted);
+        }
+        for scope in &resource.scopes {
+            let granted = lineage.iter().find(|r| self.0[**r].scopes.iter().any(|p| glob(p, scope)))?;
+            note(granted);
+        }
+        Some(via)
+    }
+}
@@ -0,0 +1,217 @@
+//! Manifest resource limits: Kubernetes-style quantities, and the cgroup v2
+//! / rlimit plumbing that enforces them on native agents.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Where per-agent cgroups are created unless `OPENI_CGROUP_ROOT` says otherwise.
+pub const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup/openi";
+
+#[derive(Debug, Error)]
+pub enum ResourceError {
+    #[error("invalid quantity {0:?}")]
+    Quantity(String),
+    #[error("{0:?} must be more than zero")]
+    Zero(String),
+    #[error("resources.limits.{key}: {source}")]
+    Limit { key: String, source: Box<ResourceError> },
+}
+
+/// Parse a Kubernetes quantity (`500m`, `1.5`, `256Mi`, `1G`, `1e3`).
+pub fn parse_quantity(s: &str) -> Result<f64, ResourceError> {
+    let s = s.trim();
+    let err = || ResourceError::Quantity(s.to_string());
+    const BINARY: [(&str, i32); 6] = [("Ki", 10), ("Mi", 20), ("Gi", 30), ("Ti", 40), ("Pi", 50), ("Ei", 60)];
+    const DECIMAL: [(char, i32); 9] =
+        [('n', -9), ('u', -6), ('m', -3), ('k', 3), ('M', 6), ('G', 9), ('T', 12), ('P', 15), ('E', 18)];
+
+    let (number, scale) = if let Some((n, shift)) = BINARY.iter().find_map(|(suf, p)| Some((s.strip_suffix(suf)?, *p))) {
+        (n, 2f64.powi(shift))
+    } else if let Some((n, exp)) = DECIMAL.iter().find_map(|(suf, p)| Some((s.strip_suffix(*suf)?, *p))) {
+        (n, 10f64.powi(exp))
+    } else {
+        (s, 1.0)
+    };
+    // Only plain decimals (with an optional exponent) are quantities.
+    if number.is_empty() || !number.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return Err(err());
+    }
+    let value: f64 = number.parse().map_err(|_| err())?;
+    if !value.is_finite() {
+        return Err(err());
+    }
+    Ok(value * scale)
+}
+
+/// The limits that are enforced, from a manifest's `resources.limits`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// Thousandths of a core.
+    pub cpu_millis: Option<u64>,
+    pub memory_bytes: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn from_map(limits: &BTreeMap<String, String>) -> Result<Self, ResourceError> {
+        // A zero limit would leave the agent nothing to run on.
+        let get = |key: &str, unit: f64| -> Result<Option<u64>, ResourceError> {
+            let Some(v) = limits.get(key) else { return Ok(None) };
+            let limit = |e| ResourceError::Limit { key: key.into(), source: Box::new(e) };
+            match (parse_quantity(v).map_err(limit)? * unit).ceil() as u64 {
+                0 => Err(limit(ResourceError::Zero(v.clone()))),
+                n => Ok(Some(n)),
+            }
+        };
+        Ok(Self { cpu_millis: get("cpu", 1000.0)?, memory_bytes: get("memory", 1.0)? })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cpu_millis.is_none() && self.memory_bytes.is_none()
+    }
+}
+
+/// An agent went over one of its limits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitBreach {
+    /// `cpu` or `memory`.
+    pub resource: String,
+    pub limit: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl LimitBreach {
+    pub fn cpu(millis: u64, detail: impl Into<String>) -> Self {
+        Self { resource: "cpu".into(), limit: format!("{}m", millis), detail: Some(detail.into()) }
+    }
+
+    pub fn memory(bytes: u64, detail: impl Into<String>) -> Self {
+        Self { resource: "memory".into(), limit: format!("{}", bytes), detail: Some(detail.into()) }
+    }
+}
+
+impl fmt::Display for LimitBreach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} limit {} exceeded", self.resource, self.limit)?;
+        if let Some(detail) = &self.detail {
+            write!(f, " ({})", detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// A cgroup v2 group holding one agent process.
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    pub fn root() -> PathBuf {
+        std::env::var_os("OPENI_CGROUP_ROOT").map_or_else(|| PathBuf::from(DEFAULT_CGROUP_ROOT), PathBuf::from)
+    }
+
+    /// Create `<root>/<name>` and apply `limits` to it. Fails when cgroup v2
+    /// isn't mounted there or the node may not delegate the controllers.
+    pub fn create(root: &Path, name: &str, limits: &ResourceLimits) -> std::io::Result<Self> {
+        // Every directory of a cgroup v2 hierarchy lists its controllers.
+        let parent = root.parent().unwrap_or(root);
+        if !parent.join("cgroup.controllers").is_file() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} is not in a cgroup v2 hierarchy", parent.display()),
+            ));
+        }
+        std::fs::create_dir_all(root)?;
+        // Controllers have to be enabled on every level down to the agent's
+        // group; the parent's may already be, or may not be ours to change.
+        let _ = std::fs::write(parent.join("cgroup.subtree_control"), "+cpu +memory");
+        std::fs::write(root.join("cgroup.subtree_control"), "+cpu +memory")?;
+        let path = root.join(name);
+        std::fs::create_dir_all(&path)?;
+        let cgroup = Self { path };
+        if let Some(millis) = limits.cpu_millis {
+            // Quota per 100ms period.
+            cgroup.write("cpu.max", &format!("{} 100000", millis * 100))?;
+        }
+        if let Some(bytes) = limits.memory_bytes {
+            cgroup.write("memory.max", &bytes.to_string())?;
+            let _ = cgroup.write("memory.swap.max", "0");
+        }
+        Ok(cgroup)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn add(&self, pid: u32) -> std::io::Result<()> {
+        self.write("cgroup.procs", &pid.to_string())
+    }
+
+    /// `cgroup.procs` of the group, for `join` in a child before it execs.
+    pub fn procs(&self) -> std::io::Result<CString> {
+        CString::new(self.path.join("cgroup.procs").into_os_string().into_vec())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Processes in the group killed for going over `memory.max`.
+    pub fn oom_kills(&self) -> u64 {
+        std::fs::read_to_string(self.path.join("memory.events"))
+            .ok()
+            .and_then(|events| {
+                events.lines().find_map(|l| l.strip_prefix("oom_kill ").and_then(|n| n.trim().parse().ok()))
+            })
+            .unwrap_or(0)
+    }
+
+    fn write(&self, file: &str, value: &str) -> std::io::Result<()> {
+        std::fs::write(self.path.join(file), value)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Only succeeds once the group is empty, i.e. the agent was reaped.
+        let _ = std::fs::remove_dir(&self.path);
+    }
+}
+
+/// Move the calling process into the cgroup whose `cgroup.procs` is
+/// `procs`. Meant for `pre_exec`, so the agent never runs outside its
+/// group: it only makes `open`, `write` and `close` calls.
+pub fn join(procs: &CStr) -> std::io::Result<()> {
+    // SAFETY: `procs` is a valid C string and the buffer outlives the
+    // calls; the fd is closed on every path.
+    unsafe {
+        let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // "0" is the writing process itself.
+        let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        if written == 1 {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+}
+
+/// Cap the calling process's address space. Meant for `pre_exec`, so it
+/// only makes the `setrlimit` call.
+pub fn limit_address_space(bytes: u64) -> std::io::Result<()> {
+    let limit = libc::rlimit { rlim_cur: bytes as libc::rlim_t, rlim_max: bytes as libc::rlim_t };
+    // SAFETY: `setrlimit` reads the struct we pass and touches no memory of ours.
+    if unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
@@ -0,0 +1,84 @@
+//! The node's private runtime directory, where its Unix sockets live.
+//!
+//! Sockets go in `$XDG_RUNTIME_DIR/openi`, or `openi-<uid>` in the temp dir
+//! where that isn't set. The directory is created `0700` and refused if it
+//! already exists but is not ours or is open to other users. Whoever
+//! connects to one of the sockets must run as the node's uid.
+
+use std::fs::{DirBuilder, Permissions};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::{UnixListener, UnixStream};
+
+/// The effective uid the node runs as.
+pub fn uid() -> u32 {
+    // SAFETY: `geteuid` has no preconditions and cannot fail.
+    unsafe { libc::geteuid() }
+}
+
+/// The node's runtime directory. Not created until `ensure`d.
+pub fn dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(run) if !run.is_empty() => PathBuf::from(run).join("openi"),
+        _ => std::env::temp_dir().join(format!("openi-{}", uid())),
+    }
+}
+
+/// Create `dir` with mode `0700`, or check that an existing one is a real
+/// directory owned by us that no one else can enter.
+pub fn ensure(dir: &Path) -> Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    let meta = std::fs::symlink_metadata(dir)?;
+    if !meta.is_dir() {
+        return Err(Error::new(ErrorKind::PermissionDenied, format!("{} is not a directory", dir.display())));
+    }
+    if meta.uid() != uid() {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} is owned by uid {}, not {}", dir.display(), meta.uid(), uid()),
+        ));
+    }
+    if meta.mode() & 0o077 != 0 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} is mode {:o}; expected 0700", dir.display(), meta.mode() & 0o777),
+        ));
+    }
+    Ok(())
+}
+
+/// Bind a socket at `path`, readable and writable by the node's uid only.
+/// A socket we left behind is replaced; anything else there is an error.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() && meta.uid() == uid() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket of ours; not removing it", path.display()),
+            ))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Check that the peer on `stream` runs as our uid and, if given, is
+/// process `pid`.
+pub fn check_peer(stream: &UnixStream, pid: Option<u32>) -> Result<()> {
+    let cred = stream.peer_cred()?;
+    if cred.uid() != uid() {
+        return Err(Error::new(ErrorKind::PermissionDenied, format!("peer runs as uid {}", cred.uid())));
+    }
+    match (pid, cred.pid()) {
+        (None, _) => Ok(()),
+        (Some(want), Some(got)) if got >= 0 && got as u32 == want => Ok(()),
+        (Some(want), got) => {
+            Err(Error::new(ErrorKind::PermissionDenied, format!("peer is pid {:?}, expected {}", got, want)))
+        }
+    }
+}
@@ -1,16 +1,153 @@
-use tracing::info;
-use anyhow::Result;
+use tracing::{info, warn};
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::agents::AgentManager;
+use crate::blobstore::FsBlobStore;
+use crate::control;
+use crate::identity::{self, IdentityVerifier, NodeCertificate, NodeIdentity};
+use crate::policy::PolicyEngine;
+use crate::process::ProcessAdapter;
+use openi_core_fabric::redact::{self, RedactionPolicy, Redactor};
+use openi_core_fabric::{
+    ApprovalConfig, ApprovalVerifier, ReplayGuard, RequireSealed, SchemaRegistry, Verifier, GLOBAL_BUS,
+};
+
+/// Ids the replay guard remembers before it starts refusing the oldest.
+const REPLAY_CAPACITY: usize = 100_000;
 
 /// Starts the OpenI Kernel runtime.
 ///
-/// Eventually this will:
-/// - Initialize WASM/OCI agent adapters
-/// - Mount the fabric bus (core-fabric)
-/// - Attach reflex monitors
-/// - Register agents and manifests
+/// Returns the node's `AgentManager`, mounted on the global fabric bus.
+/// Native agents run as child processes and `runtime: wasm` agents
+/// in-process on wasmtime; OCI adapters and reflex monitors are still to be
+/// attached.
+///
+/// `OPENI_REDACTION_POLICY` names a JSON `RedactionPolicy` replacing the
+/// built-in PHI policy for all log output.
+///
+/// `OPENI_ENCRYPTED_TOPICS` is a comma-separated list of topic patterns on
+/// which the bus rejects cleartext payloads.
+///
+/// `OPENI_SCHEMA_DIR` names a directory of `<ctype>.schema.json` payload
+/// schemas (the manifests tree's `schemas/`), checked on every publish.
+///
+/// `OPENI_REPLAY_WINDOW` (seconds) turns on replay protection: envelopes
+/// whose timestamp is further than that from the node's clock, or whose id
+/// was already delivered, are rejected.
+///
+/// When `OPENI_BLOB_DIR` is set, the node's claim-check blob store is opened
+/// there, garbage-collected once a minute and shared through
+/// `AgentManager::blob_store`.
+///
+/// `OPENI_NODE_CERT` names the node's JSON `NodeCertificate` and
+/// `OPENI_NODE_KEY` its 32-byte Ed25519 seed; `OPENI_TENANT_KEY` is the
+/// certifying tenant's public key (base64). With them, agents run as
+/// `agent://tenant/node/agent` under short-lived credentials and everything
+/// they publish is signed. `OPENI_SIGNED_TOPICS` is a comma-separated list
+/// of topic patterns on which unsigned envelopes are rejected.
+///
+/// `OPENI_APPROVALS` names a JSON `ApprovalConfig`: trusted signers and
+/// the N-of-M approvals required per topic. Origins not listed as signers
+/// are checked against the node identity's agent credentials.
+///
+/// `OPENI_POLICY` names a YAML or JSON policy (see `policy`) enforced on
+/// every publish and delivery; without one, everything is allowed.
+///
+/// `OPENI_AGENTS` is a comma-separated list of agent manifests, deployment
+/// files or manifest directories to register and start.
 ///
-/// For now, it's just a clean stub that logs readiness.
-pub async fn start() -> Result<()> {
-    info!("Kernel runtime up. (WASM/OCI adapters pending)");
-    Ok(())
+/// The CLI reaches the node through the control socket at
+/// `OPENI_CONTROL_SOCKET` (see `control`).
+pub async fn start() -> Result<Arc<AgentManager>> {
+    if let Ok(path) = std::env::var("OPENI_REDACTION_POLICY") {
+        let policy: RedactionPolicy = serde_json::from_slice(&std::fs::read(&path)?)?;
+        redact::set_default_redactor(Redactor::new(&policy)?);
+        info!("Redaction policy loaded from {}", path);
+    }
+    if let Ok(topics) = std::env::var("OPENI_ENCRYPTED_TOPICS") {
+        let patterns: Vec<&str> = topics.split(',').map(str::trim).filter(|t| !t.is_empty()).collect();
+        info!("Sealed payloads required on {:?}", patterns);
+        GLOBAL_BUS.add_filter(Arc::new(RequireSealed::new(patterns)));
+    }
+    if let Ok(dir) = std::env::var("OPENI_SCHEMA_DIR") {
+        let schemas = SchemaRegistry::load_dir(&dir)?;
+        info!("{} payload schema(s) loaded from {}", schemas.len(), dir);
+        GLOBAL_BUS.add_filter(Arc::new(schemas));
+    }
+    if let Ok(window) = std::env::var("OPENI_REPLAY_WINDOW") {
+        let window = Duration::from_secs(window.trim().parse()?);
+        info!("Replay protection on, with a {:?} clock-skew window", window);
+        GLOBAL_BUS.add_filter(Arc::new(ReplayGuard::new(window, REPLAY_CAPACITY)));
+    }
+    let agents = AgentManager::new(GLOBAL_BUS.clone());
+    if let Ok(dir) = std::env::var("OPENI_BLOB_DIR") {
+        let store = Arc::new(FsBlobStore::open(&dir)?);
+        store.clone().spawn_gc(Duration::from_secs(60));
+        agents.set_blob_store(store);
+        info!("Blob store mounted at {}", dir);
+    }
+    if let Ok(path) = std::env::var("OPENI_NODE_CERT") {
+        let var = |name: &str| std::env::var(name).map_err(|_| anyhow!("OPENI_NODE_CERT is set but {} is not", name));
+        let cert = NodeCertificate::load(&path)?;
+        let verifier = Arc::new(IdentityVerifier::new());
+        verifier.trust_tenant(cert.tenant.clone(), &var("OPENI_TENANT_KEY")?)?;
+        if let Ok(topics) = std::env::var("OPENI_SIGNED_TOPICS") {
+            for pattern in topics.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+                verifier.require(pattern);
+            }
+        }
+        let key = identity::load_key(var("OPENI_NODE_KEY")?)?;
+        let identity = Arc::new(NodeIdentity::new(cert, key, verifier)?);
+        info!("Node identity {} loaded from {}", identity.authority(), path);
+        agents.set_identity(identity);
+    }
+    if let Ok(path) = std::env::var("OPENI_APPROVALS") {
+        let config: ApprovalConfig = serde_json::from_slice(&std::fs::read(&path)?)?;
+        let mut approvals = ApprovalVerifier::from_config(&config)?;
+        if let Some(identity) = agents.identity() {
+            let credentials = identity.verifier().clone();
+            approvals = approvals.with_origin_keys(move |src| {
+                credentials.credential(src).and_then(|c| Verifier::from_base64(&c.public_key).ok())
+            });
+        }
+        GLOBAL_BUS.add_filter(Arc::new(approvals));
+        info!("Approval policies loaded from {}", path);
+    }
+    let mut process = ProcessAdapter::new(GLOBAL_BUS.clone());
+    if let Some(identity) = agents.identity() {
+        process = process.with_identity(identity);
+    }
+    agents.add_adapter(Arc::new(process));
+    #[cfg(feature = "wasm")]
+    {
+        let mut wasm = crate::wasm::WasmAdapter::new(GLOBAL_BUS.clone())?;
+        if let Some(identity) = agents.identity() {
+            wasm = wasm.with_identity(identity);
+        }
+        agents.add_adapter(Arc::new(wasm));
+    }
+    match std::env::var("OPENI_POLICY") {
+        Ok(path) => {
+            agents.set_policy(Arc::new(PolicyEngine::load(&path)?));
+            info!("Policy loaded from {}", path);
+        }
+        Err(_) => info!("No OPENI_POLICY set; every action is allowed"),
+    }
+    if let Ok(paths) = std::env::var("OPENI_AGENTS") {
+        for path in paths.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            for name in agents.register_path(path).await? {
+                // A failed start is recorded as `Failed`; keep the node up.
+                if let Err(e) = agents.start(&name).await {
+                    warn!("{}", e);
+                }
+            }
+        }
+    }
+    if let Err(e) = control::serve(agents.clone(), &control::socket_path()) {
+        warn!("control socket {}: {}", control::socket_path().display(), e);
+    }
+    info!("Kernel runtime up. (OCI adapter pending)");
+    Ok(agents)
 }
@@ -0,0 +1,136 @@
+//! Scope checks at delivery: an envelope's `scopes` header lists what its
+//! receiver must hold, and a receiving agent holds the `policies.scopes` of
+//! its manifest (patterns such as `phi:*` allowed).
+//!
+//! An agent missing any required scope is not handed the envelope. Each
+//! refusal is logged on the `openi::audit` target and reported as a
+//! `fabric.scope.violation.v1` envelope on `fabric.control`. Plain
+//! subscriptions carry no identity to hold scopes with, so envelopes that
+//! require any are never delivered to them.
+
+use crate::control::CONTROL_TOPIC;
+use crate::manifest::AgentManifest;
+use crate::policy::glob;
+use openi_core_fabric::envelope::header;
+use openi_core_fabric::{Bus, ContentType, DeliveryError, DeliveryFilter, Envelope};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use tracing::warn;
+
+/// Source of violation reports.
+pub const SCOPES_SRC: &str = "agent://fabric/scopes";
+
+/// Payload of `fabric.scope.violation.v1` envelopes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScopeViolation {
+    /// The receiving agent's URI.
+    pub agent: String,
+    pub topic: String,
+    /// Id of the refused envelope.
+    pub envelope: String,
+    /// Its sender.
+    pub src: String,
+    pub required: Vec<String>,
+    pub granted: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl ScopeViolation {
+    pub fn ctype() -> ContentType {
+        ContentType::logical("fabric.scope", "violation", 1)
+    }
+}
+
+/// Refuses deliveries to agents lacking an envelope's scopes.
+pub struct ScopeGuard {
+    bus: Weak<Bus>,
+    granted: RwLock<HashMap<String, Vec<String>>>,
+}
+
+impl ScopeGuard {
+    pub fn new(bus: &Arc<Bus>) -> Self {
+        Self { bus: Arc::downgrade(bus), granted: RwLock::new(HashMap::new()) }
+    }
+
+    /// Record the scopes an agent's manifest grants it.
+    pub fn admit(&self, manifest: &AgentManifest) {
+        self.granted.write().insert(manifest.agent_uri(), manifest.scopes.clone());
+    }
+
+    pub fn granted(&self, agent: &str) -> Vec<String> {
+        self.granted.read().get(agent).cloned().unwrap_or_default()
+    }
+
+    /// Required scopes `agent` does not hold.
+    pub fn missing(&self, agent: &str, required: &[&str]) -> Vec<String> {
+        let granted = self.granted.read();
+        let held = granted.get(agent).map(Vec::as_slice).unwrap_or_default();
+        required.iter().filter(|s| !held.iter().any(|p| glob(p, s))).map(|s| s.to_string()).collect()
+    }
+
+    fn report(&self, violation: ScopeViolation, env: &Envelope<Value>) {
+        let Some(bus) = self.bus.upgrade() else { return };
+        let Ok(payload) = serde_json::to_value(&violation) else { return };
+        let mut report = Envelope::new(SCOPES_SRC, CONTROL_TOPIC, ScopeViolation::ctype(), payload).child_of(env);
+        // Outlives the refused envelope's deadline.
+        report.headers.remove(header::DEADLINE);
+        // Delivery runs on the runtime; publish once the current fan-out is done.
+        if let Ok(rt) = tokio::runtime::Handle::try_current() {
+            rt.spawn(async move {
+                let _ = bus.publish(CONTROL_TOPIC, report).await;
+            });
+        }
+    }
+}
+
+impl DeliveryFilter for ScopeGuard {
+    fn name(&self) -> &'static str {
+        "scopes"
+    }
+
+    fn check(&self, _topic: &str, _env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        Ok(())
+    }
+
+    fn check_anonymous(&self, _topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        match env.scopes().as_slice() {
+            [] => Ok(()),
+            required => Err(DeliveryError::Denied(format!("scope(s) {} need a receiver identity", required.join(",")))),
+        }
+    }
+
+    fn check_receiver(&self, receiver: &str, topic: &str, env: &Envelope<Value>) -> Result<(), DeliveryError> {
+        let required = env.scopes();
+        if required.is_empty() {
+            return Ok(());
+        }
+        let missing = self.missing(receiver, &required);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let violation = ScopeViolation {
+            agent: receiver.to_string(),
+            topic: topic.to_string(),
+            envelope: env.id.clone(),
+            src: env.src.clone(),
+            required: required.iter().map(|s| s.to_string()).collect(),
+            granted: self.granted(receiver),
+            missing,
+        };
+        warn!(
+            target: "openi::audit",
+            agent = %violation.agent,
+            topic = %violation.topic,
+            envelope = %violation.envelope,
+            src = %violation.src,
+            missing = %violation.missing.join(","),
+            "scope violation: delivery refused"
+        );
+        let reason = format!("{} lacks scope(s) {}", receiver, violation.missing.join(","));
+        self.report(violation, env);
+        Err(DeliveryError::Denied(reason))
+    }
+}
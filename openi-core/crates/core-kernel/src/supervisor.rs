@@ -0,0 +1,128 @@
+//! Restart policy for agent replicas: whether an exited replica is started
+//! again, how long to wait first, and when to give up on one that keeps
+//! crashing.
+
+use crate::agents::Exit;
+use crate::manifest::AgentManifest;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SupervisorError {
+    #[error("unknown restart policy {0:?} (expected Always, OnFailure or Never)")]
+    Policy(String),
+}
+
+/// When a replica that exited on its own is restarted, from the manifest's
+/// `restartPolicy` (`Always` unless set).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestartPolicy {
+    #[default]
+    Always,
+    /// Only after a failure or a limit breach, not a clean exit.
+    OnFailure,
+    Never,
+}
+
+impl RestartPolicy {
+    pub fn of(manifest: &AgentManifest) -> Result<Self, SupervisorError> {
+        manifest.restart_policy.as_deref().map_or(Ok(Self::default()), str::parse)
+    }
+
+    pub fn restarts(self, exit: &Exit) -> bool {
+        match self {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => !matches!(exit, Exit::Completed),
+            RestartPolicy::Never => false,
+        }
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = SupervisorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "always" => Ok(RestartPolicy::Always),
+            "onfailure" => Ok(RestartPolicy::OnFailure),
+            "never" => Ok(RestartPolicy::Never),
+            _ => Err(SupervisorError::Policy(s.to_string())),
+        }
+    }
+}
+
+/// Node-wide restart timing.
+#[derive(Debug, Clone, Copy)]
+pub struct SupervisorConfig {
+    /// Delay before the first restart; doubled for each one after.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// A replica that exits this many times within `crash_loop_window` is
+    /// parked in `CrashLoop` instead of being restarted.
+    pub crash_loop_failures: usize,
+    /// Also how long a replica has to run for its backoff to reset.
+    pub crash_loop_window: Duration,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            crash_loop_failures: 5,
+            crash_loop_window: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// What to do with a replica that just exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Restart(Duration),
+    /// Exited this many times within the window.
+    CrashLoop(usize),
+}
+
+/// Recent exits of one replica.
+#[derive(Debug, Default)]
+pub struct Restarts {
+    exits: VecDeque<Instant>,
+    attempt: u32,
+}
+
+impl Restarts {
+    /// Record an exit at `now` by a replica that had been running for
+    /// `ran_for` (`None` if it never came up).
+    pub fn record(&mut self, config: &SupervisorConfig, now: Instant, ran_for: Option<Duration>) -> Verdict {
+        if ran_for.is_some_and(|r| r >= config.crash_loop_window) {
+            self.reset();
+        }
+        while self.exits.front().is_some_and(|t| now.duration_since(*t) > config.crash_loop_window) {
+            self.exits.pop_front();
+        }
+        if self.exits.is_empty() {
+            self.attempt = 0;
+        }
+        self.exits.push_back(now);
+        if self.exits.len() >= config.crash_loop_failures.max(1) {
+            return Verdict::CrashLoop(self.exits.len());
+        }
+        let delay = config.backoff.saturating_mul(1 << self.attempt.min(16)).min(config.max_backoff);
+        self.attempt += 1;
+        Verdict::Restart(delay)
+    }
+
+    pub fn reset(&mut self) {
+        self.exits.clear();
+        self.attempt = 0;
+    }
+}
@@ -0,0 +1,503 @@
+//! WASM agents: wasmtime components implementing the `openi:agent` world
+//! in `wit/agent.wit`.
+//!
+//! `resources.limits.memory` caps an instance's linear memory, and
+//! `resources.limits.cpu` becomes a fuel-per-second budget. Asking for more
+//! memory than the cap, or running out of fuel with the budget spent, ends
+//! the instance with a `LimitBreach`.
+//!
+//! Replicas of an agent share its key/value state, capped at
+//! `WasmLimits::state` bytes; a write past the cap is dropped and ends the
+//! instance with a memory `LimitBreach`. Health pings are
+//! answered between calls, so a guest stuck in one reads as not live.
+
+use crate::agents::{AgentAdapter, AgentInstance, Exit};
+use crate::health::{Probe, ProbeReply};
+use crate::identity::NodeIdentity;
+use crate::manifest::AgentManifest;
+use crate::resources::{LimitBreach, ResourceLimits};
+use anyhow::{anyhow, bail, Context};
+use async_trait::async_trait;
+use openi_core_fabric::redact::default_redactor;
+use openi_core_fabric::{Bus, ContentType, Envelope};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, trace, warn};
+use wasmtime::component::{Component, HasSelf, Linker};
+use wasmtime::{Config, Engine, ResourceLimiter, Store, StoreLimits, StoreLimitsBuilder, Trap};
+
+wasmtime::component::bindgen!({
+    path: "wit",
+    world: "agent",
+    exports: { default: async },
+});
+
+use exports::openi::agent::handler::Message;
+use openi::agent::log::Level;
+
+/// Execution budget for one agent instance.
+#[derive(Debug, Clone, Copy)]
+pub struct WasmLimits {
+    /// Fuel for each call into the guest (`init` or one `handle`). Running
+    /// out traps, which fails the instance.
+    pub fuel: u64,
+    /// Linear memory, in bytes, for manifests without a memory limit.
+    pub memory: usize,
+    /// Fuel that one core-second of `resources.limits.cpu` buys.
+    pub fuel_per_cpu_second: u64,
+    /// Key/value state, in bytes of keys plus values, shared by an agent's replicas.
+    pub state: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self { fuel: 50_000_000, memory: 64 << 20, fuel_per_cpu_second: 1_000_000_000, state: 16 << 20 }
+    }
+}
+
+/// `StoreLimits`, remembering when memory growth was refused.
+struct Limiter {
+    inner: StoreLimits,
+    memory: usize,
+    refused: Option<usize>,
+}
+
+impl Limiter {
+    fn new(memory: usize) -> Self {
+        Self { inner: StoreLimitsBuilder::new().memory_size(memory).build(), memory, refused: None }
+    }
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> wasmtime::Result<bool> {
+        let allowed = self.inner.memory_growing(current, desired, maximum)?;
+        if !allowed && desired > self.memory {
+            self.refused = Some(desired);
+        }
+        Ok(allowed)
+    }
+
+    fn table_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> wasmtime::Result<bool> {
+        self.inner.table_growing(current, desired, maximum)
+    }
+}
+
+/// Fuel refilled continuously at `rate` per second, holding at most one
+/// second's worth.
+struct Budget {
+    rate: u64,
+    tokens: u64,
+    last: Instant,
+}
+
+impl Budget {
+    fn new(rate: u64) -> Self {
+        Self { rate, tokens: rate, last: Instant::now() }
+    }
+
+    fn available(&mut self) -> u64 {
+        let now = Instant::now();
+        let earned = (now - self.last).as_secs_f64() * self.rate as f64;
+        self.tokens = (self.tokens as f64 + earned).min(self.rate as f64) as u64;
+        self.last = now;
+        self.tokens
+    }
+
+    fn spend(&mut self, fuel: u64) {
+        self.tokens = self.tokens.saturating_sub(fuel);
+    }
+}
+
+type Kv = Arc<Mutex<State>>;
+
+/// An agent's key/value state and its size in bytes.
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, Vec<u8>>,
+    bytes: usize,
+}
+
+/// Store data for one instance: what the host functions can see.
+struct Host {
+    agent: String,
+    limiter: Limiter,
+    state: Kv,
+    state_cap: usize,
+    /// Size the state would have reached when a write was refused.
+    state_refused: Option<usize>,
+    /// Envelope being handled; publishes become its children.
+    inbound: Option<Envelope<Value>>,
+    outbox: Vec<Envelope<Value>>,
+    subscribe: Vec<String>,
+}
+
+impl openi::agent::bus::Host for Host {
+    fn publish(&mut self, topic: String, ctype: String, payload: String) -> Result<(), String> {
+        let ctype: ContentType = ctype.parse().map_err(|e| format!("ctype: {}", e))?;
+        let payload: Value = serde_json::from_str(&payload).map_err(|e| format!("payload: {}", e))?;
+        let env = Envelope::new(self.agent.clone(), topic, ctype, payload);
+        let env = match &self.inbound {
+            Some(parent) => env.child_of(parent),
+            None => env.ensure_trace(),
+        };
+        self.outbox.push(env);
+        Ok(())
+    }
+
+    fn subscribe(&mut self, pattern: String) -> Result<(), String> {
+        if pattern.is_empty() {
+            return Err("empty pattern".into());
+        }
+        self.subscribe.push(pattern);
+        Ok(())
+    }
+}
+
+impl openi::agent::log::Host for Host {
+    fn log(&mut self, level: Level, message: String) {
+        let agent = &self.agent;
+        let message = default_redactor().redact_str(&message);
+        match level {
+            Level::Trace => trace!(%agent, "{}", message),
+            Level::Debug => debug!(%agent, "{}", message),
+            Level::Info => info!(%agent, "{}", message),
+            Level::Warn => warn!(%agent, "{}", message),
+            Level::Error => error!(%agent, "{}", message),
+        }
+    }
+}
+
+impl openi::agent::state::Host for Host {
+    fn get(&mut self, key: String) -> Option<Vec<u8>> {
+        self.state.lock().entries.get(&key).cloned()
+    }
+
+    fn put(&mut self, key: String, value: Vec<u8>) {
+        let mut state = self.state.lock();
+        let old = state.entries.get(&key).map_or(0, |v| key.len() + v.len());
+        let bytes = state.bytes - old + key.len() + value.len();
+        if bytes > self.state_cap {
+            self.state_refused = Some(bytes);
+            return;
+        }
+        state.bytes = bytes;
+        state.entries.insert(key, value);
+    }
+}
+
+impl openi::agent::clock::Host for Host {
+    fn now_ms(&mut self) -> u64 {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+    }
+}
+
+/// Runs `runtime: wasm` agents. The module comes from `spec.source`: a
+/// path (relative to the manifest), `file://...`, or `registry://org/name:ver`,
+/// which resolves to `$OPENI_WASM_REGISTRY/org/name/ver.wasm`.
+pub struct WasmAdapter {
+    engine: Engine,
+    linker: Arc<Linker<Host>>,
+    bus: Arc<Bus>,
+    limits: WasmLimits,
+    identity: Option<Arc<NodeIdentity>>,
+    states: Mutex<HashMap<String, Kv>>,
+}
+
+impl WasmAdapter {
+    pub fn new(bus: Arc<Bus>) -> anyhow::Result<Self> {
+        let mut config = Config::new();
+        config.wasm_component_model(true).async_support(true).consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let mut linker = Linker::new(&engine);
+        Agent::add_to_linker::<_, HasSelf<Host>>(&mut linker, |h| h)?;
+        Ok(Self {
+            engine,
+            linker: Arc::new(linker),
+            bus,
+            limits: WasmLimits::default(),
+            identity: None,
+            states: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn with_limits(mut self, limits: WasmLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Issue each agent a credential at start and sign what it publishes.
+    pub fn with_identity(mut self, identity: Arc<NodeIdentity>) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    pub fn limits(&self) -> WasmLimits {
+        self.limits
+    }
+
+    /// A value from an agent's state, as last written by the guest.
+    pub fn state(&self, agent: &str, key: &str) -> Option<Vec<u8>> {
+        self.states.lock().get(agent)?.lock().entries.get(key).cloned()
+    }
+
+    fn module_path(&self, manifest: &AgentManifest) -> anyhow::Result<PathBuf> {
+        let source = manifest.source.as_deref().ok_or_else(|| anyhow!("manifest has no spec.source"))?;
+        if let Some(reference) = source.strip_prefix("registry://") {
+            let root = std::env::var("OPENI_WASM_REGISTRY")
+                .map_err(|_| anyhow!("{} needs OPENI_WASM_REGISTRY to be set", source))?;
+            let (name, version) = reference.rsplit_once(':').unwrap_or((reference, "latest"));
+            return Ok(Path::new(&root).join(name).join(format!("{}.wasm", version)));
+        }
+        let path = Path::new(source.strip_prefix("file://").unwrap_or(source));
+        let base = manifest.path.as_deref().and_then(Path::parent);
+        Ok(match base {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path.to_path_buf(),
+        })
+    }
+}
+
+#[async_trait]
+impl AgentAdapter for WasmAdapter {
+    fn name(&self) -> &'static str {
+        "wasm"
+    }
+
+    fn supports(&self, manifest: &AgentManifest) -> bool {
+        manifest.runtime == "wasm"
+    }
+
+    async fn start(&self, manifest: &AgentManifest, replica: u32) -> anyhow::Result<Box<dyn AgentInstance>> {
+        let path = self.module_path(manifest)?;
+        if let Some(identity) = &self.identity {
+            identity.issue(manifest)?;
+        }
+        let component =
+            Component::from_file(&self.engine, &path).with_context(|| format!("loading {}", path.display()))?;
+        let limits = ResourceLimits::from_map(&manifest.limits)?;
+        let memory = limits.memory_bytes.map_or(self.limits.memory, |b| b as usize);
+        let state = self.states.lock().entry(manifest.name.clone()).or_default().clone();
+        let host = Host {
+            agent: manifest.agent_uri(),
+            limiter: Limiter::new(memory),
+            state,
+            state_cap: self.limits.state,
+            state_refused: None,
+            inbound: None,
+            outbox: Vec::new(),
+            subscribe: Vec::new(),
+        };
+        let mut store = Store::new(&self.engine, host);
+        store.limiter(|h| &mut h.limiter);
+        store.fuel_async_yield_interval(Some(10_000))?;
+        store.set_fuel(self.limits.fuel)?;
+        let agent = Agent::instantiate_async(&mut store, &component, &self.linker).await?;
+
+        let config: Vec<(String, String)> = manifest.env.clone().into_iter().collect();
+        store.set_fuel(self.limits.fuel)?;
+        if let Err(msg) = agent.openi_agent_handler().call_init(&mut store, &config).await? {
+            bail!("init: {}", msg);
+        }
+        if let Some(bytes) = store.data_mut().state_refused.take() {
+            bail!("init: state would grow to {} bytes, over the limit of {}", bytes, self.limits.state);
+        }
+
+        let (inbox_tx, inbox) = mpsc::channel(1024);
+        let budget = limits.cpu_millis.map(|m| (m, Budget::new(m.saturating_mul(self.limits.fuel_per_cpu_second) / 1000)));
+        let mut guest = Guest {
+            bus: self.bus.clone(),
+            identity: self.identity.clone(),
+            member: replica.to_string(),
+            store,
+            agent,
+            fuel: self.limits.fuel,
+            budget,
+            inbox_tx,
+            forwarders: Vec::new(),
+        };
+        guest.flush().await;
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let (probe_tx, probes) = mpsc::channel(4);
+        let task = tokio::spawn(guest.run(inbox, probes, stop_rx));
+        info!("wasm agent {} replica {} started from {}", manifest.name, replica, path.display());
+        Ok(Box::new(WasmInstance { task, stop: Some(stop_tx), probe: Arc::new(WasmProbe(probe_tx)) }))
+    }
+}
+
+struct Guest {
+    bus: Arc<Bus>,
+    identity: Option<Arc<NodeIdentity>>,
+    /// Queue group member name: the replica number.
+    member: String,
+    store: Store<Host>,
+    agent: Agent,
+    fuel: u64,
+    /// CPU limit in millicores, and the fuel budget it allows.
+    budget: Option<(u64, Budget)>,
+    inbox_tx: mpsc::Sender<Envelope<Value>>,
+    forwarders: Vec<JoinHandle<()>>,
+}
+
+impl Guest {
+    async fn run(
+        mut self,
+        mut inbox: mpsc::Receiver<Envelope<Value>>,
+        mut probes: mpsc::Receiver<oneshot::Sender<ProbeReply>>,
+        mut stop: oneshot::Receiver<()>,
+    ) -> Exit {
+        let exit = loop {
+            let env = tokio::select! {
+                _ = &mut stop => break Exit::Completed,
+                Some(reply) = probes.recv() => {
+                    let _ = reply.send(ProbeReply::ready());
+                    continue;
+                }
+                env = inbox.recv() => match env {
+                    Some(env) => env,
+                    None => break Exit::Completed,
+                },
+            };
+            // A stop cuts a call short at its next fuel yield.
+            tokio::select! {
+                _ = &mut stop => break Exit::Completed,
+                handled = self.handle(env) => if let Err(exit) = handled {
+                    break exit;
+                },
+            }
+        };
+        for f in &self.forwarders {
+            f.abort();
+        }
+        exit
+    }
+
+    /// Traps and limit breaches end the instance; errors the guest reports
+    /// are only logged.
+    async fn handle(&mut self, env: Envelope<Value>) -> Result<(), Exit> {
+        let msg = Message {
+            id: env.id.clone(),
+            src: env.src.clone(),
+            topic: env.dest.clone(),
+            ctype: env.ctype.to_string(),
+            payload: env.payload.to_string(),
+        };
+        let available = self.budget.as_mut().map(|(_, b)| b.available());
+        let fuel = available.map_or(self.fuel, |a| a.min(self.fuel));
+        self.store.data_mut().inbound = Some(env);
+        let result = match self.store.set_fuel(fuel) {
+            Ok(()) => self.agent.openi_agent_handler().call_handle(&mut self.store, &msg).await,
+            Err(e) => Err(e),
+        };
+        self.store.data_mut().inbound = None;
+        let spent = fuel - self.store.get_fuel().unwrap_or(0);
+        if let Some((_, budget)) = &mut self.budget {
+            budget.spend(spent);
+        }
+
+        let memory = &mut self.store.data_mut().limiter;
+        if let Some(desired) = memory.refused.take() {
+            let breach = LimitBreach::memory(memory.memory as u64, format!("tried to grow to {} bytes", desired));
+            return Err(Exit::Breach(breach));
+        }
+        let host = self.store.data_mut();
+        if let Some(bytes) = host.state_refused.take() {
+            let breach = LimitBreach::memory(host.state_cap as u64, format!("state would grow to {} bytes", bytes));
+            return Err(Exit::Breach(breach));
+        }
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(reason)) => {
+                let reason = default_redactor().redact_str(&reason);
+                warn!("{} failed to handle {}: {}", self.store.data().agent, msg.id, reason)
+            }
+            Err(e) => {
+                let out_of_fuel = e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel);
+                return Err(match (&self.budget, out_of_fuel && fuel < self.fuel) {
+                    (Some((millis, _)), true) => {
+                        Exit::Breach(LimitBreach::cpu(*millis, format!("spent {} fuel with {} budgeted", spent, fuel)))
+                    }
+                    _ => Exit::Failed(format!("{:#}", e)),
+                });
+            }
+        }
+        self.flush().await;
+        Ok(())
+    }
+
+    /// Deliver what the last call published and open what it subscribed to.
+    async fn flush(&mut self) {
+        let host = self.store.data_mut();
+        let agent = host.agent.clone();
+        let outbox = std::mem::take(&mut host.outbox);
+        let patterns = std::mem::take(&mut host.subscribe);
+        for mut env in outbox {
+            if let Some(Err(e)) = self.identity.as_ref().map(|i| i.sign(&mut env)) {
+                warn!("{} could not sign {}: {}", agent, env.id, e);
+                continue;
+            }
+            let topic = env.dest.clone();
+            if let Err(e) = self.bus.publish(&topic, env).await {
+                warn!("{} publish to {}: {}", agent, topic, e);
+            }
+        }
+        for pattern in patterns {
+            // Replicas of the agent share its subscriptions.
+            let mut sub = self.bus.subscribe_queue(pattern, agent.clone(), self.member.clone());
+            let tx = self.inbox_tx.clone();
+            self.forwarders.push(tokio::spawn(async move {
+                while let Some(env) = sub.rx.recv().await {
+                    if tx.send(env).await.is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+    }
+}
+
+struct WasmInstance {
+    task: JoinHandle<Exit>,
+    stop: Option<oneshot::Sender<()>>,
+    probe: Arc<WasmProbe>,
+}
+
+/// Asks the guest's run loop for an answer.
+struct WasmProbe(mpsc::Sender<oneshot::Sender<ProbeReply>>);
+
+#[async_trait]
+impl Probe for WasmProbe {
+    async fn probe(&self, within: Duration) -> anyhow::Result<ProbeReply> {
+        let (tx, rx) = oneshot::channel();
+        self.0.send(tx).await.map_err(|_| anyhow!("instance has exited"))?;
+        tokio::time::timeout(within, rx).await.map_err(|_| anyhow!("busy for over {:?}", within))?.map_err(|_| anyhow!("instance has exited"))
+    }
+}
+
+#[async_trait]
+impl AgentInstance for WasmInstance {
+    async fn wait(&mut self) -> Exit {
+        (&mut self.task).await.unwrap_or_else(|e| Exit::Failed(e.to_string()))
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        match (&mut self.task).await? {
+            Exit::Failed(reason) => Err(anyhow!(reason)),
+            Exit::Breach(breach) => Err(anyhow!(breach.to_string())),
+            Exit::Completed => Ok(()),
+        }
+    }
+
+    fn probe(&self) -> Option<Arc<dyn Probe>> {
+        Some(self.probe.clone())
+    }
+}
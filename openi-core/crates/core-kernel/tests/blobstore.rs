@@ -0,0 +1,54 @@
+//! Claim checks against the node's filesystem blob store.
+
+use openi_core_fabric::blob::check_out;
+use openi_core_fabric::{BlobStore, Bus, ClaimCheckPolicy, ContentType, Envelope};
+use openi_core_kernel::agents::AgentManager;
+use openi_core_kernel::blobstore::FsBlobStore;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+fn store(name: &str) -> Arc<FsBlobStore> {
+    let dir = std::env::temp_dir().join(format!("openi-blobs-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    Arc::new(FsBlobStore::open(dir).unwrap())
+}
+
+#[tokio::test]
+async fn claim_checks_are_leased_from_the_moment_they_are_stored() {
+    let manager = AgentManager::new(Arc::new(Bus::new()));
+    manager.set_blob_store(store("leased"));
+    let store = manager.blob_store().unwrap();
+
+    // An unleased copy is already on disk: storing it again must lease it.
+    let payload = json!({ "scan": "x".repeat(1024) });
+    let unleased = store.put(&serde_json::to_vec(&payload).unwrap()).unwrap();
+    let policy = ClaimCheckPolicy { threshold: 16, default_retention: Duration::from_secs(60) };
+    let env = Envelope::new("agent://local/a", "topic://t", ContentType::mime("application", "json"), payload.clone());
+    let checked = policy.check_in(&env, store.as_ref()).unwrap().unwrap();
+    assert_eq!(store.refcount(&unleased).unwrap(), 1);
+
+    assert_eq!(store.gc(SystemTime::now()).unwrap(), 0);
+    assert_eq!(check_out(checked.clone(), store.as_ref()).unwrap().payload, payload);
+    assert_eq!(store.gc(SystemTime::now() + Duration::from_secs(120)).unwrap(), 1);
+    assert!(check_out(checked, store.as_ref()).is_err());
+}
+
+#[test]
+fn concurrent_gc_never_removes_a_blob_being_stored() {
+    let store = store("race");
+    let gc = {
+        let store = store.clone();
+        std::thread::spawn(move || {
+            for _ in 0..200 {
+                store.gc(SystemTime::now()).unwrap();
+            }
+        })
+    };
+    for n in 0..200 {
+        let until = SystemTime::now() + Duration::from_secs(60);
+        let blob = store.put_retained(format!("blob {}", n).as_bytes(), "holder", Some(until)).unwrap();
+        store.retain(&blob, "second", None).unwrap();
+    }
+    gc.join().unwrap();
+}
@@ -0,0 +1,52 @@
+//! `security.encryption` from manifests, enforced at registration.
+
+use openi_core_fabric::{Bus, ContentType, DeliveryError, Envelope, SealError};
+use openi_core_kernel::agents::{AgentError, AgentManager};
+use openi_core_kernel::manifest::AgentManifest;
+use openi_core_kernel::process::ProcessAdapter;
+use serde_json::json;
+use std::sync::Arc;
+
+fn agent(name: &str, encryption: &str) -> AgentManifest {
+    let yaml = format!(
+        "kind: Agent\nname: {}\nruntime: rust\ntopics:\n  publish: [\"topic://{}/out\"]\nsecurity:\n  encryption: {}\n",
+        name, name, encryption
+    );
+    AgentManifest::from_yaml(&yaml).unwrap()
+}
+
+fn cleartext(topic: &str) -> Envelope<serde_json::Value> {
+    Envelope::new("agent://local/x", topic, ContentType::mime("application", "json"), json!({"mrn": 1}))
+}
+
+#[tokio::test]
+async fn aes256_agents_only_get_sealed_payloads_on_their_outputs() {
+    let bus = Arc::new(Bus::new());
+    let manager = AgentManager::new(bus.clone());
+    manager.register(agent("notes", "aes256")).await.unwrap();
+    manager.register(agent("metrics", "none")).await.unwrap();
+
+    let err = bus.publish("topic://notes/out", cleartext("topic://notes/out")).await.unwrap_err();
+    assert!(matches!(err, DeliveryError::Seal(SealError::Required(_))), "{}", err);
+    bus.publish("topic://metrics/out", cleartext("topic://metrics/out")).await.unwrap();
+}
+
+#[tokio::test]
+async fn unsupported_modes_fail_registration() {
+    let manager = AgentManager::new(Arc::new(Bus::new()));
+    let err = manager.register(agent("legacy", "rsa2048")).await.unwrap_err();
+    assert!(matches!(err, AgentError::Encryption(SealError::Unsupported(_))), "{}", err);
+    let err = manager.register(agent("typo", "aes128")).await.unwrap_err();
+    assert!(matches!(err, AgentError::Encryption(SealError::UnknownMode(_))), "{}", err);
+    assert!(manager.list().is_empty());
+}
+
+#[tokio::test]
+async fn kernel_run_agents_cannot_require_sealing() {
+    let bus = Arc::new(Bus::new());
+    let manager = AgentManager::new(bus.clone());
+    manager.add_adapter(Arc::new(ProcessAdapter::new(bus)));
+    let err = manager.register(agent("notes", "aes256")).await.unwrap_err();
+    assert!(matches!(err, AgentError::Unsealed { adapter: "process", .. }), "{}", err);
+    manager.register(agent("metrics", "none")).await.unwrap();
+}
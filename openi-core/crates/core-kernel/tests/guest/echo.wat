@@ -0,0 +1,90 @@
+;; Sample `openi:agent` guest, written against the canonical ABI by hand so
+;; the tests need no wasm toolchain. `tests/wasm_agent.rs` wraps it into a
+;; component with wit-component.
+;;
+;; init:   subscribes to topic://echo/in and logs "echo ready".
+;; handle: bumps the `count` state key, stores the clock in `last-ms`, and
+;;         republishes the payload on topic://echo/out. The JSON payloads
+;;         "spin" and "grow" loop forever and grow memory by 16 MiB, to
+;;         exercise the fuel and memory limits.
+(module
+  (import "openi:agent/bus@0.1.0" "publish" (func $publish (param i32 i32 i32 i32 i32 i32 i32)))
+  (import "openi:agent/bus@0.1.0" "subscribe" (func $subscribe (param i32 i32 i32)))
+  (import "openi:agent/log@0.1.0" "log" (func $log (param i32 i32 i32)))
+  (import "openi:agent/state@0.1.0" "get" (func $state_get (param i32 i32 i32)))
+  (import "openi:agent/state@0.1.0" "put" (func $state_put (param i32 i32 i32 i32)))
+  (import "openi:agent/clock@0.1.0" "now-ms" (func $now_ms (result i64)))
+
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  (data (i32.const 16) "topic://echo/in")
+  (data (i32.const 32) "topic://echo/out")
+  (data (i32.const 48) "application/json")
+  (data (i32.const 64) "echo ready")
+  (data (i32.const 80) "count")
+  (data (i32.const 96) "last-ms")
+
+  ;; 128: import return area, 144: export return area,
+  ;; 160: count (u32), 168: last-ms (u64).
+
+  (func (export "cabi_realloc") (param $old i32) (param $old_size i32) (param $align i32) (param $size i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr
+      (i32.and
+        (i32.add (global.get $heap) (i32.sub (local.get $align) (i32.const 1)))
+        (i32.sub (i32.const 0) (local.get $align))))
+    (global.set $heap (i32.add (local.get $ptr) (local.get $size)))
+    (if (i32.gt_u (global.get $heap) (i32.mul (memory.size) (i32.const 65536)))
+      (then
+        (if (i32.eq
+              (memory.grow (i32.add (i32.shr_u (i32.sub (global.get $heap) (i32.mul (memory.size) (i32.const 65536))) (i32.const 16)) (i32.const 1)))
+              (i32.const -1))
+          (then unreachable))))
+    (if (local.get $old_size)
+      (then (memory.copy (local.get $ptr) (local.get $old) (local.get $old_size))))
+    (local.get $ptr))
+
+  (func (export "openi:agent/handler@0.1.0#init") (param $config i32) (param $len i32) (result i32)
+    (call $subscribe (i32.const 16) (i32.const 15) (i32.const 128))
+    (call $log (i32.const 2) (i32.const 64) (i32.const 10))
+    (i32.store8 (i32.const 144) (i32.const 0))
+    (i32.const 144))
+
+  (func (export "openi:agent/handler@0.1.0#handle")
+    (param $id i32) (param $id_len i32)
+    (param $src i32) (param $src_len i32)
+    (param $topic i32) (param $topic_len i32)
+    (param $ctype i32) (param $ctype_len i32)
+    (param $payload i32) (param $payload_len i32)
+    (result i32)
+    (local $count i32)
+
+    (if (i32.eq (local.get $payload_len) (i32.const 6))
+      (then
+        ;; "spin"
+        (if (i32.eq (i32.load (i32.add (local.get $payload) (i32.const 1))) (i32.const 0x6e697073))
+          (then (loop $forever (br $forever))))
+        ;; "grow"
+        (if (i32.eq (i32.load (i32.add (local.get $payload) (i32.const 1))) (i32.const 0x776f7267))
+          (then
+            (if (i32.eq (memory.grow (i32.const 256)) (i32.const -1))
+              (then unreachable))))))
+
+    (call $state_get (i32.const 80) (i32.const 5) (i32.const 128))
+    (if (i32.load8_u (i32.const 128))
+      (then (local.set $count (i32.load (i32.load (i32.const 132))))))
+    (i32.store (i32.const 160) (i32.add (local.get $count) (i32.const 1)))
+    (call $state_put (i32.const 80) (i32.const 5) (i32.const 160) (i32.const 4))
+
+    (i64.store (i32.const 168) (call $now_ms))
+    (call $state_put (i32.const 96) (i32.const 7) (i32.const 168) (i32.const 8))
+
+    (call $publish
+      (i32.const 32) (i32.const 16)
+      (i32.const 48) (i32.const 16)
+      (local.get $payload) (local.get $payload_len)
+      (i32.const 128))
+    (i32.store8 (i32.const 144) (i32.const 0))
+    (i32.const 144))
+)
@@ -0,0 +1,78 @@
+//! Node certificates and agent credentials: who may sign as whom.
+
+use openi_core_fabric::{Bus, ContentType, DeliveryError, Envelope, Keypair, Signer};
+use openi_core_kernel::agents::AgentManager;
+use openi_core_kernel::identity::{IdentityError, IdentityVerifier, NodeCertificate, NodeIdentity, CLOCK_SKEW};
+use openi_core_kernel::manifest::AgentManifest;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn input(payload: Value) -> Envelope<Value> {
+    Envelope::new("agent://local/test", "topic://echo/in", ContentType::mime("application", "json"), payload)
+        .ensure_trace()
+}
+
+#[tokio::test]
+async fn agents_sign_as_their_credentialed_uri() {
+    let tenant = Keypair::from_seed(&[7; 32]);
+    let node = Keypair::generate();
+    let day = Duration::from_secs(24 * 60 * 60);
+    let cert = NodeCertificate::issue(&Signer::new(tenant.clone()), "acme", "node-1", node.public_key_base64(), day)
+        .unwrap();
+    let verifier = Arc::new(IdentityVerifier::new());
+    verifier.trust_tenant("acme", &tenant.public_key_base64()).unwrap();
+    verifier.require("topic://echo/*");
+    let identity = Arc::new(NodeIdentity::new(cert.clone(), node, verifier.clone()).unwrap());
+
+    let bus = Arc::new(Bus::new());
+    let manager = AgentManager::new(bus.clone());
+    manager.set_identity(identity.clone());
+    manager.register(AgentManifest::from_yaml("kind: Agent\nname: signed\nruntime: rust\n").unwrap()).await.unwrap();
+    // What an adapter does when it starts the agent.
+    let manifest = manager.manifest("signed").unwrap();
+    identity.issue(&manifest).unwrap();
+    let mut out = bus.subscribe("topic://echo/out");
+
+    // Unsigned envelopes are refused on required topics, except from the fabric.
+    let Err(DeliveryError::Identity(why)) = bus.publish("topic://echo/in", input(json!(0))).await else {
+        panic!("unsigned input accepted")
+    };
+    assert!(why.contains("unsigned"), "{}", why);
+    let mut sent = input(json!(1));
+    sent.src = "agent://fabric/test".into();
+    bus.publish("topic://echo/in", sent.clone()).await.unwrap();
+
+    let mut echo = Envelope::new(manifest.agent_uri(), "topic://echo/out", sent.ctype.clone(), json!(1));
+    echo = echo.child_of(&sent);
+    identity.sign(&mut echo).unwrap();
+    bus.publish("topic://echo/out", echo).await.unwrap();
+    let echoed = out.rx.try_recv().unwrap();
+    assert_eq!(echoed.src, "agent://acme/node-1/signed");
+    let credential = verifier.verify(&echoed).unwrap();
+    assert_eq!(credential.agent, echoed.src);
+    assert_eq!(Some(credential.manifest), manifest.digest);
+
+    let mut tampered = echoed.clone();
+    tampered.payload = json!(2);
+    assert!(matches!(verifier.verify(&tampered), Err(IdentityError::BadSignature(_))));
+    let Err(DeliveryError::Identity(_)) = bus.publish("topic://echo/out", tampered).await else {
+        panic!("tampered echo accepted")
+    };
+    let mut spoofed = echoed.clone();
+    spoofed.src = "agent://acme/node-1/other".into();
+    assert!(matches!(verifier.verify(&spoofed), Err(IdentityError::UnknownAgent(_))));
+
+    // A certificate is only as good as the tenant key it verifies under.
+    let stranger = IdentityVerifier::new();
+    stranger.trust_tenant("acme", &Keypair::generate().public_key_base64()).unwrap();
+    assert!(matches!(stranger.admit_node(cert.clone()), Err(IdentityError::BadSignature(_))));
+
+    // Nor is one issued further in the future than the allowed clock skew.
+    let mut early = cert;
+    early.issued_at = (time::OffsetDateTime::now_utc() + CLOCK_SKEW * 2)
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap();
+    early.sig = Signer::new(tenant).sign_bytes(&early.signed_bytes()).unwrap();
+    assert!(matches!(verifier.admit_node(early), Err(IdentityError::NotYetValid(_))));
+}
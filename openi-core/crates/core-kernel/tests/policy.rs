@@ -0,0 +1,76 @@
+//! Policy decisions on publish and on delivery to agents.
+
+use openi_core_fabric::envelope::header;
+use openi_core_fabric::{Bus, ContentType, DeliveryError, Envelope};
+use openi_core_kernel::agents::AgentManager;
+use openi_core_kernel::manifest::AgentManifest;
+use openi_core_kernel::policy::{Action, Policy, PolicyEngine, Resource};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+fn input(payload: Value) -> Envelope<Value> {
+    Envelope::new("agent://local/test", "topic://echo/in", ContentType::mime("application", "json"), payload)
+        .ensure_trace()
+}
+
+#[tokio::test]
+async fn policy_decides_who_publishes_and_who_receives() {
+    let policy = Policy::from_yaml(
+        r#"
+default: deny
+rules:
+  - id: test-feeds-echo
+    effect: allow
+    subject: { agent: "agent://local/test" }
+    action: publish
+    resource: { topic: "topic://echo/*" }
+  - id: triage-reads-echo
+    effect: allow
+    subject: { role: triage }
+    action: subscribe
+    resource: { topic: "topic://echo/*" }
+  - id: no-phi-writes
+    effect: deny
+    description: nobody writes PHI here
+    action: publish
+    resource: { scope: "phi:*" }
+"#,
+    )
+    .unwrap();
+    let bus = Arc::new(Bus::new());
+    let manager = AgentManager::new(bus.clone());
+    let manifest = AgentManifest::from_yaml("kind: Agent\nname: guarded\nruntime: rust\nsecurity:\n  role: triage\n");
+    manager.register(manifest.unwrap()).await.unwrap();
+    let engine = Arc::new(PolicyEngine::new(policy));
+    manager.set_policy(engine.clone());
+    let mut guarded = bus.subscribe_queue("topic://echo/in", "agent://local/guarded", "0");
+
+    // Allowed in, but the agent may not publish its echo.
+    let sent = input(json!(1));
+    bus.publish("topic://echo/in", sent.clone()).await.unwrap();
+    assert_eq!(guarded.rx.try_recv().unwrap().id, sent.id);
+    let echo = Envelope::new("agent://local/guarded", "topic://echo/out", sent.ctype.clone(), json!(1)).child_of(&sent);
+    let Err(DeliveryError::Denied(why)) = bus.publish("topic://echo/out", echo).await else { panic!("not denied") };
+    assert!(why.contains("denied by default"), "{}", why);
+
+    let mut stranger = input(json!(2));
+    stranger.src = "agent://local/stranger".into();
+    let Err(DeliveryError::Denied(why)) = bus.publish("topic://echo/in", stranger).await else { panic!("not denied") };
+    assert!(why.contains("denied by default"), "{}", why);
+
+    let phi = input(json!(3)).with_header(header::SCOPES, "phi:write");
+    let Err(DeliveryError::Denied(why)) = bus.publish("topic://echo/in", phi).await else { panic!("not denied") };
+    assert!(why.contains("no-phi-writes") && why.contains("nobody writes PHI here"), "{}", why);
+
+    let decision = engine.check("agent://local/guarded", Action::Subscribe, Resource {
+        topic: Some("topic://echo/in".into()),
+        ..Resource::default()
+    });
+    assert!(decision.allowed);
+    assert_eq!(decision.rule.as_deref(), Some("triage-reads-echo"));
+
+    // Without a subscribe rule the agent is never handed the input.
+    engine.replace(Policy::from_yaml("default: deny\nrules:\n  - effect: allow\n    action: publish\n").unwrap());
+    bus.publish("topic://echo/in", input(json!(4))).await.unwrap();
+    assert!(guarded.rx.try_recv().is_err());
+}
@@ -0,0 +1,136 @@
+//! Runs this test binary as a native agent over stdio and over a Unix
+//! socket. With `OPENI_AGENT` set it plays the agent: it echoes whatever
+//! it is sent to `topic://echo/out`. Built with `harness = false` so that
+//! nothing but frames is written to stdout.
+
+use openi_core_fabric::{Bus, Codec, ContentType, Envelope, FramedConnection, Role};
+use openi_core_kernel::agents::{AgentError, AgentManager};
+use openi_core_kernel::manifest::{AgentManifest, ManifestError};
+use openi_core_kernel::process::ProcessAdapter;
+use serde_json::{json, Value};
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::UnixStream;
+use tokio::time::timeout;
+
+fn main() {
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    if std::env::var_os("OPENI_AGENT").is_some() {
+        return rt.block_on(agent());
+    }
+    for transport in ["stdio", "uds"] {
+        rt.block_on(echo_round_trip(transport));
+        println!("test echo_round_trip_over_{} ... ok", transport);
+    }
+    rt.block_on(a_cgroup_it_cannot_join_fails_the_start());
+    println!("test a_cgroup_it_cannot_join_fails_the_start ... ok");
+    names_that_are_not_one_path_component_are_refused();
+    println!("test names_that_are_not_one_path_component_are_refused ... ok");
+    rt.block_on(bad_limits_are_refused_at_registration());
+    println!("test bad_limits_are_refused_at_registration ... ok");
+}
+
+async fn agent() {
+    let uri = std::env::var("OPENI_AGENT_URI").unwrap();
+    type Pipes = (Box<dyn AsyncRead + Unpin + Send>, Box<dyn AsyncWrite + Unpin + Send>);
+    let (reader, writer): Pipes = match std::env::var("OPENI_TRANSPORT").unwrap().as_str() {
+        "uds" => {
+            let stream = UnixStream::connect(std::env::var("OPENI_SOCKET").unwrap()).await.unwrap();
+            let (r, w) = stream.into_split();
+            (Box::new(r), Box::new(w))
+        }
+        _ => (Box::new(tokio::io::stdin()), Box::new(tokio::io::stdout())),
+    };
+    let mut conn = FramedConnection::handshake(reader, writer, Role::Acceptor, &Codec::ALL).await.unwrap();
+    while let Ok(Some(env)) = conn.recv::<Value>().await {
+        let reply = Envelope::new(uri.clone(), "topic://echo/out", env.ctype.clone(), env.payload.clone()).child_of(&env);
+        if conn.send(&reply).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// This binary as agent `name`; `extra` is appended to its manifest.
+fn echo_manifest(name: &str, transport: &str, extra: &str) -> AgentManifest {
+    AgentManifest::from_yaml(&format!(
+        "kind: Agent\nname: {}\nruntime: rust\nsource: {}\ntransport: {}\n\
+         topics:\n  subscribe: [\"topic://echo/in\"]\n  publish: [\"topic://echo/out\"]\n{}",
+        name,
+        std::env::current_exe().unwrap().display(),
+        transport,
+        extra
+    ))
+    .unwrap()
+}
+
+async fn echo_round_trip(transport: &str) {
+    let name = format!("echo-{}", transport);
+    let manifest = echo_manifest(&name, transport, "");
+    let sockets = std::env::temp_dir().join(format!("openi-process-{}", std::process::id()));
+
+    let bus = Arc::new(Bus::new());
+    let manager = AgentManager::new(bus.clone());
+    manager.add_adapter(Arc::new(ProcessAdapter::new(bus.clone()).with_socket_dir(&sockets)));
+    manager.register(manifest).await.unwrap();
+    let mut out = bus.subscribe("topic://echo/out");
+    manager.start(&name).await.unwrap();
+
+    let input = Envelope::new("agent://local/test", "topic://echo/in", ContentType::mime("application", "json"), json!({"n": 1}));
+    bus.publish("topic://echo/in", input.clone()).await.unwrap();
+    let reply = timeout(Duration::from_secs(30), out.rx.recv()).await.unwrap().unwrap();
+    assert_eq!(reply.src, format!("agent://local/{}", name));
+    assert_eq!(reply.payload, json!({"n": 1}));
+    assert_eq!(reply.headers.get("causation_id"), Some(&input.id));
+
+    if transport == "uds" {
+        let mode = std::fs::metadata(&sockets).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+    manager.stop(&name).await.unwrap();
+    let _ = std::fs::remove_dir_all(&sockets);
+}
+
+async fn a_cgroup_it_cannot_join_fails_the_start() {
+    // Looks enough like a cgroup v2 hierarchy for the group to be created,
+    // but has no `cgroup.procs` to join.
+    let hierarchy = std::env::temp_dir().join(format!("openi-cgroup-{}", std::process::id()));
+    std::fs::create_dir_all(&hierarchy).unwrap();
+    std::fs::write(hierarchy.join("cgroup.controllers"), "cpu memory").unwrap();
+
+    let bus = Arc::new(Bus::new());
+    let manager = AgentManager::new(bus.clone());
+    let adapter = ProcessAdapter::new(bus.clone()).with_cgroup_root(hierarchy.join("openi"));
+    manager.add_adapter(Arc::new(adapter));
+    manager.register(echo_manifest("boxed", "stdio", "resources:\n  limits:\n    memory: 64Mi\n")).await.unwrap();
+    let err = manager.start("boxed").await.unwrap_err();
+    assert!(err.to_string().contains(&format!("in {}", hierarchy.join("openi/boxed-0").display())), "{}", err);
+    let _ = std::fs::remove_dir_all(&hierarchy);
+}
+
+fn names_that_are_not_one_path_component_are_refused() {
+    for name in ["../escape", "a/b", ".hidden", "\"a b\"", "\"tab\\there\""] {
+        let yaml = format!("kind: Agent\nname: {}\nruntime: rust\n", name);
+        assert!(matches!(AgentManifest::from_yaml(&yaml), Err(ManifestError::Name(_))), "{}", name);
+    }
+    assert!(AgentManifest::from_yaml("kind: Agent\nname: lab-results.v2_a\nruntime: rust\n").is_ok());
+}
+
+async fn bad_limits_are_refused_at_registration() {
+    let bus = Arc::new(Bus::new());
+    let manager = AgentManager::new(bus.clone());
+    manager.add_adapter(Arc::new(ProcessAdapter::new(bus)));
+    for (limits, expected) in [
+        ("cpu: 0", "resources.limits.cpu: \"0\" must be more than zero"),
+        ("memory: 0Mi", "resources.limits.memory: \"0Mi\" must be more than zero"),
+        ("memory: lots", "resources.limits.memory: invalid quantity \"lots\""),
+    ] {
+        let manifest = echo_manifest("limited", "stdio", &format!("resources:\n  limits:\n    {}\n", limits));
+        let err = manager.register(manifest).await.unwrap_err();
+        assert!(matches!(err, AgentError::Resources(_)), "{}", err);
+        assert_eq!(err.to_string(), expected);
+    }
+    assert!(manager.status("limited").is_none());
+    manager.register(echo_manifest("limited", "stdio", "resources:\n  limits:\n    cpu: 1m\n")).await.unwrap();
+}
@@ -0,0 +1,102 @@
+//! Role grants from the example healthcare policy, assigned from manifests.
+
+use openi_core_fabric::Bus;
+use openi_core_kernel::agents::{AgentError, AgentManager};
+use openi_core_kernel::manifest::AgentManifest;
+use openi_core_kernel::policy::{Action, Policy, PolicyEngine, PolicyError, Resource};
+use openi_core_kernel::rbac::RbacError;
+use std::path::Path;
+use std::sync::Arc;
+
+const CRATE: &str = env!("CARGO_MANIFEST_DIR");
+
+fn agent(name: &str, security: &str) -> AgentManifest {
+    AgentManifest::from_yaml(&format!("kind: Agent\nname: {}\nruntime: rust\nsecurity:\n{}", name, security)).unwrap()
+}
+
+fn topic(t: &str) -> Resource {
+    Resource { topic: Some(t.into()), ..Resource::default() }
+}
+
+async fn node() -> Arc<AgentManager> {
+    let path = Path::new(CRATE).join("../../manifests/examples/HealthcareAgentClass/Blueprint/policy.yaml");
+    let manager = AgentManager::new(Arc::new(Bus::new()));
+    manager.register(agent("intake-voice", "  role: intake\n")).await.unwrap();
+    manager.set_policy(Arc::new(PolicyEngine::load(path).unwrap()));
+    manager.register(agent("clinical-review", "  role: clinician\n")).await.unwrap();
+    manager.register(agent("chart-reader", "  role: clinician\n  access_level: read\n")).await.unwrap();
+    manager.register(agent("rcm-claims", "  role: rcm\n")).await.unwrap();
+    manager
+}
+
+#[tokio::test]
+async fn roles_grant_topics_and_scopes_through_inheritance() {
+    let manager = node().await;
+
+    let d = manager.can("intake-voice", Action::Publish, topic("topic://intake/voice/transcript")).unwrap();
+    assert!(d.allowed, "{}", d);
+    assert_eq!(d.rule.as_deref(), Some("role intake"));
+    let d = manager.can("intake-voice", Action::Subscribe, topic("topic://triage/rankings")).unwrap();
+    assert!(!d.allowed, "{}", d);
+
+    // clinician <- triage <- intake
+    let d = manager.can("clinical-review", Action::Publish, topic("topic://intake/text/response")).unwrap();
+    assert!(d.allowed, "{}", d);
+    assert!(d.explanation.contains("from intake"), "{}", d);
+    let phi = Resource { scopes: vec!["phi:read".into()], ..topic("topic://emr/notes") };
+    let d = manager.can("agent://local/clinical-review", Action::Subscribe, phi.clone()).unwrap();
+    assert!(d.allowed, "{}", d);
+    assert!(d.explanation.contains("from triage"), "{}", d);
+
+    let d = manager.can("chart-reader", Action::Subscribe, topic("topic://emr/notes")).unwrap();
+    assert!(d.allowed, "{}", d);
+    let d = manager.can("chart-reader", Action::Publish, topic("topic://emr/notes")).unwrap();
+    assert!(!d.allowed);
+    assert!(d.explanation.contains("access_level is read"), "{}", d);
+
+    let d = manager.can("rcm-claims", Action::Publish, topic("topic://claims/837")).unwrap();
+    assert!(d.allowed, "{}", d);
+    let claims_phi = Resource { scopes: vec!["phi:read".into()], ..topic("topic://claims/837") };
+    let d = manager.can("rcm-claims", Action::Subscribe, claims_phi).unwrap();
+    assert!(!d.allowed);
+    assert_eq!(d.rule.as_deref(), Some("no-phi-for-billing"));
+
+    assert!(matches!(manager.can("nobody", Action::Access, phi), Err(AgentError::Unknown(_))));
+}
+
+#[tokio::test]
+async fn bad_roles_and_access_levels_are_refused() {
+    let manager = AgentManager::new(Arc::new(Bus::new()));
+    let err = manager.register(agent("sloppy", "  role: admin\n  access_level: sometimes\n")).await.unwrap_err();
+    assert!(matches!(err, AgentError::Rbac(RbacError::AccessLevel(_))), "{}", err);
+
+    let err = Policy::from_yaml("roles:\n  a: { inherits: b }\n").unwrap_err();
+    assert!(matches!(err, PolicyError::Rbac(RbacError::UnknownParent { .. })), "{}", err);
+    let err = Policy::from_yaml("roles:\n  a: { inherits: b }\n  b: { inherits: a }\n").unwrap_err();
+    assert!(matches!(err, PolicyError::Rbac(RbacError::Cycle(_))), "{}", err);
+}
+
+#[tokio::test]
+async fn access_levels_cap_rule_and_default_allows() {
+    let policy = Policy::from_yaml(
+        "default: allow\nrules:\n  - id: open-notes\n    effect: allow\n    resource: { topic: \"topic://emr/*\" }\n",
+    )
+    .unwrap();
+    let manager = AgentManager::new(Arc::new(Bus::new()));
+    manager.set_policy(Arc::new(PolicyEngine::new(policy)));
+    manager.register(agent("reader", "  access_level: read\n")).await.unwrap();
+    manager.register(agent("writer", "  access_level: write\n")).await.unwrap();
+
+    let d = manager.can("reader", Action::Subscribe, topic("topic://emr/notes")).unwrap();
+    assert!(d.allowed, "{}", d);
+    let d = manager.can("reader", Action::Publish, topic("topic://emr/notes")).unwrap();
+    assert!(!d.allowed, "{}", d);
+    assert_eq!(d.rule.as_deref(), Some("open-notes"));
+    assert!(d.explanation.contains("access_level is read"), "{}", d);
+
+    let d = manager.can("writer", Action::Subscribe, topic("topic://billing/claims")).unwrap();
+    assert!(!d.allowed, "{}", d);
+    assert!(d.explanation.contains("access_level is write"), "{}", d);
+    let d = manager.can("writer", Action::Publish, topic("topic://billing/claims")).unwrap();
+    assert!(d.allowed, "{}", d);
+}
@@ -0,0 +1,67 @@
+//! Scope checks on delivery, for agents and for anonymous subscriptions.
+
+use openi_core_fabric::envelope::header;
+use openi_core_fabric::{Bus, ContentType, Envelope, Subscription};
+use openi_core_kernel::agents::AgentManager;
+use openi_core_kernel::manifest::AgentManifest;
+use openi_core_kernel::scopes::ScopeViolation;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// A node with agent `scoped` registered, and a subscription standing in
+/// for its one replica.
+async fn node() -> (Arc<Bus>, Arc<AgentManager>, Subscription) {
+    let bus = Arc::new(Bus::new());
+    let manager = AgentManager::new(bus.clone());
+    let manifest = AgentManifest::from_yaml(
+        "kind: Agent\nname: scoped\nruntime: rust\npolicies:\n  scopes: [\"schema:read\", \"echo:*\"]\n",
+    )
+    .unwrap();
+    manager.register(manifest).await.unwrap();
+    let agent = bus.subscribe_queue("topic://echo/in", "agent://local/scoped", "0");
+    (bus, manager, agent)
+}
+
+fn input(payload: Value) -> Envelope<Value> {
+    Envelope::new("agent://local/test", "topic://echo/in", ContentType::mime("application", "json"), payload)
+        .ensure_trace()
+}
+
+#[tokio::test]
+async fn deliveries_need_the_receivers_scopes() {
+    let (bus, _manager, mut agent) = node().await;
+    let mut control = bus.subscribe("fabric.control");
+
+    let held = input(json!(1)).with_header(header::SCOPES, "schema:read, echo:write");
+    bus.publish("topic://echo/in", held.clone()).await.unwrap();
+    assert_eq!(agent.rx.try_recv().unwrap().id, held.id);
+
+    let phi = input(json!(2)).with_header(header::SCOPES, "schema:read,phi:read");
+    bus.publish("topic://echo/in", phi.clone()).await.unwrap();
+    let report = timeout(Duration::from_secs(5), control.rx.recv()).await.unwrap().unwrap();
+    assert_eq!(report.ctype, ScopeViolation::ctype());
+    assert_eq!(report.headers.get(header::CAUSATION_ID), Some(&phi.id));
+    let violation: ScopeViolation = serde_json::from_value(report.payload).unwrap();
+    assert_eq!(violation.agent, "agent://local/scoped");
+    assert_eq!(violation.envelope, phi.id);
+    assert_eq!(violation.missing, vec!["phi:read".to_string()]);
+    assert!(agent.rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn anonymous_subscriptions_never_receive_scoped_envelopes() {
+    let (bus, _manager, mut agent) = node().await;
+    let mut anonymous = bus.subscribe("topic://echo/in");
+
+    let scoped = input(json!(1)).with_header(header::SCOPES, "echo:read");
+    bus.publish("topic://echo/in", scoped.clone()).await.unwrap();
+    assert_eq!(agent.rx.try_recv().unwrap().id, scoped.id);
+    assert!(anonymous.rx.try_recv().is_err());
+
+    let plain = input(json!(2));
+    bus.publish("topic://echo/in", plain.clone()).await.unwrap();
+    assert_eq!(agent.rx.try_recv().unwrap().id, plain.id);
+    assert_eq!(anonymous.rx.try_recv().unwrap().id, plain.id);
+}
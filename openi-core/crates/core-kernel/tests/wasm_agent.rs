@@ -0,0 +1,313 @@
+#![cfg(feature = "wasm")]
+//! Runs the sample guest in `tests/guest/echo.wat` through `AgentManager`.
+
+use openi_core_fabric::envelope::header;
+use openi_core_fabric::{Bus, ContentType, Envelope, Keypair, Signer};
+use openi_core_kernel::agents::{AgentManager, AgentState, LifecycleEvent, LIFECYCLE_TOPIC};
+use openi_core_kernel::health::{HealthEvent, HEALTH_TOPIC};
+use openi_core_kernel::identity::{IdentityVerifier, NodeCertificate, NodeIdentity};
+use openi_core_kernel::manifest::AgentManifest;
+use openi_core_kernel::supervisor::SupervisorConfig;
+use openi_core_kernel::wasm::{WasmAdapter, WasmLimits};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+
+const CRATE: &str = env!("CARGO_MANIFEST_DIR");
+
+fn guest_component(dir: &Path) -> PathBuf {
+    let mut module = wat::parse_file(Path::new(CRATE).join("tests/guest/echo.wat")).unwrap();
+    let mut resolve = wit_parser::Resolve::default();
+    let (pkg, _) = resolve.push_dir(Path::new(CRATE).join("wit")).unwrap();
+    let world = resolve.select_world(&[pkg], Some("agent")).unwrap();
+    wit_component::embed_component_metadata(&mut module, &resolve, world, wit_component::StringEncoding::UTF8)
+        .unwrap();
+    let component = wit_component::ComponentEncoder::default().module(&module).unwrap().validate(true).encode().unwrap();
+    let path = dir.join("echo.wasm");
+    std::fs::write(&path, component).unwrap();
+    path
+}
+
+/// A registered echo agent on a fresh bus; `extra` is appended to its manifest.
+async fn echo_agent(name: &str, limits: WasmLimits, extra: &str) -> (Arc<Bus>, Arc<AgentManager>, Arc<WasmAdapter>) {
+    echo_agent_with(name, limits, extra, SupervisorConfig::default()).await
+}
+
+async fn echo_agent_with(
+    name: &str,
+    limits: WasmLimits,
+    extra: &str,
+    config: SupervisorConfig,
+) -> (Arc<Bus>, Arc<AgentManager>, Arc<WasmAdapter>) {
+    let dir = std::env::temp_dir().join(format!("openi-wasm-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    guest_component(&dir);
+    let mut manifest = AgentManifest::from_yaml(&format!(
+        "kind: Agent\nname: {}\nruntime: wasm\nsource: echo.wasm\nenv:\n  GREETING: hi\n{}",
+        name, extra
+    ))
+    .unwrap();
+    manifest.path = Some(dir.join("AgentManifest.yaml"));
+
+    let bus = Arc::new(Bus::new());
+    let adapter = Arc::new(WasmAdapter::new(bus.clone()).unwrap().with_limits(limits));
+    let manager = AgentManager::with_config(bus.clone(), config);
+    manager.add_adapter(adapter.clone());
+    manager.register(manifest).await.unwrap();
+    (bus, manager, adapter)
+}
+
+fn input(payload: Value) -> Envelope<Value> {
+    Envelope::new("agent://local/test", "topic://echo/in", ContentType::mime("application", "json"), payload)
+        .ensure_trace()
+}
+
+async fn next_event(lifecycle: &mut openi_core_fabric::Subscription, to: AgentState) -> LifecycleEvent {
+    loop {
+        let env = timeout(Duration::from_secs(30), lifecycle.rx.recv()).await.unwrap().unwrap();
+        let event: LifecycleEvent = serde_json::from_value(env.payload).unwrap();
+        if event.to == to {
+            return event;
+        }
+    }
+}
+
+#[tokio::test]
+async fn echo_round_trip() {
+    let (bus, manager, adapter) = echo_agent("echo", WasmLimits::default(), "").await;
+    let mut out = bus.subscribe("topic://echo/out");
+    manager.start("echo").await.unwrap();
+    assert_eq!(manager.status("echo").unwrap().state, AgentState::Running);
+
+    for n in 1..=2u32 {
+        let sent = input(json!({ "n": n }));
+        bus.publish("topic://echo/in", sent.clone()).await.unwrap();
+        let echoed = timeout(Duration::from_secs(10), out.rx.recv()).await.unwrap().unwrap();
+        assert_eq!(echoed.payload, json!({ "n": n }));
+        assert_eq!(echoed.src, "agent://local/echo");
+        assert_eq!(echoed.headers.get(header::CAUSATION_ID), Some(&sent.id));
+        assert_eq!(adapter.state("echo", "count"), Some(n.to_le_bytes().to_vec()));
+    }
+    let last_ms = u64::from_le_bytes(adapter.state("echo", "last-ms").unwrap().try_into().unwrap());
+    assert!(last_ms > 0);
+
+    manager.stop("echo").await.unwrap();
+    assert_eq!(manager.status("echo").unwrap().state, AgentState::Stopped);
+
+    // State outlives the instance.
+    manager.start("echo").await.unwrap();
+    bus.publish("topic://echo/in", input(json!(3))).await.unwrap();
+    timeout(Duration::from_secs(10), out.rx.recv()).await.unwrap().unwrap();
+    assert_eq!(adapter.state("echo", "count"), Some(3u32.to_le_bytes().to_vec()));
+    manager.stop("echo").await.unwrap();
+}
+
+#[tokio::test]
+async fn running_out_of_fuel_fails_the_agent() {
+    let limits = WasmLimits { fuel: 1_000_000, ..WasmLimits::default() };
+    let (bus, manager, _) = echo_agent("spinner", limits, "").await;
+    let mut lifecycle = bus.subscribe(LIFECYCLE_TOPIC);
+    manager.start("spinner").await.unwrap();
+    bus.publish("topic://echo/in", input(json!("spin"))).await.unwrap();
+
+    let failed = next_event(&mut lifecycle, AgentState::Failed).await;
+    assert_eq!(failed.agent, "spinner");
+    assert!(failed.reason.unwrap().contains("fuel"));
+    assert!(failed.breach.is_none());
+}
+
+#[tokio::test]
+async fn spending_the_cpu_budget_is_a_breach_and_restarts() {
+    let (bus, manager, _) = echo_agent("busy", WasmLimits::default(), "resources:\n  limits:\n    cpu: 1m\n").await;
+    let mut lifecycle = bus.subscribe(LIFECYCLE_TOPIC);
+    manager.start("busy").await.unwrap();
+    bus.publish("topic://echo/in", input(json!("spin"))).await.unwrap();
+
+    let failed = next_event(&mut lifecycle, AgentState::Failed).await;
+    assert_eq!(failed.breach.unwrap().resource, "cpu");
+    next_event(&mut lifecycle, AgentState::Running).await;
+    assert_eq!(manager.status("busy").unwrap().starts, 2);
+    manager.stop("busy").await.unwrap();
+}
+
+#[tokio::test]
+async fn growing_past_the_memory_limit_is_a_breach_and_restarts() {
+    let (bus, manager, _) = echo_agent("grower", WasmLimits::default(), "resources:\n  limits:\n    memory: 1Mi\n").await;
+    let mut lifecycle = bus.subscribe(LIFECYCLE_TOPIC);
+    manager.start("grower").await.unwrap();
+    bus.publish("topic://echo/in", input(json!("grow"))).await.unwrap();
+
+    let failed = next_event(&mut lifecycle, AgentState::Failed).await;
+    assert_eq!(failed.agent, "grower");
+    let breach = failed.breach.unwrap();
+    assert_eq!((breach.resource.as_str(), breach.limit.as_str()), ("memory", "1048576"));
+    next_event(&mut lifecycle, AgentState::Running).await;
+    manager.stop("grower").await.unwrap();
+}
+
+#[tokio::test]
+async fn state_past_the_limit_is_a_breach() {
+    let limits = WasmLimits { state: 16, ..WasmLimits::default() };
+    let (bus, manager, adapter) = echo_agent("hoarder", limits, "").await;
+    let mut lifecycle = bus.subscribe(LIFECYCLE_TOPIC);
+    manager.start("hoarder").await.unwrap();
+    bus.publish("topic://echo/in", input(json!(1))).await.unwrap();
+
+    // `count` fits in 16 bytes; `last-ms` on top of it does not.
+    let failed = next_event(&mut lifecycle, AgentState::Failed).await;
+    let breach = failed.breach.unwrap();
+    assert_eq!((breach.resource.as_str(), breach.limit.as_str()), ("memory", "16"));
+    assert_eq!(breach.detail.as_deref(), Some("state would grow to 24 bytes"));
+    assert_eq!(adapter.state("hoarder", "count"), Some(1u32.to_le_bytes().to_vec()));
+    assert_eq!(adapter.state("hoarder", "last-ms"), None);
+    manager.stop("hoarder").await.unwrap();
+}
+
+#[tokio::test]
+async fn replicas_share_subscriptions_and_scale_independently() {
+    let (bus, manager, _) = echo_agent("pool", WasmLimits::default(), "replicas: 2\n").await;
+    let mut out = bus.subscribe("topic://echo/out");
+    manager.start("pool").await.unwrap();
+    let status = manager.status("pool").unwrap();
+    assert_eq!((status.state, status.replicas.len()), (AgentState::Running, 2));
+
+    // Each input is handled by one replica, not both.
+    for n in 0..4 {
+        bus.publish("topic://echo/in", input(json!(n))).await.unwrap();
+        timeout(Duration::from_secs(10), out.rx.recv()).await.unwrap().unwrap();
+    }
+    assert!(timeout(Duration::from_millis(200), out.rx.recv()).await.is_err());
+
+    manager.scale("pool", 3).await.unwrap();
+    let status = manager.status("pool").unwrap();
+    assert!(status.replicas.iter().all(|r| r.state == AgentState::Running && r.starts == 1));
+    assert_eq!(status.replicas.len(), 3);
+
+    manager.scale("pool", 1).await.unwrap();
+    let status = manager.status("pool").unwrap();
+    assert_eq!(status.replicas.iter().map(|r| (r.replica, r.state)).collect::<Vec<_>>(), [(0, AgentState::Running)]);
+    bus.publish("topic://echo/in", input(json!("after"))).await.unwrap();
+    let echoed = timeout(Duration::from_secs(10), out.rx.recv()).await.unwrap().unwrap();
+    assert_eq!(echoed.payload, json!("after"));
+    manager.stop("pool").await.unwrap();
+}
+
+#[tokio::test]
+async fn a_replica_that_keeps_failing_is_parked_in_crash_loop() {
+    let limits = WasmLimits { fuel: 1_000_000, ..WasmLimits::default() };
+    let config = SupervisorConfig {
+        backoff: Duration::from_millis(10),
+        crash_loop_failures: 3,
+        ..SupervisorConfig::default()
+    };
+    let (bus, manager, _) = echo_agent_with("crasher", limits, "restartPolicy: OnFailure\n", config).await;
+    let mut lifecycle = bus.subscribe(LIFECYCLE_TOPIC);
+    manager.start("crasher").await.unwrap();
+    for _ in 0..2 {
+        bus.publish("topic://echo/in", input(json!("spin"))).await.unwrap();
+        next_event(&mut lifecycle, AgentState::Failed).await;
+        next_event(&mut lifecycle, AgentState::Running).await;
+    }
+    bus.publish("topic://echo/in", input(json!("spin"))).await.unwrap();
+    let parked = next_event(&mut lifecycle, AgentState::CrashLoop).await;
+    assert!(parked.reason.unwrap().contains("3 times"));
+    assert_eq!(manager.status("crasher").unwrap().starts, 3);
+
+    // Starting it by hand gets it going again.
+    manager.start("crasher").await.unwrap();
+    assert_eq!(manager.status("crasher").unwrap().state, AgentState::Running);
+    manager.stop("crasher").await.unwrap();
+}
+
+#[tokio::test]
+async fn never_restart_leaves_a_failed_replica_down() {
+    let limits = WasmLimits { fuel: 1_000_000, ..WasmLimits::default() };
+    let config = SupervisorConfig { backoff: Duration::from_millis(10), ..SupervisorConfig::default() };
+    let (bus, manager, _) = echo_agent_with("once", limits, "restartPolicy: Never\n", config).await;
+    let mut lifecycle = bus.subscribe(LIFECYCLE_TOPIC);
+    manager.start("once").await.unwrap();
+    bus.publish("topic://echo/in", input(json!("spin"))).await.unwrap();
+    next_event(&mut lifecycle, AgentState::Failed).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let status = manager.status("once").unwrap();
+    assert_eq!((status.state, status.starts), (AgentState::Failed, 1));
+}
+
+#[tokio::test]
+async fn health_is_probed_on_the_declared_interval() {
+    let extra = "observability:\n  health_check_interval: 50ms\n  metrics_topic: topic://echo/metrics\n";
+    let (bus, manager, _) = echo_agent("probed", WasmLimits::default(), extra).await;
+    let mut health = bus.subscribe(HEALTH_TOPIC);
+    let mut metrics = bus.subscribe("topic://echo/metrics");
+    manager.start("probed").await.unwrap();
+
+    let env = timeout(Duration::from_secs(10), health.rx.recv()).await.unwrap().unwrap();
+    let event: HealthEvent = serde_json::from_value(env.payload).unwrap();
+    assert_eq!((event.agent.as_str(), event.replica), ("probed", 0));
+    assert!(event.health.live && event.health.ready);
+    timeout(Duration::from_secs(10), metrics.rx.recv()).await.unwrap().unwrap();
+    let replica = &manager.status("probed").unwrap().replicas[0];
+    assert!(replica.health.as_ref().is_some_and(|h| h.live && h.ready));
+    manager.stop("probed").await.unwrap();
+}
+
+#[tokio::test]
+async fn a_replica_that_stops_answering_probes_is_restarted() {
+    // Enough fuel that a spin outlasts several probes.
+    let limits = WasmLimits { fuel: u64::MAX / 2, ..WasmLimits::default() };
+    let config = SupervisorConfig { backoff: Duration::from_millis(10), ..SupervisorConfig::default() };
+    let extra = "observability:\n  health_check_interval: 50ms\n";
+    let (bus, manager, _) = echo_agent_with("stuck", limits, extra, config).await;
+    let mut lifecycle = bus.subscribe(LIFECYCLE_TOPIC);
+    manager.start("stuck").await.unwrap();
+    bus.publish("topic://echo/in", input(json!("spin"))).await.unwrap();
+
+    let degraded = next_event(&mut lifecycle, AgentState::Degraded).await;
+    assert!(degraded.reason.unwrap().starts_with("not ready"));
+    let failed = next_event(&mut lifecycle, AgentState::Failed).await;
+    assert!(failed.reason.unwrap().contains("health checks"));
+    next_event(&mut lifecycle, AgentState::Running).await;
+    assert_eq!(manager.status("stuck").unwrap().starts, 2);
+    manager.stop("stuck").await.unwrap();
+}
+
+#[tokio::test]
+async fn wasm_agents_sign_their_publishes() {
+    let tenant = Keypair::from_seed(&[7; 32]);
+    let node = Keypair::generate();
+    let day = Duration::from_secs(24 * 60 * 60);
+    let cert = NodeCertificate::issue(&Signer::new(tenant.clone()), "acme", "node-1", node.public_key_base64(), day)
+        .unwrap();
+    let verifier = Arc::new(IdentityVerifier::new());
+    verifier.trust_tenant("acme", &tenant.public_key_base64()).unwrap();
+    verifier.require("topic://echo/*");
+    let identity = Arc::new(NodeIdentity::new(cert, node, verifier.clone()).unwrap());
+
+    let dir = std::env::temp_dir().join(format!("openi-wasm-signed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    guest_component(&dir);
+    let mut manifest =
+        AgentManifest::from_yaml("kind: Agent\nname: signed\nruntime: wasm\nsource: echo.wasm\n").unwrap();
+    manifest.path = Some(dir.join("AgentManifest.yaml"));
+    let digest = manifest.digest.clone().unwrap();
+    let bus = Arc::new(Bus::new());
+    let manager = AgentManager::new(bus.clone());
+    manager.set_identity(identity.clone());
+    manager.add_adapter(Arc::new(WasmAdapter::new(bus.clone()).unwrap().with_identity(identity)));
+    manager.register(manifest).await.unwrap();
+    let mut out = bus.subscribe("topic://echo/out");
+    manager.start("signed").await.unwrap();
+
+    let mut sent = input(json!(1));
+    sent.src = "agent://fabric/test".into();
+    bus.publish("topic://echo/in", sent).await.unwrap();
+
+    let echoed = timeout(Duration::from_secs(10), out.rx.recv()).await.unwrap().unwrap();
+    assert_eq!(echoed.src, "agent://acme/node-1/signed");
+    let credential = verifier.verify(&echoed).unwrap();
+    assert_eq!(credential.agent, echoed.src);
+    assert_eq!(credential.manifest, digest);
+    manager.stop("signed").await.unwrap();
+}
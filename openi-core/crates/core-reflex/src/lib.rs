@@ -7,7 +7,6 @@
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::error;
 
 pub mod monitor;
 pub mod supervisor;
@@ -15,9 +14,9 @@ pub mod supervisor;
 pub use monitor::*;
 pub use supervisor::*;
 
-/// ---------------------------------------------------------------------------
-/// Core Data Types
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Core Data Types
+// ---------------------------------------------------------------------------
 
 /// Basic event structure observed by Reflex monitors.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -54,9 +53,9 @@ impl Default for ReflexSubjects {
     }
 }
 
-/// ---------------------------------------------------------------------------
-/// Bus Abstractions
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Bus Abstractions
+// ---------------------------------------------------------------------------
 
 /// Trait representing a publish/subscribe transport layer.
 ///
@@ -74,9 +73,9 @@ pub trait BusSubscription: Send + Sync {
     async fn next(&mut self) -> Option<Envelope>;
 }
 
-/// ---------------------------------------------------------------------------
-/// Reflex Evaluation Model
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Reflex Evaluation Model
+// ---------------------------------------------------------------------------
 
 /// Reflex evaluation outcomes. A Reflex can:
 /// - Continue: normal operation
@@ -8,6 +8,19 @@ use tokio::time::{interval, Duration, Instant};
 /// Trait alias so we can hold a heterogenous set of boxed reflexes.
 type BoxedReflex = Box<dyn Reflex>;
 
+/// Scrubs free text (reasons, errors) before it is logged or published.
+pub type Redact = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+#[cfg(feature = "openi-core-fabric")]
+fn default_redact() -> Redact {
+    Arc::new(|s| openi_core_fabric::redact::default_redactor().redact_str(s))
+}
+
+#[cfg(not(feature = "openi-core-fabric"))]
+fn default_redact() -> Redact {
+    Arc::new(|s| s.to_string())
+}
+
 /// Minimal ReflexSubjects for simulation or real deployment.
 #[derive(Clone, Debug)]
 pub struct ReflexSubjects {
@@ -30,6 +43,7 @@ pub struct ReflexSupervisor<BUS> {
     subjects: ReflexSubjects,
     reflexes: Vec<BoxedReflex>,
     tick_interval: Duration,
+    redact: Redact,
 }
 
 impl<BUS> ReflexSupervisor<BUS>
@@ -42,6 +56,7 @@ where
             subjects,
             reflexes: Vec::new(),
             tick_interval: Duration::from_millis(500),
+            redact: default_redact(),
         }
     }
 
@@ -55,17 +70,26 @@ where
         self
     }
 
+    /// Override how reasons and errors are scrubbed. With the
+    /// `openi-core-fabric` feature the default is the fabric's PHI redactor.
+    pub fn with_redactor(mut self, redact: Redact) -> Self {
+        self.redact = redact;
+        self
+    }
+
     /// Start the Reflex event + tick loops.
     pub fn spawn(self) {
         let bus = self.bus.clone();
         let subjects = self.subjects.clone();
         let reflexes = Arc::new(Mutex::new(self.reflexes));
         let tick_every = self.tick_interval;
+        let redact = self.redact;
 
         // Event loop
         tokio::spawn({
             let bus = bus.clone();
             let reflexes = reflexes.clone();
+            let redact = redact.clone();
             async move {
                 println!("🧠 ReflexSupervisor: subscribing to {}", subjects.all_events_subject);
                 let mut sub = match bus.subscribe(&subjects.all_events_subject).await {
@@ -82,15 +106,17 @@ where
                         match r.on_event(&evt).await {
                             Ok(ReflexAction::Continue) => {}
                             Ok(ReflexAction::Alert(reason)) => {
+                                let reason = redact(&reason);
                                 println!("⚠️  ALERT from {} → {}", r.name(), reason);
                                 let _ = publish_alert(&*bus, &subjects.control_subject, r.name(), &reason, &evt).await;
                             }
                             Ok(ReflexAction::Halt(reason)) => {
+                                let reason = redact(&reason);
                                 println!("🛑 HALT from {} → {}", r.name(), reason);
                                 let _ = publish_halt(&*bus, &subjects.control_subject, r.name(), &reason, &evt).await;
                             }
                             Err(err) => {
-                                eprintln!("❗ Reflex error in {} → {}", r.name(), err);
+                                eprintln!("❗ Reflex error in {} → {}", r.name(), redact(&err.to_string()));
                             }
                         }
                     }
@@ -107,7 +133,7 @@ where
                 let mut g = reflexes.lock().await;
                 for r in g.iter_mut() {
                     if let Err(e) = r.on_tick(now).await {
-                        eprintln!("⏱️  Tick error in {} → {}", r.name(), e);
+                        eprintln!("⏱️  Tick error in {} → {}", r.name(), redact(&e.to_string()));
                     }
                 }
             }
@@ -115,9 +141,9 @@ where
     }
 }
 
-/// ---------------------------------------------------------------------------
-/// Helper functions for reflex control envelopes
-/// ---------------------------------------------------------------------------
+// ---------------------------------------------------------------------------
+// Helper functions for reflex control envelopes
+// ---------------------------------------------------------------------------
 
 async fn publish_alert<B: FabricBus>(
     bus: &B,
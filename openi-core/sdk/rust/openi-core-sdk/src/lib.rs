@@ -1,26 +1,254 @@
 use anyhow::Result;
-use openi_core_fabric::Envelope;
+use openi_core_fabric::redact::{default_redactor, redacted};
+use openi_core_fabric::{blob, seal, typed};
+use openi_core_fabric::{BlobStore, Bus, ClaimCheckPolicy, Dedup, DedupConfig, Envelope, SealKey, SealPolicy, SchemaRegistry, SchemaViolationReport, GLOBAL_BUS};
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use tracing::Instrument;
+
+tokio::task_local! {
+    /// Envelope (headers only) whose handler is currently running. Envelopes
+    /// published from inside a handler become its children.
+    static INBOUND: Envelope<Value>;
+}
 
 pub struct Agent {
     pub name: String,
     pub version: String,
+    bus: Arc<Bus>,
+    claim_check: Option<(Arc<dyn BlobStore>, ClaimCheckPolicy)>,
+    schemas: Option<Arc<SchemaRegistry>>,
+    errors_topic: Option<String>,
+    dedup: Option<DedupConfig>,
+    group: Option<String>,
+    seal: Option<SealPolicy>,
+    open_key: Option<Arc<SealKey>>,
 }
 
 impl Agent {
     pub fn new(name: impl Into<String>, version: impl Into<String>) -> Self {
-        Self { name: name.into(), version: version.into() }
+        Self::with_bus(name, version, GLOBAL_BUS.clone())
+    }
+
+    pub fn with_bus(name: impl Into<String>, version: impl Into<String>, bus: Arc<Bus>) -> Self {
+        Self { name: name.into(), version: version.into(), bus, claim_check: None, schemas: None, errors_topic: None, dedup: None, group: None, seal: None, open_key: None }
+    }
+
+    /// Send payloads over the policy threshold as `blob://` references into
+    /// `store`, and resolve incoming references from it.
+    pub fn with_claim_check(mut self, store: Arc<dyn BlobStore>, policy: ClaimCheckPolicy) -> Self {
+        self.claim_check = Some((store, policy));
+        self
+    }
+
+    /// Validate incoming payloads against `schemas` before handling them.
+    pub fn with_schemas(mut self, schemas: Arc<SchemaRegistry>) -> Self {
+        self.schemas = Some(schemas);
+        self
+    }
+
+    /// The manifest's `errors` topic. Schema violations in what this agent
+    /// publishes (caught by the bus) or receives are reported there.
+    pub fn with_errors_topic(mut self, topic: impl Into<String>) -> Self {
+        let topic = topic.into();
+        self.bus.set_errors_topic(self.src(), topic.clone());
+        self.errors_topic = Some(topic);
+        self
+    }
+
+    /// Skip envelopes each handler has already handled within the window.
+    pub fn with_dedup(mut self, config: DedupConfig) -> Self {
+        self.dedup = Some(config);
+        self
+    }
+
+    /// Share dedup state with every agent in consumer group `name` (e.g.
+    /// replicas of one agent), so the group handles each envelope once.
+    pub fn with_consumer_group(mut self, name: impl Into<String>) -> Self {
+        self.group = Some(name.into());
+        self
+    }
+
+    /// Seal every published payload for the policy's recipients.
+    pub fn with_encryption(mut self, policy: SealPolicy) -> Self {
+        self.seal = Some(policy);
+        self
+    }
+
+    /// Open payloads sealed for this agent (by its `agent://local/<name>` id).
+    pub fn with_seal_key(mut self, key: SealKey) -> Self {
+        self.open_key = Some(Arc::new(key));
+        self
+    }
+
+    fn src(&self) -> String {
+        format!("agent://local/{}", self.name)
     }
 
     pub async fn publish<T: Serialize + DeserializeOwned>(&self, dest: &str, ctype: &str, payload: T) -> Result<()> {
-        let env = Envelope::new(format!("agent://local/{}", self.name), dest.to_string(), ctype.to_string(), payload);
-        // TODO: send into local kernel's mailbox (stub)
-        println!("[publish] {}", serde_json::to_string(&env)?);
+        let env = Envelope::new(self.src(), dest.to_string(), ctype.parse()?, serde_json::to_value(payload)?);
+        self.send(dest, env).await
+    }
+
+    /// Publish with the content type registered for `T` on the bus
+    /// (see `Bus::register_type`).
+    pub async fn publish_typed<T: Serialize + 'static>(&self, dest: &str, payload: T) -> Result<()> {
+        let env = self.bus.typed_envelope(&self.src(), dest, payload)?;
+        self.send(dest, env).await
+    }
+
+    async fn send(&self, dest: &str, mut env: Envelope<Value>) -> Result<()> {
+        env = match INBOUND.try_with(|p| p.clone()) {
+            Ok(parent) => env.child_of(&parent),
+            Err(_) => env.ensure_trace(),
+        };
+        if let Some(policy) = &self.seal {
+            env = policy.seal(&env)?;
+        }
+        if let Some((store, policy)) = &self.claim_check {
+            if let Some(checked) = policy.check_in(&env, store.as_ref())? {
+                env = checked;
+            }
+        }
+        tracing::debug!("publish {}", redacted(&env));
+        self.bus.publish(dest, env).await?;
         Ok(())
     }
 
-    pub async fn subscribe<T: DeserializeOwned>(&self, _topic: &str, _handler: fn(Envelope<T>) -> Result<()>) -> Result<()> {
-        // TODO: register handler with fabric bus (stub)
+    /// Run `handler` for every envelope on `topic`. Envelopes that expire
+    /// while queued are dead-lettered instead of handled, and claim-checked
+    /// payloads are fetched from the blob store first. Sealed payloads are
+    /// opened with the agent's seal key. Payloads failing
+    /// their schema are reported to the errors topic and not handled. If `T`
+    /// has a registered content type, older versions are upcast to it.
+    /// Errors are logged through the default redactor. With `with_dedup`,
+    /// redelivered envelopes are dropped before they reach the handler.
+    pub async fn subscribe<T, F, Fut>(&self, topic: &str, handler: F) -> Result<()>
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(Envelope<T>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        // A consumer group always dedups, with the default window unless set.
+        let config = self.dedup.or(self.group.as_ref().map(|_| DedupConfig::default()));
+        let mut sub = match (config, &self.group) {
+            (Some(config), Some(group)) => {
+                let dedup = self.bus.dedup_group(&format!("{}|{}", group, topic), config);
+                self.bus.subscribe_dedup(topic, dedup)
+            }
+            (Some(config), None) => self.bus.subscribe_dedup(topic, Arc::new(Dedup::new(config))),
+            (None, _) => self.bus.subscribe(topic),
+        };
+        let bus = self.bus.clone();
+        let store = self.claim_check.as_ref().map(|(s, _)| s.clone());
+        let schemas = self.schemas.clone();
+        let errors_topic = self.errors_topic.clone();
+        let src = self.src();
+        let expected = self.bus.ctype_of::<T>();
+        let upcaster = self.bus.upcaster();
+        let redact = default_redactor();
+        let open_key = self.open_key.clone();
+        tokio::spawn(async move {
+            while let Some(mut env) = sub.rx.recv().await {
+                if env.is_expired() {
+                    let dest = env.dest.clone();
+                    bus.dead_letter_expired(&dest, env).await;
+                    continue;
+                }
+                if blob::is_claim_check(&env) {
+                    let Some(store) = &store else {
+                        tracing::warn!("{}: {} is claim-checked but no blob store is configured", sub.pattern, env.id);
+                        continue;
+                    };
+                    env = match blob::check_out(env, store.as_ref()) {
+                        Ok(env) => env,
+                        Err(e) => {
+                            tracing::warn!("{}: cannot resolve claim check: {}", sub.pattern, redact.redact_str(&e.to_string()));
+                            continue;
+                        }
+                    };
+                }
+                if seal::is_sealed(&env) {
+                    let Some(key) = &open_key else {
+                        tracing::warn!("{}: {} is sealed but no seal key is configured", sub.pattern, env.id);
+                        continue;
+                    };
+                    env = match seal::open(env, &src, key) {
+                        Ok(env) => env,
+                        Err(e) => {
+                            tracing::warn!("{}: cannot open sealed payload: {}", sub.pattern, e);
+                            continue;
+                        }
+                    };
+                }
+                if let Some(Err(violation)) = schemas.as_ref().map(|s| s.validate(&env.ctype, &env.payload)) {
+                    tracing::warn!("{}: {} rejected: {}", sub.pattern, env.id, violation);
+                    if let Some(errors) = &errors_topic {
+                        let report = SchemaViolationReport::envelope(&src, errors, &sub.pattern, &env, violation);
+                        if let Err(e) = bus.publish(errors, report).await {
+                            tracing::warn!("{}: cannot report schema violation: {}", sub.pattern, redact.redact_str(&e.to_string()));
+                        }
+                    }
+                    continue;
+                }
+                let parent = env.with_payload(Value::Null);
+                let typed = match typed::decode::<T>(env, expected.as_ref(), upcaster.as_deref()) {
+                    Ok(typed) => typed,
+                    Err(e) => {
+                        tracing::warn!("{}: {}", sub.pattern, redact.redact_str(&e.to_string()));
+                        continue;
+                    }
+                };
+                let span = parent.span();
+                if let Err(e) = INBOUND.scope(parent, handler(typed)).instrument(span).await {
+                    tracing::warn!("{}: handler failed: {}", sub.pattern, redact.redact_str(&e.to_string()));
+                }
+            }
+        });
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openi_core_fabric::envelope::header;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn envelopes_expiring_in_the_queue_are_dead_lettered() {
+        let bus = Arc::new(Bus::new());
+        bus.set_expiry_topic(Some("topic://expired".into()));
+        let mut dead = bus.subscribe("topic://expired");
+        let handled = Arc::new(AtomicUsize::new(0));
+        let agent = Agent::with_bus("worker", "1", bus.clone());
+        let count = handled.clone();
+        agent
+            .subscribe("topic://work", move |_: Envelope<Value>| {
+                let count = count.clone();
+                async move {
+                    count.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    Ok(())
+                }
+            })
+            .await
+            .unwrap();
+
+        let ctype = "application/json".parse().unwrap();
+        let slow = Envelope::new("agent://local/a", "topic://work", ctype, serde_json::json!(1));
+        let ctype = "application/json".parse().unwrap();
+        let brief = Envelope::new("agent://local/a", "topic://work", ctype, serde_json::json!(2))
+            .with_ttl(Duration::from_millis(50));
+        bus.publish("topic://work", slow).await.unwrap();
+        bus.publish("topic://work", brief.clone()).await.unwrap();
+
+        let lettered = tokio::time::timeout(Duration::from_secs(5), dead.rx.recv()).await.unwrap().unwrap();
+        assert_eq!(lettered.id, brief.id);
+        assert_eq!(lettered.headers.get(header::EXPIRED_FROM).map(String::as_str), Some("topic://work"));
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+    }
+}